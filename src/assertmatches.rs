@@ -0,0 +1,62 @@
+use eyre::{eyre, Context, Result};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Compares files restored in memory with the regular files under reference_dir
+///
+/// Prints one tab separated line per difference, sorted by path:
+/// `differs`, `only-in-backup` or `only-in-reference`, then the relative path.
+/// Errors if there is any
+pub fn assert_matches(restored: &HashMap<PathBuf, Vec<u8>>, reference_dir: &Path) -> Result<()> {
+    let mut reference = BTreeSet::new();
+    collect_files(reference_dir, Path::new(""), &mut reference)?;
+
+    let mut paths: BTreeSet<&PathBuf> = restored.keys().collect();
+    paths.extend(reference.iter());
+
+    let mut differences = 0;
+    for path in paths {
+        let kind = match (restored.get(path), reference.contains(path)) {
+            (Some(content), true) => {
+                let full = reference_dir.join(path);
+                let expected = fs::read(&full).wrap_err_with(|| format!("read {:?}", full))?;
+                if *content == expected {
+                    continue;
+                }
+                "differs"
+            }
+            (Some(_), false) => "only-in-backup",
+            (None, _) => "only-in-reference",
+        };
+        println!("{}\t{}", kind, path.display());
+        differences += 1;
+    }
+
+    if differences > 0 {
+        return Err(eyre!(
+            "{} differences between the backup and {:?}",
+            differences,
+            reference_dir
+        ));
+    }
+    Ok(())
+}
+
+/// Relative paths of the regular files under root.join(relative)
+fn collect_files(root: &Path, relative: &Path, files: &mut BTreeSet<PathBuf>) -> Result<()> {
+    let dir = root.join(relative);
+    for entry in fs::read_dir(&dir).wrap_err_with(|| format!("read_dir({:?})", dir))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if file_type.is_file() {
+            files.insert(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use crate::crypto::ReadSeek;
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+/// The files of a backup directory that isn't a directory on disk, a tar or
+/// zip of one (--backup-archive) or object storage (--backend)
+///
+/// Mounted at a path, its files are listed and opened as if they were in a
+/// directory of that path, e.g. "backup.tar/duplicati-b1.dblock.zip"
+pub trait Backend: Send + Sync {
+    /// File names, a backup directory is flat
+    fn names(&self) -> Vec<String>;
+
+    /// None if there is no file called name
+    fn stat(&self, name: &str) -> Option<Stat>;
+
+    fn open(&self, name: &str) -> io::Result<Box<dyn ReadSeek>>;
+}
+
+#[derive(Clone, Copy)]
+pub struct Stat {
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+static MOUNTED: RwLock<Vec<(PathBuf, Arc<dyn Backend>)>> = RwLock::new(Vec::new());
+
+/// Serves the files under root from backend from now on, in place of what was
+/// mounted there before
+pub fn mount(root: &Path, backend: Arc<dyn Backend>) {
+    let mut mounted = MOUNTED.write().unwrap();
+    mounted.retain(|(mounted_root, _)| mounted_root != root);
+    mounted.push((root.to_path_buf(), backend));
+}
+
+fn mounted(root: &Path) -> Option<Arc<dyn Backend>> {
+    MOUNTED
+        .read()
+        .unwrap()
+        .iter()
+        .find(|(mounted_root, _)| mounted_root == root)
+        .map(|(_, backend)| backend.clone())
+}
+
+/// The backend path is in and its name there, None for a file on disk
+fn mounted_file(path: &Path) -> Option<(Arc<dyn Backend>, &str)> {
+    let backend = mounted(path.parent()?)?;
+    Some((backend, path.file_name()?.to_str()?))
+}
+
+/// True for files on disk, they can be memory mapped
+pub fn is_on_disk(path: &Path) -> bool {
+    mounted_file(path).is_none()
+}
+
+/// A backup file, from a backend or on disk
+pub fn open(path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+    match mounted_file(path) {
+        Some((backend, name)) => backend.open(name),
+        None => Ok(Box::new(File::open(path)?)),
+    }
+}
+
+/// Size and modification time of a backup file, from a backend or on disk
+pub fn stat(path: &Path) -> io::Result<Stat> {
+    match mounted_file(path) {
+        Some((backend, name)) => backend
+            .stat(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file in backend")),
+        None => {
+            let metadata = fs::metadata(path)?;
+            Ok(Stat {
+                len: metadata.len(),
+                modified: metadata.modified()?,
+            })
+        }
+    }
+}
+
+/// Paths of the files in dir, from its backend if one is mounted there
+pub fn read_dir(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if let Some(backend) = mounted(dir) {
+        return Ok(backend.names().iter().map(|name| dir.join(name)).collect());
+    }
+    Ok(fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|f| f.path())
+        .collect())
+}
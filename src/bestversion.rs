@@ -0,0 +1,90 @@
+use crate::{
+    completeness::missing_blocks, database::DFileDatabase, dfileentry::FileEntry, FileEntries,
+};
+use eyre::Result;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+pub struct BestEffortMerge {
+    pub entries: FileEntries,
+    /// Files taken from an older version because the newest one misses blocks
+    pub from_older: usize,
+    /// Paths where no version has all blocks, these are left out
+    pub unrestorable: Vec<String>,
+}
+
+enum Choice<'a> {
+    Newest,
+    Older(&'a FileEntry),
+    Unrestorable,
+}
+
+/// Picks, per path of the newest version, the most recent version whose blocks are all present
+///
+/// versions must be sorted newest first. Paths deleted before the newest version stay deleted
+pub fn merge_best_effort_latest(
+    versions: Vec<FileEntries>,
+    db: &DFileDatabase,
+) -> Result<BestEffortMerge> {
+    let mut versions = versions.into_iter();
+    let Some(newest) = versions.next() else {
+        return Ok(BestEffortMerge {
+            entries: FileEntries {
+                entries: Vec::new(),
+            },
+            from_older: 0,
+            unrestorable: Vec::new(),
+        });
+    };
+    let older: Vec<FileEntries> = versions.collect();
+
+    // path -> older entries, newest first
+    let mut older_by_path: HashMap<&str, Vec<&FileEntry>> = HashMap::new();
+    for version in &older {
+        for entry in &version.entries {
+            older_by_path
+                .entry(entry.path.as_str())
+                .or_default()
+                .push(entry);
+        }
+    }
+
+    let choices: Vec<Choice<'_>> = newest
+        .entries
+        .par_iter()
+        .map_init(Vec::new, |hashes_buf, entry| -> Result<Choice<'_>> {
+            if missing_blocks(entry, db, hashes_buf)?.is_empty() {
+                return Ok(Choice::Newest);
+            }
+            let candidates = older_by_path.get(entry.path.as_str());
+            for candidate in candidates.into_iter().flatten() {
+                if candidate.file_type.is_file()
+                    && missing_blocks(candidate, db, hashes_buf)?.is_empty()
+                {
+                    return Ok(Choice::Older(candidate));
+                }
+            }
+            Ok(Choice::Unrestorable)
+        })
+        .collect::<Result<_>>()?;
+
+    let mut entries = Vec::with_capacity(newest.entries.len());
+    let mut from_older = 0;
+    let mut unrestorable = Vec::new();
+    for (entry, choice) in newest.entries.iter().zip(choices) {
+        match choice {
+            Choice::Newest => entries.push(entry.clone()),
+            Choice::Older(older_entry) => {
+                from_older += 1;
+                entries.push(older_entry.clone());
+            }
+            Choice::Unrestorable => unrestorable.push(entry.path.clone()),
+        }
+    }
+
+    Ok(BestEffortMerge {
+        entries: FileEntries { entries },
+        from_older,
+        unrestorable,
+    })
+}
@@ -0,0 +1,121 @@
+use crate::sevenzarchive::SevenZArchive;
+use crate::ziparchive::{
+    check_not_encrypted, diagnose_unreadable_zip, MyCloneFileConfig, MyCloneFileReader,
+};
+use eyre::{eyre, Result};
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Zip compression method id of zstandard, used per entry by newer Duplicati versions
+const ZIP_METHOD_ZSTD: u16 = 93;
+
+/// A dblock volume whose entries are blocks named by their urlsafe base64 hash
+///
+/// Entry indexes are positions in file_names_ordered, BlockLocation::file_index
+/// means the same thing for every backend
+pub trait BlockArchive: Send + Sync {
+    fn get_file_index(&self, name: &str) -> Option<usize>;
+
+    fn contains_file_name(&self, name: &str) -> bool {
+        self.get_file_index(name).is_some()
+    }
+
+    /// Entry names in the order they are stored
+    fn file_names_ordered(&self) -> Box<dyn Iterator<Item = &str> + '_>;
+
+    /// Content of the entry called name, errors if there is none
+    fn by_name(&mut self, name: &str) -> Result<BlockEntry<'_>>;
+
+    /// Content of the entry called name, decompressed here rather than by the backend
+    ///
+    /// For entries by_name failed on with unsupported_compression
+    fn by_name_decompressed_here(&mut self, name: &str) -> Result<BlockEntry<'_>> {
+        Err(eyre!("no decompressor for entry {:?}", name))
+    }
+
+    /// Another handle to the same volume for one reading thread, it shares the
+    /// parsed index and opens its own file on first read
+    fn clone_archive(&self) -> Box<dyn BlockArchive>;
+}
+
+/// One entry of a BlockArchive being read
+pub struct BlockEntry<'a> {
+    /// Uncompressed size as recorded by the archive
+    pub size: u64,
+    pub reader: Box<dyn Read + 'a>,
+}
+
+impl BlockArchive for ZipArchive<MyCloneFileReader> {
+    fn get_file_index(&self, name: &str) -> Option<usize> {
+        ZipArchive::get_file_index(self, name)
+    }
+
+    fn contains_file_name(&self, name: &str) -> bool {
+        ZipArchive::contains_file_name(self, name)
+    }
+
+    fn file_names_ordered(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        Box::new(ZipArchive::file_names_ordered(self))
+    }
+
+    fn by_name(&mut self, name: &str) -> Result<BlockEntry<'_>> {
+        let file = ZipArchive::by_name(self, name)?;
+        Ok(BlockEntry {
+            size: file.size(),
+            reader: Box::new(file),
+        })
+    }
+
+    fn by_name_decompressed_here(&mut self, name: &str) -> Result<BlockEntry<'_>> {
+        let index = ZipArchive::get_file_index(self, name).ok_or(ZipError::FileNotFound)?;
+        let raw = self.by_index_raw(index)?;
+        // Without zip's zstd feature the method only has a deprecated Unsupported(93) variant
+        #[allow(deprecated)]
+        let method = raw.compression().to_u16();
+        let size = raw.size();
+        match method {
+            ZIP_METHOD_ZSTD => Ok(BlockEntry {
+                size,
+                reader: Box::new(zstd::Decoder::new(raw)?),
+            }),
+            method => Err(eyre!(
+                "entry {:?} uses zip compression method {}, not supported",
+                name,
+                method
+            )),
+        }
+    }
+
+    fn clone_archive(&self) -> Box<dyn BlockArchive> {
+        Box::new(self.clone())
+    }
+}
+
+/// True if err is the archive backend refusing an entry's compression method
+pub fn unsupported_compression(err: &eyre::Report) -> bool {
+    matches!(
+        err.downcast_ref::<ZipError>(),
+        Some(ZipError::UnsupportedArchive(_))
+    )
+}
+
+/// True for volumes written by Duplicati's 7z compression module, e.g. "x.dblock.7z"
+pub fn is_sevenz(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("7z"))
+}
+
+/// Opens the dblock at config.path and reads its index, as 7z or zip by extension
+pub fn open_block_archive(config: Arc<MyCloneFileConfig>) -> Result<Box<dyn BlockArchive>> {
+    let path = config.path.clone();
+    let mut reader = MyCloneFileReader::new(config)?;
+    check_not_encrypted(&mut reader, &path)?;
+    if is_sevenz(&path) {
+        return Ok(Box::new(SevenZArchive::new(reader)?));
+    }
+    let archive = ZipArchive::new(reader).map_err(|err| diagnose_unreadable_zip(&path, err))?;
+    Ok(Box::new(archive))
+}
@@ -0,0 +1,73 @@
+use crate::blockhash::BlockIdHash;
+use lru::LruCache;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Decompressed blocks kept in memory, least recently used ones go first
+///
+/// Deduplicated blocks are read by many files, a hit skips opening the dblock and
+/// inflating the block again. Only block contents count towards the budget
+pub struct BlockCache {
+    inner: Mutex<CacheInner>,
+    budget: usize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+struct CacheInner {
+    blocks: LruCache<BlockIdHash, Arc<Vec<u8>>>,
+    /// Bytes of block contents in blocks
+    used: usize,
+}
+
+impl BlockCache {
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            inner: Mutex::new(CacheInner {
+                blocks: LruCache::unbounded(),
+                used: 0,
+            }),
+            budget: budget_bytes,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get(&self, block_id: &BlockIdHash) -> Option<Arc<Vec<u8>>> {
+        let block = self.inner.lock().unwrap().blocks.get(block_id).cloned();
+        let counter = match block {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        block
+    }
+
+    /// Evicts older blocks to make room, blocks bigger than the budget aren't kept
+    pub fn insert(&self, block_id: &BlockIdHash, content: &[u8]) {
+        if content.len() > self.budget {
+            return;
+        }
+        let len = content.len();
+        let content = Arc::new(content.to_vec());
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(old) = inner.blocks.put(block_id.clone(), content) {
+            inner.used -= old.len();
+        }
+        inner.used += len;
+        while inner.used > self.budget {
+            let Some((_, evicted)) = inner.blocks.pop_lru() else {
+                break;
+            };
+            inner.used -= evicted.len();
+        }
+    }
+
+    /// (hits, misses) so far
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -3,13 +3,14 @@ use std::{cell::RefCell, fmt::Display};
 use base64::engine::general_purpose;
 use smallvec::SmallVec;
 
-use crate::hexdisplay::HexDisplayBytes;
+use crate::hashalgo::HashAlgo;
+use crate::hexdisplay::{hex2bytes, HexDisplayBytes};
 thread_local! {
     pub static BASE64_DECODE_BUF: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(64));
 
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub struct BlockIdHash {
     pub hash: SmallVec<[u8; 32]>,
 }
@@ -21,8 +22,10 @@ impl Display for BlockIdHash {
 }
 
 impl BlockIdHash {
+    /// None unless b is as long as a digest of a supported HashAlgo:
+    /// 32 (SHA256), 20 (SHA1) or 16 (MD5) bytes
     pub fn from_bytes(b: &[u8]) -> Option<BlockIdHash> {
-        if b.len() != 32 {
+        if !matches!(b.len(), 32 | 20 | 16) {
             return None;
         }
         Some(BlockIdHash {
@@ -30,10 +33,27 @@ impl BlockIdHash {
         })
     }
 
+    /// True if this is the hash of zero bytes with algo, the block every empty
+    /// file of a backup shares.
+    ///
+    /// Such blocks never need to be read from a dblock.
+    pub fn is_empty_block(&self, algo: HashAlgo) -> bool {
+        self.hash.as_slice() == &*algo.digest(b"")
+    }
+
+    /// Parses the hex form shown in errors ("Missing block abcdef..."), None unless
+    /// it's as long as from_bytes accepts
+    ///
+    /// Round trip to the name inside a dblock:
+    /// `BlockIdHash::from_hex(hex)?.as_base64_urlsafe(buf)`,
+    /// and back: `BlockIdHash::from_base64_urlsafe(name)?.to_string()`
+    pub fn from_hex(hex: &str) -> Option<BlockIdHash> {
+        Self::from_bytes(&hex2bytes(hex.trim())?)
+    }
+
     pub fn from_base64(block_id_str: &str) -> Option<BlockIdHash> {
         Self::from_base64_config(block_id_str, general_purpose::STANDARD)
     }
-    #[allow(unused)]
     pub fn from_base64_urlsafe(block_id_str: &str) -> Option<BlockIdHash> {
         Self::from_base64_config(block_id_str, general_purpose::URL_SAFE)
     }
@@ -54,7 +74,6 @@ impl BlockIdHash {
         })
     }
 
-    #[allow(unused)]
     pub fn as_base64<'a>(&self, buf: &'a mut [u8]) -> &'a str {
         self.as_base64_config(general_purpose::STANDARD, buf)
     }
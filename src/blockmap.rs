@@ -0,0 +1,89 @@
+use crate::{
+    blockhash::BlockIdHash, database::DFileDatabase, dfileentry::FileEntry, dfiletype::FileType,
+};
+use eyre::{eyre, Context, Result};
+use std::path::PathBuf;
+
+/// Where one byte range of a restored file comes from
+#[derive(Clone, Debug)]
+pub struct BlockRef {
+    /// Offset in the restored file
+    pub offset: u64,
+    pub len: u64,
+    pub hash: BlockIdHash,
+    /// dblock.zip holding the block
+    pub volume: PathBuf,
+    /// Which file inside the dblock.zip
+    pub file_index: u32,
+}
+
+/// Byte-range map of a file, in file order, without reading any content block
+///
+/// Blocklists are read to expand the block hashes, the same way
+/// restore_file_multiblock_main does before writing. Lengths follow from
+/// the declared size: every block is full except the last one
+pub fn block_map(entry: &FileEntry, db: &DFileDatabase) -> Result<Vec<BlockRef>> {
+    let FileType::File { size, .. } = &entry.file_type else {
+        return Err(eyre!("{:?} is not a file", entry.path));
+    };
+    let size = (*size).max(0) as u64;
+    let hashes = block_hashes(entry, db)?;
+
+    let block_size = db.block_size() as u64;
+    let mut map = Vec::with_capacity(hashes.len());
+    for (i, hash) in hashes.into_iter().enumerate() {
+        let offset = i as u64 * block_size;
+        if offset >= size {
+            return Err(eyre!(
+                "blocklists of {:?} have more blocks than size {} needs",
+                entry.path,
+                size
+            ));
+        }
+        let location = db
+            .get_block_id_location(&hash)
+            .ok_or_else(|| eyre!("Failed to find block {} for {:?}", hash, entry.path))?;
+        map.push(BlockRef {
+            offset,
+            len: block_size.min(size - offset),
+            hash,
+            volume: location.ziplocation.path.clone(),
+            file_index: location.file_index,
+        });
+    }
+    if (map.len() as u64) * block_size < size {
+        return Err(eyre!(
+            "blocklists of {:?} have {} blocks, too few for size {}",
+            entry.path,
+            map.len(),
+            size
+        ));
+    }
+    Ok(map)
+}
+
+/// Content block hashes of a file in order, blocklists expanded. Empty for empty files
+pub fn block_hashes(entry: &FileEntry, db: &DFileDatabase) -> Result<Vec<BlockIdHash>> {
+    let FileType::File { hash, size, .. } = &entry.file_type else {
+        return Err(eyre!("{:?} is not a file", entry.path));
+    };
+    if *size <= 0 || hash.is_empty_block(db.block_hash_algo()) {
+        return Ok(Vec::new());
+    }
+    if entry.block_lists.is_empty() {
+        return Ok(vec![hash.clone()]);
+    }
+
+    let mut hashes = Vec::new();
+    let mut hashes_buf = Vec::new();
+    for blocklist in &entry.block_lists {
+        hashes_buf.clear();
+        db.get_content_block(blocklist, &mut hashes_buf)
+            .wrap_err_with(|| format!("read blocklist {}", blocklist))?
+            .ok_or_else(|| eyre!("Failed to find blocklist {}", blocklist))?;
+        for bhash in hashes_buf.chunks(db.hash_size()) {
+            hashes.push(db.blocklist_hash(bhash)?);
+        }
+    }
+    Ok(hashes)
+}
@@ -0,0 +1,33 @@
+use crate::blockhash::BlockIdHash;
+use eyre::Result;
+use std::collections::HashMap;
+
+/// Source of block contents other than the indexed dblocks, see DFileDatabase::with_block_provider
+///
+/// get_block appends the block to buf and returns Ok(Some(bytes appended)),
+/// or Ok(None) if it doesn't have the block. Err is for a block that
+/// exists but can't be read. Called from many threads at once
+pub trait BlockProvider: Send + Sync {
+    fn get_block(&self, hash: &BlockIdHash, buf: &mut Vec<u8>) -> Result<Option<usize>>;
+}
+
+/// Blocks held in memory, e.g. for tests
+#[derive(Default)]
+pub struct HashMapBlockProvider {
+    blocks: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl HashMapBlockProvider {
+    pub fn insert(&mut self, hash: &BlockIdHash, block: Vec<u8>) {
+        self.blocks.insert(hash.hash.to_vec(), block);
+    }
+}
+
+impl BlockProvider for HashMapBlockProvider {
+    fn get_block(&self, hash: &BlockIdHash, buf: &mut Vec<u8>) -> Result<Option<usize>> {
+        Ok(self.blocks.get(hash.hash.as_slice()).map(|block| {
+            buf.extend_from_slice(block);
+            block.len()
+        }))
+    }
+}
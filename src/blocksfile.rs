@@ -0,0 +1,108 @@
+use crate::{
+    blockhash::BlockIdHash, database::DFileDatabase, dfileentry::FileEntry, dfiletype::FileType,
+};
+use base64::{engine::general_purpose, Engine};
+use eyre::{eyre, Context, Result};
+use rayon::prelude::*;
+use std::{collections::HashSet, fs, path::Path};
+
+/// How much of a file the --blocks-file allowlist covers
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Coverage {
+    Full,
+    Partial,
+    None,
+}
+
+/// Reads block hashes, one per line: base64 as in the dlist, urlsafe base64
+/// as dblock entries are named, or hex. Empty lines and # comments are skipped
+pub fn read_blocks_file(path: &Path) -> Result<HashSet<BlockIdHash>> {
+    let content = fs::read_to_string(path).wrap_err_with(|| format!("read {:?}", path))?;
+    let mut hashes = HashSet::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let hash = general_purpose::STANDARD
+            .decode(line)
+            .or_else(|_| general_purpose::URL_SAFE.decode(line))
+            .ok()
+            .and_then(|bytes| BlockIdHash::from_bytes(&bytes))
+            .or_else(|| BlockIdHash::from_hex(line))
+            .ok_or_else(|| {
+                eyre!(
+                    "{:?} line {}: not a block hash: {:?}",
+                    path,
+                    number + 1,
+                    line
+                )
+            })?;
+        hashes.insert(hash);
+    }
+    Ok(hashes)
+}
+
+/// Coverage of every entry, in order. Anything but a file with content is fully covered
+///
+/// Content blocks have to be in allowed. Blocklists are read from the db, a file
+/// whose blocklist is in no indexed dblock is at most partially covered
+pub fn block_coverage(
+    entries: &[FileEntry],
+    db: &DFileDatabase,
+    allowed: &HashSet<BlockIdHash>,
+) -> Result<Vec<Coverage>> {
+    entries
+        .par_iter()
+        .map_init(Vec::new, |hashes_buf, entry| {
+            file_coverage(entry, db, allowed, hashes_buf)
+                .wrap_err_with(|| format!("block coverage of {:?}", entry.path))
+        })
+        .collect()
+}
+
+fn file_coverage(
+    entry: &FileEntry,
+    db: &DFileDatabase,
+    allowed: &HashSet<BlockIdHash>,
+    hashes_buf: &mut Vec<u8>,
+) -> Result<Coverage> {
+    let FileType::File { hash, size, .. } = &entry.file_type else {
+        return Ok(Coverage::Full);
+    };
+    if *size == 0 || hash.is_empty_block(db.block_hash_algo()) {
+        return Ok(Coverage::Full);
+    }
+    if entry.block_lists.is_empty() {
+        return Ok(if allowed.contains(hash) {
+            Coverage::Full
+        } else {
+            Coverage::None
+        });
+    }
+
+    let (mut covered, mut uncovered) = (0usize, 0usize);
+    for blocklist in &entry.block_lists {
+        hashes_buf.clear();
+        let found = db
+            .get_content_block(blocklist, hashes_buf)
+            .wrap_err_with(|| format!("read blocklist {}", blocklist))?;
+        if found.is_none() {
+            uncovered += 1;
+            continue;
+        }
+        for bhash in hashes_buf.chunks(db.hash_size()) {
+            let bhash = db.blocklist_hash(bhash)?;
+            if allowed.contains(&bhash) {
+                covered += 1;
+            } else {
+                uncovered += 1;
+            }
+        }
+    }
+    Ok(match (covered, uncovered) {
+        (_, 0) => Coverage::Full,
+        (0, _) => Coverage::None,
+        _ => Coverage::Partial,
+    })
+}
@@ -0,0 +1,351 @@
+use crate::{hexdisplay::HexDisplayBytes, pathcheck::NAME_MAX};
+use eyre::{eyre, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Where restored file content goes, shared by all workers
+pub trait BlockSink: Sync {
+    /// relative is the path under the restore dir, after renaming
+    fn create_file(&self, relative: &Path) -> Result<Box<dyn FileSink + '_>>;
+}
+
+/// Lets the caller keep a handle to a sink it hands to RestoreParams
+impl<S: BlockSink + Send> BlockSink for Arc<S> {
+    fn create_file(&self, relative: &Path) -> Result<Box<dyn FileSink + '_>> {
+        (**self).create_file(relative)
+    }
+}
+
+/// Receives the content of one restored file
+pub trait FileSink {
+    /// Blocks come in file order, offset is where buf starts in the file
+    fn write_block(&mut self, offset: u64, buf: &[u8]) -> Result<()>;
+
+    /// Called once the file is verified, never after an error
+    fn finish(self: Box<Self>) -> Result<()>;
+
+    /// Called before the first block with the declared size, if it isn't 0
+    fn preallocate(&mut self, _len: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// The same file taking writes from several threads at once, if the sink can
+    fn positioned(&self) -> Option<&dyn PositionedFile> {
+        None
+    }
+}
+
+/// A file being restored whose blocks can be written in any order, concurrently
+pub trait PositionedFile: Sync {
+    fn write_block_at(&self, offset: u64, buf: &[u8]) -> Result<()>;
+
+    /// Reads back what was written, fills all of buf
+    fn read_block_at(&self, offset: u64, buf: &mut [u8]) -> Result<()>;
+}
+
+/// Writes files under a directory
+pub struct DirBlockSink {
+    root: PathBuf,
+    /// Creates missing parent directories of files, needed when the folder pass is skipped
+    create_parent_dirs: bool,
+}
+
+impl DirBlockSink {
+    pub fn new<P: AsRef<Path>>(root: P, create_parent_dirs: bool) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            create_parent_dirs,
+        }
+    }
+}
+
+/// Files are written under this suffix and renamed once verified
+///
+/// An interrupted restore leaves only .partial files behind, so a file with
+/// its final name is always complete, whatever its size says
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// <name>.partial, or .<hash of name>.partial if that would be over NAME_MAX
+pub fn partial_path_of(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    if name.len() + PARTIAL_SUFFIX.len() > NAME_MAX {
+        let digest = Sha256::digest(name.as_encoded_bytes());
+        name = format!(".{}", HexDisplayBytes(&digest[..16])).into();
+    }
+    name.push(PARTIAL_SUFFIX);
+    path.with_file_name(name)
+}
+
+impl BlockSink for DirBlockSink {
+    fn create_file(&self, relative: &Path) -> Result<Box<dyn FileSink + '_>> {
+        let path = self.root.join(relative);
+        let partial_path = partial_path_of(&path);
+        if self.create_parent_dirs {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        // Readable for hashing what --intra-file-parallelism wrote out of order
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&partial_path)
+            .wrap_err_with(|| format!("create {:?}", partial_path))?;
+        Ok(Box::new(PartialFile {
+            file,
+            partial_path,
+            path,
+        }))
+    }
+}
+
+struct PartialFile {
+    file: File,
+    partial_path: PathBuf,
+    path: PathBuf,
+}
+
+impl FileSink for PartialFile {
+    fn write_block(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .wrap_err("seek to block offset")?;
+        self.file.write_all(buf).wrap_err("write block")?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let Self {
+            file,
+            partial_path,
+            path,
+        } = *self;
+        // Windows can't rename open files
+        drop(file);
+        fs::rename(&partial_path, &path)
+            .wrap_err_with(|| format!("rename {:?} to {:?}", partial_path, path))?;
+        Ok(())
+    }
+
+    /// Growing the file block by block lets it fragment, which slows reading it back from a HDD
+    fn preallocate(&mut self, len: u64) -> Result<()> {
+        self.file.set_len(len).wrap_err("set file length")?;
+        // Reserves the extents too, so they can be contiguous. Not every filesystem can
+        #[cfg(target_os = "linux")]
+        if let Err(err) = fallocate(&self.file, len) {
+            if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+                return Err(err).wrap_err_with(|| format!("allocate {} bytes", len));
+            }
+        }
+        Ok(())
+    }
+
+    fn positioned(&self) -> Option<&dyn PositionedFile> {
+        Some(self)
+    }
+}
+
+/// Not posix_fallocate, whose fallback writes zeros where the filesystem can't allocate
+#[cfg(target_os = "linux")]
+fn fallocate(file: &File, len: u64) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let ret = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off_t) };
+    if ret == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl PositionedFile for PartialFile {
+    #[cfg(unix)]
+    fn write_block_at(&self, offset: u64, buf: &[u8]) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+        self.file.write_all_at(buf, offset)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn read_block_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+        self.file.read_exact_at(buf, offset)?;
+        Ok(())
+    }
+
+    // seek_write/seek_read move the shared cursor, harmless as nothing else uses it here
+    #[cfg(windows)]
+    fn write_block_at(&self, mut offset: u64, mut buf: &[u8]) -> Result<()> {
+        use std::os::windows::fs::FileExt;
+        while !buf.is_empty() {
+            let n = self.file.seek_write(buf, offset)?;
+            if n == 0 {
+                return Err(eyre!("wrote 0 bytes at offset {}", offset));
+            }
+            buf = &buf[n..];
+            offset += n as u64;
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn read_block_at(&self, mut offset: u64, mut buf: &mut [u8]) -> Result<()> {
+        use std::os::windows::fs::FileExt;
+        while !buf.is_empty() {
+            let n = self.file.seek_read(buf, offset)?;
+            if n == 0 {
+                return Err(eyre!("file ends before offset {}", offset));
+            }
+            buf = &mut std::mem::take(&mut buf)[n..];
+            offset += n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps restored files in memory, for assert-matches and for consumers that don't want a disk
+///
+/// cap bounds the bytes written over all files, including files that failed later
+pub struct MemoryBlockSink {
+    files: Mutex<HashMap<PathBuf, Vec<u8>>>,
+    cap: u64,
+    used: AtomicU64,
+}
+
+impl MemoryBlockSink {
+    pub fn new(cap: u64) -> Self {
+        Self {
+            files: Mutex::new(HashMap::new()),
+            cap,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Relative path -> content of every finished file
+    pub fn into_files(self) -> HashMap<PathBuf, Vec<u8>> {
+        self.files.into_inner().unwrap()
+    }
+}
+
+impl BlockSink for MemoryBlockSink {
+    fn create_file(&self, relative: &Path) -> Result<Box<dyn FileSink + '_>> {
+        Ok(Box::new(MemoryFile {
+            sink: self,
+            path: relative.to_path_buf(),
+            content: Vec::new(),
+        }))
+    }
+}
+
+struct MemoryFile<'a> {
+    sink: &'a MemoryBlockSink,
+    path: PathBuf,
+    content: Vec<u8>,
+}
+
+impl FileSink for MemoryFile<'_> {
+    fn write_block(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        let end = offset as usize + buf.len();
+        let grow = end.saturating_sub(self.content.len()) as u64;
+        let used = self.sink.used.fetch_add(grow, Ordering::Relaxed) + grow;
+        if used > self.sink.cap {
+            return Err(eyre!(
+                "memory sink cap of {} bytes exceeded by {:?}",
+                self.sink.cap,
+                self.path
+            ));
+        }
+        if self.content.len() < end {
+            self.content.resize(end, 0);
+        }
+        self.content[offset as usize..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let MemoryFile {
+            sink,
+            path,
+            content,
+        } = *self;
+        sink.files.lock().unwrap().insert(path, content);
+        Ok(())
+    }
+}
+
+/// Compares blocks with files already restored under root instead of writing them
+pub struct CompareBlockSink {
+    root: PathBuf,
+}
+
+impl CompareBlockSink {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl BlockSink for CompareBlockSink {
+    fn create_file(&self, relative: &Path) -> Result<Box<dyn FileSink + '_>> {
+        let path = self.root.join(relative);
+        let file = File::open(&path).wrap_err_with(|| format!("open restored {:?}", path))?;
+        Ok(Box::new(CompareFile {
+            file,
+            path,
+            end: 0,
+            buf: Vec::new(),
+        }))
+    }
+}
+
+struct CompareFile {
+    file: File,
+    path: PathBuf,
+    /// Furthest byte compared, the restored file must not be longer
+    end: u64,
+    buf: Vec<u8>,
+}
+
+impl FileSink for CompareFile {
+    fn write_block(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        self.buf.resize(buf.len(), 0);
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .wrap_err("seek to block offset")?;
+        self.file
+            .read_exact(&mut self.buf)
+            .wrap_err_with(|| format!("{:?} is shorter than the backup", self.path))?;
+        if self.buf != buf {
+            return Err(eyre!(
+                "{:?} differs from the backup in the block at offset {}",
+                self.path,
+                offset
+            ));
+        }
+        self.end = self.end.max(offset + buf.len() as u64);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let len = self.file.metadata()?.len();
+        if len != self.end {
+            return Err(eyre!(
+                "{:?} is {} bytes, the backup has {}",
+                self.path,
+                len,
+                self.end
+            ));
+        }
+        Ok(())
+    }
+}
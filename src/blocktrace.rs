@@ -0,0 +1,76 @@
+use crate::{blockhash::BlockIdHash, ziparchive::BlockLocation};
+use eyre::{Context, Result};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Full per-block event log, used to find out why a restore is slow
+///
+/// Lines are formatted before taking the lock, so writers only contend on the copy into BufWriter
+pub struct BlockTrace {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl BlockTrace {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::create(path.as_ref())
+            .wrap_err_with(|| format!("create trace file {:?}", path.as_ref()))?;
+        let mut writer = BufWriter::with_capacity(256 * 1024, file);
+        writeln!(
+            writer,
+            "timestamp_us\tthread\tblock_hash\tvolume\tfile_index\tbytes\tout_offset"
+        )?;
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// out_offset is None for blocklist reads, which are not written to the output file
+    pub fn record(
+        &self,
+        hash: &BlockIdHash,
+        location: Option<&BlockLocation>,
+        bytes: usize,
+        out_offset: Option<u64>,
+    ) -> Result<()> {
+        let timestamp_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros())
+            .unwrap_or(0);
+        let thread = std::thread::current().id();
+        let (volume, file_index) = match location {
+            Some(loc) => (
+                loc.ziplocation.path.to_string_lossy().to_string(),
+                loc.file_index.to_string(),
+            ),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        let out_offset = out_offset
+            .map(|o| o.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let line = format!(
+            "{timestamp_us}\t{thread:?}\t{hash}\t{volume}\t{file_index}\t{bytes}\t{out_offset}\n"
+        );
+
+        self.writer
+            .lock()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .wrap_err("write trace line")?;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.writer
+            .lock()
+            .unwrap()
+            .flush()
+            .wrap_err("flush trace file")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+use crate::backend::{self, Backend, Stat};
+use crate::crypto::ReadSeek;
+use eyre::{eyre, Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use zip::{CompressionMethod, ZipArchive};
+
+/// A tar or zip of a backup directory, read in place with --backup-archive
+///
+/// Opening a member opens the archive and limits reads to the member's bytes,
+/// nothing is extracted
+pub struct Bundle {
+    path: PathBuf,
+    /// Of the archive, for every member
+    modified: SystemTime,
+    members: BTreeMap<String, Member>,
+}
+
+/// Where a member's content is in the archive
+#[derive(Clone, Copy)]
+struct Member {
+    offset: u64,
+    len: u64,
+}
+
+/// Indexes the members of the archive at path once and mounts them under path
+/// as if it was a directory. Returns how many there are
+pub fn mount_archive(path: &Path) -> Result<usize> {
+    let mut file = File::open(path).wrap_err_with(|| format!("open {:?}", path))?;
+    let modified = file.metadata()?.modified()?;
+    let mut magic = [0u8; 4];
+    let n = file.read(&mut magic)?;
+    file.rewind()?;
+    let members = if magic[..n] == *b"PK\x03\x04" {
+        zip_members(file)
+    } else {
+        tar_members(file)
+    }
+    .wrap_err_with(|| format!("index {:?}", path))?;
+    let count = members.len();
+    let bundle = Bundle {
+        path: path.to_path_buf(),
+        modified,
+        members,
+    };
+    backend::mount(path, Arc::new(bundle));
+    Ok(count)
+}
+
+impl Backend for Bundle {
+    fn names(&self) -> Vec<String> {
+        self.members.keys().cloned().collect()
+    }
+
+    fn stat(&self, name: &str) -> Option<Stat> {
+        let member = self.members.get(name)?;
+        Some(Stat {
+            len: member.len,
+            modified: self.modified,
+        })
+    }
+
+    fn open(&self, name: &str) -> io::Result<Box<dyn ReadSeek>> {
+        let member = *self
+            .members
+            .get(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such member"))?;
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(member.offset))?;
+        Ok(Box::new(MemberReader {
+            file,
+            member,
+            pos: 0,
+        }))
+    }
+}
+
+/// Reads one member of the archive file
+struct MemberReader {
+    file: File,
+    member: Member,
+    /// Relative to the member's first byte
+    pos: u64,
+}
+
+impl Read for MemberReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.member.len.saturating_sub(self.pos);
+        let max = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        let n = self.file.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for MemberReader {
+    fn seek(&mut self, seek: SeekFrom) -> io::Result<u64> {
+        let pos = match seek {
+            SeekFrom::Start(to) => Some(to),
+            SeekFrom::End(delta) => self.member.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        let pos = pos
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        self.file.seek(SeekFrom::Start(self.member.offset + pos))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+const TAR_BLOCK: u64 = 512;
+
+/// Regular files of a ustar, pax or GNU tar by name, directories are left out
+///
+/// Only the file name is kept, a backup directory is flat. Reads the headers
+/// only, seeking over the content
+fn tar_members(mut file: File) -> Result<BTreeMap<String, Member>> {
+    let tar_len = file.metadata()?.len();
+    let mut members = BTreeMap::new();
+    let mut header = [0u8; TAR_BLOCK as usize];
+    let mut offset = 0;
+    // From a GNU 'L' or pax 'x' header, for the header after it
+    let mut long_name: Option<String> = None;
+    loop {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)
+            .wrap_err_with(|| format!("read tar header at {}", offset))?;
+        if header.iter().all(|&b| b == 0) {
+            // End of archive
+            break;
+        }
+        check_tar_checksum(&header).wrap_err_with(|| format!("tar header at {}", offset))?;
+        let len = tar_number(&header[124..136])
+            .ok_or_else(|| eyre!("bad size in tar header at {}", offset))?;
+        let content = offset + TAR_BLOCK;
+        if content + len > tar_len {
+            return Err(eyre!(
+                "tar member at {} ends at {}, after the end of the archive at {}",
+                offset,
+                content + len,
+                tar_len
+            ));
+        }
+        let read_content = |file: &mut File| -> Result<Vec<u8>> {
+            let mut buf = vec![0; usize::try_from(len)?];
+            file.read_exact(&mut buf)?;
+            Ok(buf)
+        };
+        match header[156] {
+            b'L' => {
+                let name = read_content(&mut file)?;
+                long_name = Some(String::from_utf8_lossy(until_nul(&name)).into_owned());
+            }
+            b'x' => {
+                let records = read_content(&mut file)?;
+                if let Some(path) = pax_path(&records) {
+                    long_name = Some(path);
+                }
+            }
+            // Regular file, '\0' in pre-POSIX tars
+            b'0' | b'\0' | b'7' => {
+                let name = long_name.take().unwrap_or_else(|| ustar_name(&header));
+                if let Some(name) = Path::new(&name).file_name().and_then(|n| n.to_str()) {
+                    members.insert(
+                        name.to_string(),
+                        Member {
+                            offset: content,
+                            len,
+                        },
+                    );
+                }
+            }
+            _ => long_name = None,
+        }
+        offset = content + len.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+    }
+    Ok(members)
+}
+
+fn until_nul(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+/// The name field, behind the ustar prefix if there is one
+fn ustar_name(header: &[u8]) -> String {
+    let name = String::from_utf8_lossy(until_nul(&header[0..100]));
+    if &header[257..262] == b"ustar" {
+        let prefix = String::from_utf8_lossy(until_nul(&header[345..500]));
+        if !prefix.is_empty() {
+            return format!("{}/{}", prefix, name);
+        }
+    }
+    name.into_owned()
+}
+
+/// Octal, or big-endian binary if the high bit of the first byte is set (GNU)
+fn tar_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7f), |n, &b| {
+                n.checked_mul(256)?.checked_add(u64::from(b))
+            });
+    }
+    let digits = std::str::from_utf8(until_nul(field)).ok()?.trim();
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// Sum of the header bytes with the checksum field as spaces
+fn check_tar_checksum(header: &[u8]) -> Result<()> {
+    let stored = tar_number(&header[148..156]).ok_or_else(|| eyre!("bad checksum field"))?;
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| match i {
+            148..156 => u64::from(b' '),
+            _ => u64::from(b),
+        })
+        .sum();
+    if sum != stored {
+        return Err(eyre!(
+            "not a tar archive, or a damaged one: checksum mismatch"
+        ));
+    }
+    Ok(())
+}
+
+/// The path record of pax extended header records, "<len> path=<value>\n"
+fn pax_path(records: &[u8]) -> Option<String> {
+    let mut rest = records;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ')?;
+        let len: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        let record = rest.get(space + 1..len)?;
+        if let Some(path) = record.strip_prefix(b"path=") {
+            let path = path.strip_suffix(b"\n").unwrap_or(path);
+            return Some(String::from_utf8_lossy(path).into_owned());
+        }
+        rest = &rest[len..];
+    }
+    None
+}
+
+/// Files of a zip by name
+///
+/// Only files stored uncompressed can be read in place, compressed ones are
+/// warned about and left out
+fn zip_members(file: File) -> Result<BTreeMap<String, Member>> {
+    let mut zip = ZipArchive::new(file)?;
+    let mut members = BTreeMap::new();
+    for index in 0..zip.len() {
+        let entry = zip.by_index_raw(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        if entry.compression() != CompressionMethod::Stored {
+            println!(
+                "warn: left out {:?}, it is compressed, only stored (zip -0) files can be read in place",
+                entry.name()
+            );
+            continue;
+        }
+        let Some(name) = Path::new(entry.name()).file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        members.insert(
+            name.to_string(),
+            Member {
+                offset: entry.data_start(),
+                len: entry.compressed_size(),
+            },
+        );
+    }
+    Ok(members)
+}
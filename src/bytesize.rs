@@ -0,0 +1,29 @@
+/// A size for output: "1.5 GiB" when human, "1610612736 bytes" otherwise
+#[derive(Clone, Copy, Debug)]
+pub struct ByteSize {
+    pub bytes: u64,
+    /// --human-readable
+    pub human: bool,
+}
+
+impl ByteSize {
+    pub fn new(bytes: u64, human: bool) -> Self {
+        Self { bytes, human }
+    }
+}
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+        if !self.human || self.bytes < 1024 {
+            return write!(f, "{} bytes", self.bytes);
+        }
+        let mut value = self.bytes as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,1465 @@
+use crate::assertmatches::assert_matches;
+use crate::backend;
+use crate::bestversion::merge_best_effort_latest;
+use crate::blocksfile::{block_coverage, read_blocks_file, Coverage};
+use crate::blocksink::{BlockSink, CompareBlockSink, DirBlockSink, MemoryBlockSink};
+use crate::blocktrace::BlockTrace;
+use crate::bundle::mount_archive;
+use crate::bytesize::ByteSize;
+use crate::completeness::{incomplete_files, missing_blocks};
+use crate::crypto::resolve_passphrase;
+use crate::dlist::{
+    check_dlist_file, describe_names, find_dlist_files, is_volume, keep_created_before,
+    parse_dlist_file, read_manifest, sort_dlists_by_created, FileEntries, VolumeKind,
+};
+use crate::filereader::{check_range, file_size, read_range};
+use crate::flags::{Command, PathOs, RestoreFlags, SidecarAlgorithm, ZipReader};
+use crate::freespace::{available_space_for, triage_to_free_space};
+use crate::indexcache::IndexCache;
+use crate::interrupt::is_interrupted;
+use crate::listing::list_entries;
+use crate::manifestcheck::check_manifest_consistency;
+use crate::metadataonly::restore_metadata_only;
+use crate::mtime::parse_backup_time;
+use crate::pathcheck::{
+    conflict_renames, find_long_names, find_path_problems, flattened_renames, sanitized_renames,
+    ConflictSuffix, Fix, FLATTEN_MAP_NAME,
+};
+use crate::pathfilter::{only_path, subtree, PathFilter};
+use crate::pathstyle::{extended_length_path, PathStyle};
+use crate::progress::{PbrProgress, RestoreProgress};
+use crate::reflink::{find_duplicates, reflink_duplicate};
+use crate::report::{
+    FailedFile, FailedFiles, ReportRow, RestoreReport, RunReport, RUN_REPORT_SCHEMA_VERSION,
+};
+use crate::restoring::{
+    calculate_path, calculate_summary, restore_entry, HashMismatch, RestoreContext, RestoreParams,
+    RestoreSummary, SidecarHash,
+};
+use crate::skipexisting::skip_existing;
+use crate::sorting::{sort_files_sequentially, volume_runs};
+use crate::special::resolve_special_files;
+use crate::symlinks::restore_symlinks;
+use crate::verifyblocks::{report_verify_blocks, verify_blocks};
+use crate::versiondiff::changed_files_since;
+use crate::ziparchive::default_max_open_archives;
+
+use crate::database::*;
+use crate::dfileentry::*;
+use eyre::eyre;
+use eyre::{Context, Result};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Exit code when filtering left no entries to restore
+pub const EXIT_NOTHING_TO_RESTORE: i32 = 2;
+/// Exit code after Ctrl-C, as shells report a process killed by SIGINT
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// How a successful run ended, decides the exit code
+pub enum RunOutcome {
+    Finished,
+    /// Restored or verified the whole selection
+    Restored(RestoreSummary),
+    NothingToRestore,
+    /// --print-config, stdout holds only the JSON
+    PrintedConfig,
+    /// list, nothing is printed after the listing
+    Listed,
+    /// Ctrl-C stopped the restore, the files started before it are complete
+    Interrupted,
+}
+
+/// Flags open_dblock_db needs, owned to move them to the indexing thread
+struct DbOptions {
+    /// Empty to find them by name, see VolumeKind
+    dblock_extensions: Vec<String>,
+    dindex_extensions: Vec<String>,
+    ignore_dindex: bool,
+    hash_to_path: bool,
+    block_read_chunk: Option<usize>,
+    max_open_archives: Option<usize>,
+    skip_bad_volumes: bool,
+    passphrase: Option<String>,
+    block_cache_bytes: Option<usize>,
+    reader: ZipReader,
+    /// Indexing threads, None to index on the global pool
+    io_threads: Option<usize>,
+    check_manifests: bool,
+}
+
+impl DbOptions {
+    fn new(args: &RestoreFlags) -> Self {
+        Self {
+            dblock_extensions: args.dblock_extensions.clone(),
+            dindex_extensions: args.dindex_extensions.clone(),
+            ignore_dindex: args.ignore_dindex,
+            hash_to_path: args.hash_to_path,
+            block_read_chunk: args.block_read_chunk,
+            max_open_archives: args.max_open_archives.or_else(default_max_open_archives),
+            skip_bad_volumes: args.skip_bad_volumes,
+            passphrase: args.passphrase.clone(),
+            block_cache_bytes: args.block_cache_mb.map(|mb| mb.saturating_mul(1024 * 1024)),
+            reader: args.reader,
+            io_threads: args.threads_io,
+            check_manifests: args.check_manifest_consistency,
+        }
+    }
+}
+
+/// Lists the backup at --backend and mounts it, returns how many files it has
+#[cfg_attr(not(feature = "sftp"), allow(unused_variables))]
+fn mount_backend(url: &str, args: &RestoreFlags) -> Result<usize> {
+    #[cfg(any(not(feature = "s3"), not(feature = "sftp")))]
+    let needs_feature = |feature: &str| {
+        eyre!(
+            "--backend {:?} needs a build with the {} feature (cargo build --features {})",
+            url,
+            feature,
+            feature
+        )
+    };
+    match url.split_once("://").map(|(scheme, _)| scheme) {
+        #[cfg(feature = "s3")]
+        Some("s3") => crate::s3::mount_s3(url),
+        #[cfg(not(feature = "s3"))]
+        Some("s3") => Err(needs_feature("s3")),
+        #[cfg(feature = "sftp")]
+        Some("sftp") => crate::sftp::mount_sftp(url, args.identity.as_deref()),
+        #[cfg(not(feature = "sftp"))]
+        Some("sftp") => Err(needs_feature("sftp")),
+        _ => Err(eyre!("--backend {:?} is not an s3:// or sftp:// URL", url)),
+    }
+}
+
+/// Lists and indexes the dblocks in dblock_dir
+fn open_dblock_db(
+    dblock_dir: &str,
+    manifest_contents: &[u8],
+    options: &DbOptions,
+    index_cache: Option<&Path>,
+) -> Result<DFileDatabase> {
+    let dblock_extensions = &options.dblock_extensions;
+    println!("Listing dblocks");
+    // Get list of dblocks and dindexes
+    let dir_files = backend::read_dir(Path::new(dblock_dir))
+        .wrap_err_with(|| format!("read_dir({:?})", dblock_dir))?;
+    let zip_file_names: Vec<PathBuf> = dir_files
+        .iter()
+        .filter(|path| is_volume(path, VolumeKind::Dblock, dblock_extensions))
+        .cloned()
+        .collect();
+    let dindex_paths: Vec<PathBuf> = dir_files
+        .into_iter()
+        .filter(|path| {
+            !options.ignore_dindex
+                && is_volume(path, VolumeKind::Dindex, &options.dindex_extensions)
+        })
+        .collect();
+    if zip_file_names.is_empty() {
+        Err(eyre!(
+            "no dblock file {} found in {:?}",
+            describe_names(VolumeKind::Dblock, dblock_extensions),
+            dblock_dir
+        ))?;
+    }
+
+    println!("Found {} dblocks", zip_file_names.len());
+    if dindex_paths.is_empty() && index_cache.is_none() && !backend::is_on_disk(&zip_file_names[0])
+    {
+        println!(
+            "warn: no dindex files, the index of every dblock is read over the network. --index-cache keeps it for later runs"
+        );
+    }
+    println!("Indexing dblocks");
+    let index_cache = index_cache.map(IndexCache::open).transpose()?;
+    let hash_to_path = options.hash_to_path || index_cache.is_some() || !dindex_paths.is_empty();
+    let dblock_db = DFileDatabase::new(manifest_contents, hash_to_path)?
+        .with_block_read_chunk(options.block_read_chunk)
+        .with_max_open_archives(options.max_open_archives)
+        .with_skip_bad_volumes(options.skip_bad_volumes)
+        .with_passphrase(options.passphrase.clone())
+        .with_block_cache(options.block_cache_bytes)
+        .with_reader(options.reader)
+        .with_index_cache(index_cache);
+    in_pool(options.io_threads, "--threads-io", || -> Result<()> {
+        if dindex_paths.is_empty() {
+            dblock_db.create_block_id_to_filenames(&zip_file_names)?;
+            return Ok(());
+        }
+        println!("Found {} dindex files", dindex_paths.len());
+        let from_dindex = dblock_db.create_index_from_dindex(&dindex_paths)?;
+        let to_scan: Vec<PathBuf> = zip_file_names
+            .iter()
+            .filter(|path| !from_dindex.contains(*path))
+            .cloned()
+            .collect();
+        let scanned = dblock_db.create_block_id_to_filenames(&to_scan)?;
+        println!("{} blocks scanned in {} dblocks", scanned, to_scan.len());
+        Ok(())
+    })??;
+    print_index_diagnostics(&dblock_db);
+    if options.check_manifests {
+        in_pool(options.io_threads, "--threads-io", || {
+            check_manifest_consistency(&dblock_db)
+        })??;
+    }
+    Ok(dblock_db)
+}
+
+/// Warns about duplicate and malformed block names, they point at an odd or damaged backup
+fn print_index_diagnostics(db: &DFileDatabase) {
+    let Some(diagnostics) = db.index_diagnostics() else {
+        return;
+    };
+    if diagnostics.duplicate_blocks > 0 {
+        println!(
+            "warn: {} blocks are stored more than once, only one of the copies is read",
+            diagnostics.duplicate_blocks
+        );
+    }
+    if let Some(first) = &diagnostics.first_bad_name {
+        println!(
+            "warn: {} dblock entries are not {}-byte {} block hashes and were left out (e.g. {:?})",
+            diagnostics.bad_names,
+            db.hash_size(),
+            db.block_hash_algo(),
+            first
+        );
+    }
+    if diagnostics.is_healthy() {
+        println!("Index check: no duplicate or malformed block names");
+    }
+}
+
+/// Reassembles every restored file from another copy of the backup and compares
+///
+/// Restored files, the local dlist (file hashes) and the other copy's volumes must all agree
+fn compare_with_remote(
+    args: &RestoreFlags,
+    remote_dir: &str,
+    restore_dir: &str,
+    manifest_contents: &[u8],
+    local_params: RestoreParams<'_>,
+    entries: FileEntries,
+) -> Result<()> {
+    println!();
+    println!("Comparing restored files with {:?}", remote_dir);
+    let remote_db = open_dblock_db(remote_dir, manifest_contents, &DbOptions::new(args), None)?;
+    let params = RestoreParams {
+        db: Arc::new(remote_db),
+        restore_path: Some(restore_dir),
+        path_style: local_params.path_style,
+        restore_mtime: false,
+        mtime_source: local_params.mtime_source,
+        restore_permissions: false,
+        restore_special: false,
+        restore_reparse: false,
+        prefetch: args.prefetch,
+        intra_file_parallelism: args.intra_file_parallelism,
+        per_block_hash: args.per_block_hash,
+        write_order: args.write_order,
+        validate_before_write: args.validate_before_write,
+        sidecar: None,
+        sink: Some(Box::new(CompareBlockSink::new(restore_dir))),
+        path_renames: local_params.path_renames,
+        strip_prefix: local_params.strip_prefix,
+        summary: calculate_summary(&entries.entries),
+        trace: None,
+        report: None,
+        progress: progress_bar(args, &calculate_summary(&entries.entries)),
+    };
+    in_checksum_pool(args, || {
+        restore_files(args, &params, &entries, "Comparing", false)
+    })??;
+    println!("Restored files match {:?}", remote_dir);
+    Ok(())
+}
+
+/// Restores the files of entries in memory and compares them with reference_dir
+fn assert_matches_reference(
+    args: &RestoreFlags,
+    reference_dir: &Path,
+    mut params: RestoreParams<'_>,
+    entries: FileEntries,
+) -> Result<()> {
+    // The summary counts every byte to restore, more would be a bug
+    let sink = Arc::new(MemoryBlockSink::new(params.summary.total_bytes));
+    params.sink = Some(Box::new(sink.clone()));
+    let mut files = FileEntries {
+        entries: entries
+            .entries
+            .into_iter()
+            .filter(|e| e.is_file())
+            .collect(),
+    };
+    sort_files_sequentially(&mut files.entries, &params.db);
+    restore_files(args, &params, &files, "Restoring in memory", true)?;
+    drop(params);
+
+    println!();
+    println!("Comparing with {:?}", reference_dir);
+    let restored = Arc::into_inner(sink)
+        .expect("params with the other handle are dropped")
+        .into_files();
+    assert_matches(&restored, reference_dir)?;
+    println!("All {} files match {:?}", restored.len(), reference_dir);
+    Ok(())
+}
+
+/// Writes the --offset/--length range of one file to output
+fn extract_single_file(
+    args: &RestoreFlags,
+    db: &DFileDatabase,
+    file_entries: &FileEntries,
+    path: &str,
+    output: &Path,
+) -> Result<()> {
+    let size = file_size(&file_entries.entries, path)?;
+    let length = args
+        .length
+        .unwrap_or_else(|| size.saturating_sub(args.offset));
+    // Before creating output, which would truncate it
+    check_range(&file_entries.entries, path, args.offset, length)?;
+    println!(
+        "Extracting {} bytes at offset {} of {:?}",
+        length, args.offset, path
+    );
+    let file = File::create(output).wrap_err_with(|| format!("create {:?}", output))?;
+    let mut out = std::io::BufWriter::new(file);
+    read_range(
+        db,
+        &file_entries.entries,
+        path,
+        args.offset,
+        length,
+        &mut out,
+    )?;
+    out.into_inner()
+        .map_err(|err| err.into_error())
+        .wrap_err_with(|| format!("flush {:?}", output))?;
+    println!("Wrote {:?}", output);
+    Ok(())
+}
+
+/// Runs a pass over files already on disk, on its own --checksum-parallelism threads if given
+///
+/// Hashing local files and reading backup blocks have different good widths, e.g. an SSD
+/// target restored from a slow remote
+fn in_checksum_pool<T: Send>(args: &RestoreFlags, pass: impl FnOnce() -> T + Send) -> Result<T> {
+    in_pool(args.checksum_parallelism, "--checksum-parallelism", pass)
+}
+
+/// Runs pass on a pool of that many threads if given, else on the global pool.
+/// flag names the setting in errors
+fn in_pool<T: Send>(
+    threads: Option<usize>,
+    flag: &str,
+    pass: impl FnOnce() -> T + Send,
+) -> Result<T> {
+    let Some(threads) = threads else {
+        return Ok(pass());
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .wrap_err_with(|| format!("build {} thread pool", flag))?;
+    Ok(pool.install(pass))
+}
+
+/// Errors if any file misses blocks, before a long restore gets started
+fn check_missing_volumes(entries: &[FileEntry], db: &DFileDatabase) -> Result<()> {
+    println!("Checking that all blocks are present");
+    let incomplete = incomplete_files(entries, db)?;
+    if incomplete.is_empty() {
+        return Ok(());
+    }
+    for (path, missing) in &incomplete {
+        println!("{} blocks missing: {:?}", missing.len(), path);
+    }
+    Err(eyre!(
+        "{} files have blocks in missing dblock volumes, nothing was restored",
+        incomplete.len()
+    ))
+}
+
+/// Blocks listed per file with --check-blocks, the rest is counted
+const CHECK_BLOCKS_LISTED: usize = 10;
+
+/// --check-blocks: resolves every content block and blocklist of entries, writes nothing
+fn check_blocks(entries: &[FileEntry], db: &DFileDatabase) -> Result<()> {
+    println!("Resolving the blocks of every file");
+    let incomplete = incomplete_files(entries, db)?;
+    if incomplete.is_empty() {
+        println!("All blocks found");
+        return Ok(());
+    }
+    let mut distinct = HashSet::new();
+    for (path, missing) in &incomplete {
+        println!("{} blocks missing: {:?}", missing.len(), path);
+        for hash in missing.iter().take(CHECK_BLOCKS_LISTED) {
+            println!("  {}", hash);
+        }
+        if missing.len() > CHECK_BLOCKS_LISTED {
+            println!("  ... {} more", missing.len() - CHECK_BLOCKS_LISTED);
+        }
+        distinct.extend(missing);
+    }
+    Err(eyre!(
+        "{} blocks missing, needed by {} files",
+        distinct.len(),
+        incomplete.len()
+    ))
+}
+
+/// Prints a row per dlist, newest first: --version index, Created, AppVersion, file count
+///
+/// Reads the dlists only, the file count needs each filelist.json parsed
+fn list_versions(
+    dlist_file_paths: &[PathBuf],
+    manifests: &HashMap<PathBuf, Vec<u8>>,
+    passphrase: Option<&str>,
+) -> Result<()> {
+    let rows = dlist_file_paths
+        .par_iter()
+        .rev()
+        .map(|path| -> Result<(String, String, usize)> {
+            let manifest = match manifests.get(path) {
+                Some(manifest) => manifest.clone(),
+                None => read_manifest(path, passphrase)?,
+            };
+            let created = parse_backup_time(&manifest_created(&manifest)?)?;
+            let app_version = manifest_app_version(&manifest)?;
+            let entries = parse_dlist_file(path, passphrase)?;
+            let summary = calculate_summary(&entries.entries);
+            Ok((created.to_string(), app_version, summary.file_count))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    println!("version\tcreated\tapp_version\tfiles");
+    for (index, (created, app_version, files)) in rows.iter().enumerate() {
+        println!("{}\t{}\t{}\t{}", index, created, app_version, files);
+    }
+    Ok(())
+}
+
+/// --source-os, or the deprecated --replace-backslash-to-slash when that is auto
+fn source_os(args: &RestoreFlags) -> PathOs {
+    match (args.source_os, args.replace_backslash_to_slash) {
+        (PathOs::Auto, Some(true)) => PathOs::Windows,
+        (PathOs::Auto, Some(false)) => PathOs::Unix,
+        (os, _) => os,
+    }
+}
+
+/// --sidecar-extension, defaults to the algorithm name
+fn sidecar_extension(args: &RestoreFlags) -> String {
+    args.sidecar_extension.clone().unwrap_or_else(|| {
+        match args.sidecar_algorithm {
+            SidecarAlgorithm::Sha256 => "sha256",
+            SidecarAlgorithm::Sha512 => "sha512",
+        }
+        .to_string()
+    })
+}
+
+/// For --print-config: the parsed flags, then what they resolve to before any dlist is read
+fn print_config(args: &RestoreFlags, dblock_dir: &str) -> Result<()> {
+    let target_os = match args.target_os {
+        PathOs::Auto if cfg!(windows) => PathOs::Windows,
+        PathOs::Auto => PathOs::Unix,
+        os => os,
+    };
+    let config = serde_json::json!({
+        "flags": args,
+        "derived": {
+            "dblock_dir": dblock_dir,
+            "threads": args.threads_rayon,
+            "threads_io": args.threads_io.unwrap_or(args.threads_rayon),
+            "hash_to_path": args.hash_to_path || args.index_cache.is_some(),
+            "max_open_archives": args.max_open_archives.or_else(default_max_open_archives),
+            // auto is decided from the dlist paths
+            "source_os": source_os(args),
+            "target_os": target_os,
+            "sidecar_extension": args.write_sidecar_hash.then(|| sidecar_extension(args)),
+            "create_parent_dirs": create_parent_dirs(args),
+        },
+    });
+    println!("{}", serde_json::to_string_pretty(&config)?);
+    Ok(())
+}
+
+/// Runs the command line tool with parsed flags
+///
+/// Prints progress to stdout as it goes, the caller turns the outcome into an exit code
+pub fn run(mut args: RestoreFlags) -> Result<RunOutcome> {
+    let started = Instant::now();
+    // The members of --backup-archive are listed and opened as if it was the backup dir
+    let backup_dir = args
+        .backup_dir
+        .as_deref()
+        .or(args.backup_archive.as_deref())
+        .or(args.backend.as_deref())
+        .map(|dir| dir.trim().to_string());
+    let dblock_dir = args
+        .dblock_dir
+        .as_deref()
+        .map(|dir| dir.trim().to_string())
+        .or_else(|| backup_dir.clone())
+        .ok_or_else(|| eyre!("--backup-dir <DIR> or --dblock-dir <DIR> not provided"))?;
+    if args.print_config {
+        print_config(&args, &dblock_dir)?;
+        return Ok(RunOutcome::PrintedConfig);
+    }
+    if let (Some(_), Some(archive)) = (&args.backup_archive, &backup_dir) {
+        let members = mount_archive(Path::new(archive))?;
+        println!("{} files in {:?}", members, archive);
+    }
+    if let (Some(_), Some(url)) = (&args.backend, &backup_dir) {
+        let objects = mount_backend(url, &args)?;
+        println!("{} files in {}", objects, url);
+    }
+    args.passphrase = resolve_passphrase(
+        args.passphrase.as_deref(),
+        args.passphrase_env.as_deref(),
+        args.passphrase_file.as_deref(),
+    )?;
+    let restores = !args.verify_only
+        && args.command.is_none()
+        && args.single_file.is_none()
+        && !args.list_files
+        && !args.list_files_json
+        && !args.check_blocks
+        && !args.verify_blocks;
+    let restore_dir = if restores {
+        let dir = args
+            .restore_dir
+            .as_ref()
+            .ok_or_else(|| eyre!("--restore_dir <DIR> not provided"))?;
+        Some(dir.trim())
+    } else {
+        None
+    };
+    let restore_dir = match restore_dir {
+        Some(dir) if args.long_paths => {
+            Some(extended_length_path(dir).wrap_err_with(|| format!("make {:?} absolute", dir))?)
+        }
+        dir => dir.map(String::from),
+    };
+    let restore_dir = restore_dir.as_deref();
+
+    // Set CPU count
+    let core_ids = if args.pin_threads {
+        // None on platforms without affinity support, pinning is skipped then
+        core_affinity::get_core_ids().unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads_rayon)
+        .start_handler(move |index| {
+            if !core_ids.is_empty() {
+                core_affinity::set_for_current(core_ids[index % core_ids.len()]);
+            }
+        })
+        .build_global()
+        .unwrap_or_else(|err| {
+            // A library user ran a restore before
+            println!("warn: keeping the existing thread pool: {}", err);
+        });
+
+    let passphrase = args.passphrase.as_deref();
+    let mut manifests = HashMap::new();
+    let mut dlist_file_paths = match &args.dlist {
+        Some(dlist) => {
+            let dlist = PathBuf::from(dlist.trim());
+            check_dlist_file(&dlist, passphrase)?;
+            println!("Using dlist {:?}", dlist);
+            vec![dlist]
+        }
+        None => {
+            let backup_dir = backup_dir
+                .as_ref()
+                .ok_or_else(|| eyre!("--backup-dir <DIR> or --dlist <FILE> not provided"))?;
+            let mut dlist_file_paths = find_dlist_files(backup_dir, &args.dlist_extensions)?;
+            manifests = sort_dlists_by_created(&mut dlist_file_paths, passphrase);
+            dlist_file_paths
+        }
+    };
+    if let Some(Command::ListVersions) = &args.command {
+        list_versions(&dlist_file_paths, &manifests, passphrase)?;
+        return Ok(RunOutcome::Listed);
+    }
+    if let Some(version) = args.version {
+        let count = dlist_file_paths.len();
+        if version >= count {
+            return Err(eyre!(
+                "--version {} but only {} versions found",
+                version,
+                count
+            ));
+        }
+        dlist_file_paths.truncate(count - version);
+    }
+    if let Some(before) = &args.before {
+        keep_created_before(&mut dlist_file_paths, &manifests, before, passphrase)?;
+    }
+    let newest_dlist = dlist_file_paths.last().expect("at least one dlist");
+    if let Some(version) = args.version {
+        println!("Version {}: {:?}", version, newest_dlist);
+    } else if args.before.is_some() {
+        println!("Using {:?}", newest_dlist);
+    } else if args.dlist.is_none() {
+        println!(
+            "Newest: {:?} appears to be newest dlist, using it.",
+            newest_dlist
+        );
+    }
+    println!("Parsing manifest");
+    let manifest_contents = match manifests.remove(newest_dlist) {
+        Some(manifest) => manifest,
+        None => read_manifest(newest_dlist, passphrase)?,
+    };
+    if let Some(version) = args.version {
+        let created = parse_backup_time(&manifest_created(&manifest_contents)?)?;
+        println!("Restoring version {} created {}", version, created);
+    }
+
+    // Listing the dlist or checking its paths reads no dblocks
+    let dlist_only = args.validate_paths
+        || args.list_files
+        || args.list_files_json
+        || matches!(
+            args.command,
+            Some(Command::List {
+                with_blocks: false,
+                ..
+            })
+        );
+
+    // Open dblock db connection and build db
+    println!();
+    let manifest_for_db = manifest_contents.clone();
+    let db_options = DbOptions::new(&args);
+    let index_cache = args.index_cache.clone();
+    let db_join = (!dlist_only).then(|| {
+        std::thread::spawn(move || -> Result<DFileDatabase> {
+            open_dblock_db(
+                &dblock_dir,
+                &manifest_for_db,
+                &db_options,
+                index_cache.as_deref(),
+            )
+        })
+    });
+
+    println!("Parsing dlist");
+    let file_entries = parse_dlist_file(newest_dlist, passphrase)?;
+
+    let path_style = PathStyle::new(source_os(&args), args.target_os, &file_entries.entries);
+    if args.validate_paths {
+        print_path_problems(&file_entries.entries, path_style);
+        return Ok(RunOutcome::Finished);
+    }
+    if let Some(Command::List { json, .. }) = &args.command {
+        let db = db_join.map(|join| join.join().unwrap()).transpose()?;
+        list_entries(&file_entries.entries, db.as_ref(), *json)?;
+        return Ok(RunOutcome::Listed);
+    }
+    if args.list_files || args.list_files_json {
+        list_entries(&file_entries.entries, None, args.list_files_json)?;
+        return Ok(RunOutcome::Listed);
+    }
+    let db_join = db_join.expect("dblocks are indexed unless only the dlist is needed");
+    if args.verify_blocks {
+        let dblock_db = db_join.join().unwrap()?;
+        println!("Verifying every block against its hash");
+        report_verify_blocks(&verify_blocks(&dblock_db)?)?;
+        return Ok(RunOutcome::Finished);
+    }
+
+    let older_versions = if args.best_effort_latest {
+        println!("Parsing older dlists");
+        dlist_file_paths
+            .iter()
+            .rev()
+            .skip(1)
+            .map(|dlist| parse_dlist_file(dlist, passphrase))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
+
+    let file_entries = match args.since_version {
+        Some(version) => {
+            let older_dlist = dlist_file_paths.iter().rev().nth(version).ok_or_else(|| {
+                eyre!(
+                    "--since-version {} but only {} versions found",
+                    version,
+                    dlist_file_paths.len()
+                )
+            })?;
+            println!("Comparing with {:?}", older_dlist);
+            let older = parse_dlist_file(older_dlist, passphrase)?;
+            let changed = changed_files_since(file_entries, &older);
+            println!("{} files changed since that version", changed.entries.len());
+            changed
+        }
+        None => file_entries,
+    };
+    let file_entries = match PathFilter::new(&args.include, &args.exclude)? {
+        Some(filter) => {
+            let filtered = filter.apply(file_entries, path_style);
+            println!(
+                "{} entries match --include/--exclude",
+                filtered.entries.len()
+            );
+            filtered
+        }
+        None => file_entries,
+    };
+    let file_entries = match &args.only_path {
+        Some(path) => only_path(file_entries, path, path_style)?,
+        None => file_entries,
+    };
+    let (file_entries, subtree_root) = match &args.subtree {
+        Some(prefix) => {
+            let (entries, root) = subtree(file_entries, prefix, path_style)?;
+            println!(
+                "{} entries under --subtree {:?}",
+                entries.entries.len(),
+                root
+            );
+            (entries, Some(root))
+        }
+        None => (file_entries, None),
+    };
+    let strip_prefix = subtree_root.filter(|_| args.strip_prefix);
+
+    let dblock_db = db_join.join().unwrap()?;
+    if let (Some(path), Some(output)) = (&args.single_file, &args.output) {
+        extract_single_file(&args, &dblock_db, &file_entries, path, output)?;
+        return Ok(RunOutcome::Finished);
+    }
+    let mut file_entries = file_entries;
+    if args.restore_special {
+        let special_count = resolve_special_files(&mut file_entries.entries, &dblock_db);
+        if special_count > 0 {
+            println!("{} FIFOs and device nodes found", special_count);
+        }
+    }
+
+    let file_entries = if args.best_effort_latest {
+        println!("Choosing newest restorable version of each file");
+        let mut versions = vec![file_entries];
+        versions.extend(older_versions);
+        let merged = merge_best_effort_latest(versions, &dblock_db)?;
+        println!(
+            "{} files taken from older versions, {} have no complete version",
+            merged.from_older,
+            merged.unrestorable.len()
+        );
+        for path in &merged.unrestorable {
+            println!("not restorable from any version: {:?}", path);
+        }
+        merged.entries
+    } else {
+        file_entries
+    };
+    let file_entries = match &args.blocks_file {
+        Some(blocks_file) => keep_covered_files(blocks_file, &dblock_db, file_entries)?,
+        None => file_entries,
+    };
+    let file_entries = match restore_dir {
+        Some(dir) if args.exclude_larger_than_free_space => {
+            fit_to_free_space(&args, Path::new(dir), file_entries)?
+        }
+        _ => file_entries,
+    };
+    let summary =
+        calculate_summary(&file_entries.entries).with_storage(&file_entries.entries, &dblock_db);
+
+    let conflict_suffix = ConflictSuffix::parse(&args.conflict_suffix)?;
+    let mut path_renames = if args.flatten {
+        flattened_renames(&file_entries.entries, path_style, &conflict_suffix)
+    } else if args.sanitize_paths {
+        sanitized_renames(
+            &file_entries.entries,
+            path_style,
+            Fix::All,
+            &conflict_suffix,
+        )
+    } else if args.truncate_long_names {
+        sanitized_renames(
+            &file_entries.entries,
+            path_style,
+            Fix::LongNames,
+            &conflict_suffix,
+        )
+    } else {
+        check_long_names(&file_entries.entries, path_style)?;
+        HashMap::new()
+    };
+    if args.rename_on_conflict {
+        let renamed = conflict_renames(
+            &file_entries.entries,
+            path_style,
+            &mut path_renames,
+            &conflict_suffix,
+        );
+        if renamed > 0 {
+            println!("{} files renamed, their target path was taken", renamed);
+        }
+    }
+
+    if let (true, Some(dir)) = (args.flatten, restore_dir) {
+        write_flatten_map(Path::new(dir), &path_renames)?;
+    }
+
+    print_summary(&summary, args.human_readable);
+    if args.check_blocks {
+        check_blocks(&file_entries.entries, &dblock_db)?;
+        return Ok(RunOutcome::Finished);
+    }
+    if args.abort_on_missing_volume {
+        println!("Missing volumes: abort before restoring");
+        check_missing_volumes(&file_entries.entries, &dblock_db)?;
+    } else {
+        println!("Missing volumes: skip the affected files, restore the rest");
+    }
+    if let Some(Command::AssertMatches { reference_dir }) = &args.command {
+        let params = RestoreParams {
+            db: Arc::new(dblock_db),
+            restore_path: None,
+            path_style,
+            restore_mtime: false,
+            mtime_source: args.created_time_from,
+            restore_permissions: false,
+            restore_special: false,
+            restore_reparse: false,
+            prefetch: args.prefetch,
+            intra_file_parallelism: args.intra_file_parallelism,
+            per_block_hash: args.per_block_hash,
+            write_order: args.write_order,
+            validate_before_write: args.validate_before_write,
+            sidecar: None,
+            sink: None,
+            path_renames,
+            strip_prefix,
+            summary,
+            trace: None,
+            report: None,
+            progress: progress_bar(&args, &summary),
+        };
+        assert_matches_reference(&args, reference_dir, params, file_entries)?;
+        return Ok(RunOutcome::Finished);
+    }
+    let compare_entries = args.compare_remote.as_ref().map(|_| FileEntries {
+        entries: file_entries
+            .entries
+            .iter()
+            .filter(|e| e.is_file())
+            .cloned()
+            .collect(),
+    });
+
+    let trace = args
+        .trace_file
+        .as_ref()
+        .map(BlockTrace::create)
+        .transpose()?;
+    let report = args
+        .report
+        .as_ref()
+        .map(|path| RestoreReport::create(path, args.report_format))
+        .transpose()?;
+    let restore_params = RestoreParams {
+        db: Arc::new(dblock_db),
+        restore_path: restore_dir,
+        path_style,
+        restore_mtime: args.restore_mtime,
+        mtime_source: args.created_time_from,
+        restore_permissions: args.restore_permissions,
+        restore_special: args.restore_special,
+        restore_reparse: args.restore_reparse,
+        prefetch: args.prefetch,
+        intra_file_parallelism: args.intra_file_parallelism,
+        per_block_hash: args.per_block_hash,
+        write_order: args.write_order,
+        validate_before_write: args.validate_before_write,
+        sidecar: args.write_sidecar_hash.then(|| SidecarHash {
+            algorithm: args.sidecar_algorithm,
+            extension: sidecar_extension(&args),
+        }),
+        sink: restore_dir.map(|dir| -> Box<dyn BlockSink> {
+            Box::new(DirBlockSink::new(dir, create_parent_dirs(&args)))
+        }),
+        path_renames,
+        strip_prefix,
+        summary,
+        trace,
+        report,
+        progress: progress_bar(&args, &summary),
+    };
+    let outcome = match restore_all(&args, &restore_params, file_entries) {
+        Err(err) if is_interrupted(&err) => Ok(RunOutcome::Interrupted),
+        outcome => outcome,
+    };
+    if let Some(trace) = &restore_params.trace {
+        trace.flush()?;
+    }
+    // Also after a failure, the report then ends with the failed file
+    if let Some(report) = &restore_params.report {
+        report.finish()?;
+    }
+    if let Some(path) = &args.report_json {
+        let failed_files: &[FailedFile] = match &outcome {
+            Err(err) => err
+                .downcast_ref::<FailedFiles>()
+                .map_or(&[][..], |failed| &failed.0),
+            Ok(_) => &[],
+        };
+        let report = RunReport {
+            schema_version: RUN_REPORT_SCHEMA_VERSION,
+            mode: if restore_dir.is_some() {
+                "restore"
+            } else {
+                "verify"
+            },
+            dlist: newest_dlist,
+            manifest: serde_json::from_slice(&manifest_contents)?,
+            summary: restore_params.summary,
+            elapsed_secs: started.elapsed().as_secs_f64(),
+            status: match &outcome {
+                Ok(RunOutcome::NothingToRestore) => "nothing-to-restore",
+                Ok(RunOutcome::Interrupted) => "interrupted",
+                Ok(_) => "ok",
+                Err(_) => "failed",
+            },
+            error: outcome
+                .as_ref()
+                .err()
+                .map(|err| err.chain().map(|cause| cause.to_string()).collect()),
+            skipped_missing_blocks: failed_files.iter().filter(|f| f.blocks_missing).count(),
+            failed_files,
+        };
+        report.write(path)?;
+    }
+    let outcome = outcome?;
+
+    if let (Some(remote_dir), Some(restore_dir), Some(entries), RunOutcome::Restored(_)) =
+        (&args.compare_remote, restore_dir, compare_entries, &outcome)
+    {
+        compare_with_remote(
+            &args,
+            remote_dir,
+            restore_dir,
+            &manifest_contents,
+            restore_params,
+            entries,
+        )?;
+    }
+
+    Ok(outcome)
+}
+
+/// --progress-bar, its file bar counts bytes or --progress-predicted time
+fn progress_bar(args: &RestoreFlags, summary: &RestoreSummary) -> Option<Arc<dyn RestoreProgress>> {
+    args.progress_bar.then(|| -> Arc<dyn RestoreProgress> {
+        let file_total = match args.progress_predicted {
+            true => summary.predicted_bytes,
+            false => summary.total_bytes,
+        };
+        Arc::new(PbrProgress::new(
+            summary.folder_count,
+            file_total,
+            args.progress_predicted,
+        ))
+    })
+}
+
+/// Whether the restore dir sink creates missing parents, for restores that
+/// leave out folders of the backup
+fn create_parent_dirs(args: &RestoreFlags) -> bool {
+    args.only_files
+        || args.flatten
+        || args.since_version.is_some()
+        || !args.include.is_empty()
+        || !args.exclude.is_empty()
+        || args.only_path.is_some()
+}
+
+fn restore_all(
+    args: &RestoreFlags,
+    params: &RestoreParams<'_>,
+    file_entries: FileEntries,
+) -> Result<RunOutcome> {
+    if file_entries.entries.is_empty() {
+        println!("warn: no entries matched the given filters; nothing to restore");
+        println!("Check your patterns against the paths stored in the backup");
+        return Ok(RunOutcome::NothingToRestore);
+    }
+    if args.metadata_only {
+        println!("Applying metadata to existing files");
+        let counts = restore_metadata_only(&file_entries.entries, params)?;
+        println!(
+            "{} files updated, {} not on disk, {} skipped for a different size",
+            counts.applied, counts.missing, counts.size_mismatch
+        );
+        return Ok(RunOutcome::Finished);
+    }
+    let doing = if params.restore_path.is_some() {
+        "Restoring"
+    } else {
+        "Verifying"
+    };
+    if args.only_folders {
+        let implied = implied_folders(&file_entries.entries, params);
+        restore_folders(params, &file_entries.entries, &implied, doing)?;
+        return Ok(RunOutcome::Finished);
+    }
+
+    // Flattened files all go straight into the restore dir
+    let skip_folders = args.only_files || args.flatten;
+    let (folders, implied): (Vec<FileEntry>, Vec<PathBuf>) = if skip_folders {
+        (Vec::new(), Vec::new())
+    } else {
+        let folders = file_entries
+            .entries
+            .iter()
+            .filter(|f| f.is_folder())
+            .cloned()
+            .collect();
+        (folders, implied_folders(&file_entries.entries, params))
+    };
+    // A single file has nothing to sort
+    let sort = args.only_path.is_none();
+    if sort {
+        println!("Sorting file_entries");
+    }
+
+    let dbc = params.db.clone();
+    let sort_join = std::thread::spawn(move || -> FileEntries {
+        let mut file_entries = file_entries;
+        if sort {
+            sort_files_sequentially(&mut file_entries.entries, &dbc);
+        }
+        file_entries
+    });
+
+    if !skip_folders {
+        restore_folders(params, &folders, &implied, doing)?;
+    }
+
+    if !sort_join.is_finished() {
+        println!("Waiting for sorting to finish");
+    }
+    let file_entries = sort_join.join().unwrap();
+
+    println!();
+
+    let file_entries = match (args.skip_existing, params.restore_path) {
+        (Some(mode), Some(_)) => {
+            in_checksum_pool(args, || skip_existing(file_entries, params, mode))??
+        }
+        _ => file_entries,
+    };
+    restore_files(args, params, &file_entries, doing, true)?;
+
+    // Last, so link targets and their folders already exist
+    if params.restore_path.is_some() {
+        let created = restore_symlinks(&file_entries.entries, params)?;
+        if created > 0 {
+            println!("{} symlinks created", created);
+        }
+    }
+    Ok(RunOutcome::Restored(params.summary))
+}
+
+/// Creates every folder entry and the implied folders, before any file is written
+///
+/// Nested folders may be created in any order, create_dir_all makes their parents
+/// and tolerates another thread creating the same one
+fn restore_folders(
+    params: &RestoreParams<'_>,
+    entries: &[FileEntry],
+    implied: &[PathBuf],
+    doing: &str,
+) -> Result<()> {
+    println!("{doing} directory structure");
+
+    entries
+        .iter()
+        .filter(|f| f.is_folder())
+        .par_bridge()
+        .try_for_each_with(RestoreContext::new(), |ctx, entry_folder| -> Result<()> {
+            restore_entry(entry_folder, params, ctx)
+                .wrap_err_with(|| format!("restoring dir {:?}", entry_folder.path))
+        })?;
+    for path in implied {
+        fs::create_dir_all(path).wrap_err_with(|| format!("create dir {:?}", path))?;
+    }
+    if !implied.is_empty() {
+        println!(
+            "{} folders without an entry of their own created",
+            implied.len()
+        );
+    }
+    if let Some(progress) = &params.progress {
+        progress.finish();
+    }
+
+    Ok(())
+}
+
+/// Parent directories of files and links that have no folder entry of their own
+///
+/// Some backups leave a folder implicit in the paths below it. Empty without a
+/// restore dir
+fn implied_folders(entries: &[FileEntry], params: &RestoreParams<'_>) -> Vec<PathBuf> {
+    let absolute = |entry: &FileEntry| calculate_path(entry, params).map(|(path, _)| path);
+    let folders: HashSet<PathBuf> = entries
+        .iter()
+        .filter(|entry| entry.is_folder())
+        .filter_map(absolute)
+        .collect();
+    let mut implied: Vec<PathBuf> = entries
+        .iter()
+        .filter(|entry| !entry.is_folder())
+        .filter_map(absolute)
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .filter(|parent| !folders.contains(parent))
+        .collect();
+    implied.sort();
+    implied.dedup();
+    implied
+}
+
+/// skip_missing: files with blocks in missing volumes are skipped and listed as
+/// failed at the end, instead of stopping the run
+fn restore_files(
+    args: &RestoreFlags,
+    params: &RestoreParams<'_>,
+    file_entries: &FileEntries,
+    doing: &str,
+    skip_missing: bool,
+) -> Result<()> {
+    println!("{doing} files");
+    let restore_context = RestoreContext::new();
+    let skipped_missing: Mutex<Vec<FailedFile>> = Mutex::new(Vec::new());
+    let reflinked = AtomicUsize::new(0);
+    let restored = AtomicUsize::new(0);
+    let failures: Mutex<Vec<FailedFile>> = Mutex::new(Vec::new());
+    // Verifying audits the whole backup, a file that doesn't match its hash is
+    // listed at the end instead of stopping the run
+    let audit = params.restore_path.is_none() && !args.fail_fast;
+    let mismatches: Mutex<Vec<FailedFile>> = Mutex::new(Vec::new());
+    let failed_count = || {
+        failures.lock().unwrap().len()
+            + mismatches.lock().unwrap().len()
+            + skipped_missing.lock().unwrap().len()
+    };
+    let check_fail_fast = |failed: usize| match args.fail_fast_after {
+        Some(max) if failed >= max => Err(eyre!(
+            "aborted after {} failed files (--fail-fast-after), the backup looks broken",
+            failed
+        )),
+        _ => Ok(()),
+    };
+    let restore_one = |ctx: &mut RestoreContext,
+                       entry_file: &FileEntry,
+                       reflink_from: Option<&FileEntry>|
+     -> Result<()> {
+        let result = match reflink_from {
+            Some(first) if reflink_duplicate(entry_file, first, params)? => {
+                reflinked.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            _ => restore_entry(entry_file, params, ctx),
+        };
+        // Not started, neither failed nor restored
+        if result.as_ref().is_err_and(is_interrupted) {
+            return result;
+        }
+        if let Some(report) = &params.report {
+            let error = result.as_ref().err().map(|err| format!("{:#}", err));
+            report.record(ReportRow::new(entry_file, error))?;
+        }
+        // Blocks in a missing volume fail only this file, unless --abort-on-missing-volume.
+        // The run still fails at the end, listing the skipped files
+        match result {
+            Err(err)
+                if skip_missing
+                    && !missing_blocks(entry_file, &params.db, &mut Vec::new())?.is_empty() =>
+            {
+                println!("skipped, blocks missing: {:?}", entry_file.path);
+                let skipped = FailedFile::blocks_missing(&entry_file.path, &err);
+                skipped_missing.lock().unwrap().push(skipped);
+                return check_fail_fast(failed_count());
+            }
+            Err(err) if audit && is_hash_mismatch(&err) => {
+                println!("hash mismatch, continuing: {:?}", entry_file.path);
+                let mismatch = FailedFile::new(&entry_file.path, &err);
+                mismatches.lock().unwrap().push(mismatch);
+                return check_fail_fast(failed_count());
+            }
+            Err(err) if args.continue_on_error => {
+                println!("failed, continuing: {:?}", entry_file.path);
+                let failed = FailedFile::new(&entry_file.path, &err);
+                failures.lock().unwrap().push(failed);
+                return check_fail_fast(failed_count());
+            }
+            result => result.wrap_err_with(|| format!("restoring file {:?}", entry_file.path))?,
+        }
+        restored.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    };
+    // Duplicates wait until the first file with their content is restored. They're
+    // found after --skip-existing left out what's already restored, so a duplicate
+    // already on disk is never cloned over
+    let duplicates = if args.reflink_dups {
+        find_duplicates(&file_entries.entries)
+    } else {
+        Vec::new()
+    };
+    let duplicate_paths: HashSet<&str> = duplicates
+        .iter()
+        .map(|(dup, _)| dup.path.as_str())
+        .collect();
+    let to_restore = |f: &&FileEntry| {
+        (f.is_file() || f.file_type.is_special()) && !duplicate_paths.contains(f.path.as_str())
+    };
+    let pass = if let Some(workers_per_volume) = args.workers_per_volume {
+        volume_runs(&file_entries.entries, &params.db, workers_per_volume)
+            .into_par_iter()
+            .try_for_each_with(restore_context.clone(), |ctx, run| {
+                run.iter()
+                    .filter(to_restore)
+                    .try_for_each(|entry_file| restore_one(ctx, entry_file, None))
+            })
+    } else {
+        file_entries
+            .entries
+            .iter()
+            .filter(to_restore)
+            .par_bridge()
+            .try_for_each_with(restore_context.clone(), |ctx, entry_file| {
+                restore_one(ctx, entry_file, None)
+            })
+    };
+    let pass = pass.and_then(|()| {
+        duplicates
+            .par_iter()
+            .try_for_each_with(restore_context, |ctx, (dup, first)| {
+                restore_one(ctx, dup, Some(first))
+            })
+    });
+    if let Err(err) = pass {
+        if is_interrupted(&err) {
+            if let Some(progress) = &params.progress {
+                progress.finish();
+            }
+            println!();
+            println!(
+                "Interrupted, {} files finished and {} failed before Ctrl-C",
+                restored.load(Ordering::Relaxed),
+                failed_count()
+            );
+            if params.restore_path.is_some() {
+                println!("Run again with --skip-existing size to restore the rest");
+            }
+        }
+        return Err(err);
+    }
+    if let Some(progress) = &params.progress {
+        progress.finish();
+    }
+    println!();
+    let skipped_missing = skipped_missing.into_inner().unwrap();
+    if !skipped_missing.is_empty() {
+        println!(
+            "{} files skipped, their blocks are in missing dblock volumes",
+            skipped_missing.len()
+        );
+    }
+    if !duplicates.is_empty() {
+        println!(
+            "{} of {} duplicate files reflinked",
+            reflinked.into_inner(),
+            duplicates.len()
+        );
+    }
+    if let Some((hits, misses)) = params.db.block_cache_stats() {
+        println!("block cache: {} hits, {} misses", hits, misses);
+    }
+
+    let restored = restored.into_inner();
+    let mut failures = failures.into_inner().unwrap();
+    if args.continue_on_error {
+        println!("{} files restored, {} failed", restored, failures.len());
+    }
+    let mismatches = mismatches.into_inner().unwrap();
+    if audit {
+        println!(
+            "{} files match their hash, {} don't",
+            restored,
+            mismatches.len()
+        );
+    }
+    failures.extend(mismatches);
+    failures.extend(skipped_missing);
+    if !failures.is_empty() {
+        for failed in &failures {
+            println!("failed: {:?}: {}", failed.path, failed.errors.join(": "));
+        }
+        if params.restore_path.is_some() {
+            println!("Partially written files are left as <name>.partial");
+        }
+        return Err(FailedFiles(failures).into());
+    }
+    Ok(())
+}
+/// True if err comes from content that doesn't match its hash, not from a
+/// volume that can't be read
+fn is_hash_mismatch(err: &eyre::Report) -> bool {
+    err.chain()
+        .any(|cause| cause.downcast_ref::<HashMismatch>().is_some())
+}
+
+/// Keeps only files whose content blocks are all listed in blocks_file
+fn keep_covered_files(
+    blocks_file: &Path,
+    db: &DFileDatabase,
+    file_entries: FileEntries,
+) -> Result<FileEntries> {
+    let allowed = read_blocks_file(blocks_file)?;
+    println!("{} block hashes read from {:?}", allowed.len(), blocks_file);
+    let coverage = block_coverage(&file_entries.entries, db, &allowed)?;
+
+    let (mut full, mut partial, mut none) = (0, 0, 0);
+    let mut entries = Vec::new();
+    for (entry, coverage) in file_entries.entries.into_iter().zip(coverage) {
+        match coverage {
+            Coverage::Full => {
+                full += entry.is_file() as usize;
+                entries.push(entry);
+            }
+            Coverage::Partial => {
+                println!("partially covered by --blocks-file: {:?}", entry.path);
+                partial += 1;
+            }
+            Coverage::None => none += 1,
+        }
+    }
+    println!(
+        "{} files fully covered by --blocks-file, {} partially and {} not at all, restoring the first",
+        full, partial, none
+    );
+    Ok(FileEntries { entries })
+}
+
+/// Drops files that won't fit on the target, keeps a 1% reserve for folders and metadata
+fn fit_to_free_space(
+    args: &RestoreFlags,
+    restore_dir: &Path,
+    file_entries: FileEntries,
+) -> Result<FileEntries> {
+    let available = available_space_for(restore_dir)?;
+    let budget = available - available / 100;
+    let needed = calculate_summary(&file_entries.entries).total_bytes;
+    if needed <= budget {
+        return Ok(file_entries);
+    }
+    let human = args.human_readable;
+    println!(
+        "Restore needs {} but only {} are free, leaving out files",
+        ByteSize::new(needed, human),
+        ByteSize::new(available, human)
+    );
+    let triage = triage_to_free_space(file_entries.entries, budget, args.triage_order);
+    let skipped_bytes: u64 = triage.skipped.iter().map(|(_, size)| size).sum();
+    for (path, size) in &triage.skipped {
+        println!(
+            "skipped for lack of space: {:?} ({})",
+            path,
+            ByteSize::new(*size, human)
+        );
+    }
+    println!(
+        "{} files ({}) skipped for lack of space",
+        triage.skipped.len(),
+        ByteSize::new(skipped_bytes, human)
+    );
+    Ok(triage.entries)
+}
+
+/// Lets --flatten users find where each file came from, written before any file
+fn write_flatten_map(restore_dir: &Path, renames: &HashMap<String, PathBuf>) -> Result<()> {
+    let map: BTreeMap<String, &str> = renames
+        .iter()
+        .map(|(path, flat)| (flat.to_string_lossy().to_string(), path.as_str()))
+        .collect();
+    fs::create_dir_all(restore_dir)?;
+    let path = restore_dir.join(FLATTEN_MAP_NAME);
+    fs::write(&path, serde_json::to_vec_pretty(&map)?)
+        .wrap_err_with(|| format!("write {:?}", path))?;
+    println!(
+        "{} files flattened, original paths in {:?}",
+        map.len(),
+        path
+    );
+    Ok(())
+}
+
+/// Fails before restoring anything instead of with ENAMETOOLONG halfway through
+fn check_long_names(entries: &[FileEntry], path_style: PathStyle) -> Result<()> {
+    let long_names = find_long_names(entries, path_style);
+    if let Some(first) = long_names.first() {
+        return Err(eyre!(
+            "{} paths have a name longer than 255 bytes, e.g. {:?}, \
+             restore them with --truncate-long-names or --sanitize-paths",
+            long_names.len(),
+            first
+        ));
+    }
+    Ok(())
+}
+
+fn print_path_problems(entries: &[FileEntry], path_style: PathStyle) {
+    let problems = find_path_problems(entries, path_style);
+    for problem in &problems {
+        println!(
+            "{:?}: {}, would be restored as {:?} with --sanitize-paths",
+            problem.path, problem.reason, problem.suggested
+        );
+    }
+    println!(
+        "{} paths would be rejected by the target filesystem",
+        problems.len()
+    );
+}
+
+fn print_summary(summary: &RestoreSummary, human: bool) {
+    println!("{} files to be restored", summary.file_count);
+    println!("{} folders to be restored", summary.folder_count);
+    println!("{} in files", ByteSize::new(summary.total_bytes, human));
+    println!(
+        "{} on drive to be restored (predicted)",
+        ByteSize::new(summary.predicted_bytes, human)
+    );
+    if let Some(ratio) = summary.dedup_ratio() {
+        println!(
+            "{} in dblocks holding {} blocks, referred to {} times: dedup ratio {:.2}",
+            ByteSize::new(summary.physical_bytes, human),
+            summary.unique_block_count,
+            summary.referenced_block_count,
+            ratio
+        );
+    }
+}
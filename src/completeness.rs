@@ -0,0 +1,62 @@
+use crate::{
+    blockhash::BlockIdHash, database::DFileDatabase, dfileentry::FileEntry, dfiletype::FileType,
+};
+use eyre::{Context, Result};
+use rayon::prelude::*;
+
+/// Blocks of an entry that are not in any indexed dblock
+///
+/// Reads blocklist blocks (into hashes_buf) to check every content block.
+/// A missing blocklist is reported itself, its content blocks can't be known
+pub fn missing_blocks(
+    entry: &FileEntry,
+    db: &DFileDatabase,
+    hashes_buf: &mut Vec<u8>,
+) -> Result<Vec<BlockIdHash>> {
+    let FileType::File { hash, size, .. } = &entry.file_type else {
+        return Ok(Vec::new());
+    };
+    if *size == 0 || hash.is_empty_block(db.block_hash_algo()) {
+        return Ok(Vec::new());
+    }
+
+    let mut missing = Vec::new();
+    if entry.block_lists.is_empty() {
+        if db.get_block_id_location(hash).is_none() {
+            missing.push(hash.clone());
+        }
+        return Ok(missing);
+    }
+    for blocklist in &entry.block_lists {
+        hashes_buf.clear();
+        let found = db
+            .get_content_block(blocklist, hashes_buf)
+            .wrap_err_with(|| format!("read blocklist {}", blocklist))?;
+        if found.is_none() {
+            missing.push(blocklist.clone());
+            continue;
+        }
+        for bhash in hashes_buf.chunks(db.hash_size()) {
+            let bhash = db.blocklist_hash(bhash)?;
+            if db.get_block_id_location(&bhash).is_none() {
+                missing.push(bhash);
+            }
+        }
+    }
+    Ok(missing)
+}
+
+/// Paths of files that miss blocks, with the blocks missing
+pub fn incomplete_files(
+    entries: &[FileEntry],
+    db: &DFileDatabase,
+) -> Result<Vec<(String, Vec<BlockIdHash>)>> {
+    let incomplete: Vec<Option<(String, Vec<BlockIdHash>)>> = entries
+        .par_iter()
+        .map_init(Vec::new, |hashes_buf, entry| -> Result<_> {
+            let missing = missing_blocks(entry, db, hashes_buf)?;
+            Ok((!missing.is_empty()).then(|| (entry.path.clone(), missing)))
+        })
+        .collect::<Result<_>>()?;
+    Ok(incomplete.into_iter().flatten().collect())
+}
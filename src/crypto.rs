@@ -0,0 +1,272 @@
+use crate::backend;
+use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, KeyIvInit};
+use eyre::{eyre, Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+/// A backup file as read by zip, decrypted or not
+pub trait ReadSeek: Read + Seek + Send + Sync {}
+impl<T: Read + Seek + Send + Sync> ReadSeek for T {}
+
+/// SharpAESCrypt (AES Crypt format) files start with this, Duplicati's default encryption
+pub const AESCRYPT_MAGIC: &[u8] = b"AES";
+const AES_BLOCK: u64 = 16;
+/// SHA-256 rounds turning the passphrase into the key that wraps the file key
+const KEY_ROUNDS: usize = 8192;
+/// Ciphertext decrypted per refill of AesCryptReader
+const DECRYPT_CHUNK: u64 = 64 * 1024;
+/// Modulo byte and HMAC-SHA256 after the ciphertext
+const TRAILER_LEN: u64 = 1 + 32;
+
+/// File key and layout from the header of an AES Crypt v1/v2 file
+///
+/// Deriving it takes KEY_ROUNDS hashes, so it's read once per file and shared by all readers
+pub struct AesCryptKeys {
+    key: [u8; 32],
+    iv: [u8; 16],
+    /// Offset of the first ciphertext byte
+    data_start: u64,
+    cipher_len: u64,
+    plain_len: u64,
+}
+
+impl AesCryptKeys {
+    /// Parses the header and unwraps the file key, errors on a wrong passphrase
+    pub fn read(reader: &mut impl ReadSeek, passphrase: &str) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut head = [0u8; 5];
+        reader.read_exact(&mut head).wrap_err("read AES header")?;
+        if &head[..3] != AESCRYPT_MAGIC {
+            return Err(eyre!("not an AES Crypt file"));
+        }
+        let version = head[3];
+        if version != 1 && version != 2 {
+            return Err(eyre!("AES Crypt version {} is not supported", version));
+        }
+        if version == 2 {
+            // Extensions (e.g. CREATED_BY), each with a length, an empty one ends them
+            loop {
+                let mut len = [0u8; 2];
+                reader.read_exact(&mut len).wrap_err("read AES extension")?;
+                match u16::from_be_bytes(len) {
+                    0 => break,
+                    len => reader.seek(SeekFrom::Current(len as i64))?,
+                };
+            }
+        }
+
+        let mut iv1 = [0u8; 16];
+        let mut wrapped = [0u8; 48];
+        let mut wrapped_hmac = [0u8; 32];
+        reader.read_exact(&mut iv1)?;
+        reader.read_exact(&mut wrapped)?;
+        reader.read_exact(&mut wrapped_hmac)?;
+        let data_start = reader.stream_position()?;
+
+        let key1 = derive_key(&iv1, passphrase);
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key1).expect("HMAC takes any key length");
+        mac.update(&wrapped);
+        mac.verify_slice(&wrapped_hmac)
+            .map_err(|_| eyre!("wrong passphrase, or the AES header is corrupt"))?;
+        Aes256CbcDec::new(&key1.into(), &iv1.into())
+            .decrypt_padded_mut::<NoPadding>(&mut wrapped)
+            .map_err(|_| eyre!("unwrap AES file key"))?;
+        let mut iv = [0u8; 16];
+        let mut key = [0u8; 32];
+        iv.copy_from_slice(&wrapped[..16]);
+        key.copy_from_slice(&wrapped[16..]);
+
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        let cipher_len = file_len
+            .checked_sub(data_start + TRAILER_LEN)
+            .filter(|len| len % AES_BLOCK == 0)
+            .ok_or_else(|| eyre!("AES file is truncated"))?;
+        reader.seek(SeekFrom::Start(data_start + cipher_len))?;
+        let mut last_block_len = [0u8; 1];
+        reader.read_exact(&mut last_block_len)?;
+        // 0 means the last block is full
+        let plain_len = match (cipher_len, last_block_len[0] as u64 % AES_BLOCK) {
+            (0, _) => 0,
+            (_, 0) => cipher_len,
+            (_, last) => cipher_len - AES_BLOCK + last,
+        };
+
+        Ok(Self {
+            key,
+            iv,
+            data_start,
+            cipher_len,
+            plain_len,
+        })
+    }
+}
+
+/// SHA-256 of the IV and the UTF-16LE passphrase, repeated KEY_ROUNDS times
+fn derive_key(iv: &[u8; 16], passphrase: &str) -> [u8; 32] {
+    let passphrase: Vec<u8> = passphrase
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let mut digest = [0u8; 32];
+    digest[..16].copy_from_slice(iv);
+    for _ in 0..KEY_ROUNDS {
+        let mut hasher = Sha256::new();
+        hasher.update(digest);
+        hasher.update(&passphrase);
+        digest = hasher.finalize().into();
+    }
+    digest
+}
+
+/// Decrypts an AES Crypt file with random access, so zip can seek to the central directory
+///
+/// CBC needs only the previous ciphertext block to decrypt a block, a seek costs no
+/// extra reads. The HMAC over the whole ciphertext is not checked, that would take
+/// a full read; Duplicati's block and file hashes catch damaged content instead
+pub struct AesCryptReader<R> {
+    inner: R,
+    keys: Arc<AesCryptKeys>,
+    /// Position in the plaintext
+    pos: u64,
+    /// Decrypted plaintext starting at buf_start
+    buf: Vec<u8>,
+    buf_start: u64,
+}
+
+impl<R: Read + Seek> AesCryptReader<R> {
+    pub fn new(inner: R, keys: Arc<AesCryptKeys>) -> Self {
+        Self {
+            inner,
+            keys,
+            pos: 0,
+            buf: Vec::new(),
+            buf_start: 0,
+        }
+    }
+
+    /// Decrypts the chunk holding pos
+    fn refill(&mut self) -> io::Result<()> {
+        let keys = &self.keys;
+        let block_start = self.pos / AES_BLOCK * AES_BLOCK;
+        let len = (keys.cipher_len - block_start).min(DECRYPT_CHUNK);
+        let mut iv = keys.iv;
+        if block_start == 0 {
+            self.inner.seek(SeekFrom::Start(keys.data_start))?;
+        } else {
+            // The previous ciphertext block is the IV of this one
+            self.inner
+                .seek(SeekFrom::Start(keys.data_start + block_start - AES_BLOCK))?;
+            self.inner.read_exact(&mut iv)?;
+        }
+        self.buf.resize(len as usize, 0);
+        self.inner.read_exact(&mut self.buf)?;
+        Aes256CbcDec::new(&keys.key.into(), &iv.into())
+            .decrypt_padded_mut::<NoPadding>(&mut self.buf)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "AES decrypt"))?;
+        self.buf
+            .truncate((keys.plain_len - block_start).min(len) as usize);
+        self.buf_start = block_start;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for AesCryptReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.keys.plain_len || out.is_empty() {
+            return Ok(0);
+        }
+        let buffered = self.buf_start..self.buf_start + self.buf.len() as u64;
+        if !buffered.contains(&self.pos) {
+            self.refill()?;
+        }
+        let from = (self.pos - self.buf_start) as usize;
+        let n = out.len().min(self.buf.len() - from);
+        out[..n].copy_from_slice(&self.buf[from..from + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for AesCryptReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.keys.plain_len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = new_pos
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.pos)
+    }
+}
+
+/// Decrypts file if it starts with AESCRYPT_MAGIC and a passphrase is given
+///
+/// keys caches the file key for later opens of the same file. Without a passphrase
+/// an encrypted file is returned as is, check_not_encrypted then explains
+pub fn decrypt_maybe(
+    mut file: impl ReadSeek + 'static,
+    path: &Path,
+    passphrase: Option<&str>,
+    keys: &OnceLock<Arc<AesCryptKeys>>,
+) -> Result<Box<dyn ReadSeek>> {
+    let Some(passphrase) = passphrase else {
+        return Ok(Box::new(file));
+    };
+    let keys = match keys.get() {
+        Some(keys) => keys.clone(),
+        None => {
+            let mut magic = [0u8; 3];
+            let n = file.read(&mut magic)?;
+            file.rewind()?;
+            if magic[..n] != *AESCRYPT_MAGIC {
+                return Ok(Box::new(file));
+            }
+            let read = AesCryptKeys::read(&mut file, passphrase)
+                .wrap_err_with(|| format!("decrypt {:?}", path))?;
+            keys.get_or_init(|| Arc::new(read)).clone()
+        }
+    };
+    Ok(Box::new(AesCryptReader::new(file, keys)))
+}
+
+/// The passphrase from at most one of --passphrase, --passphrase-env and --passphrase-file
+///
+/// A file may end with a newline (`echo secret > file`), it's not part of the passphrase.
+/// Other whitespace is kept
+pub fn resolve_passphrase(
+    literal: Option<&str>,
+    env: Option<&str>,
+    file: Option<&Path>,
+) -> Result<Option<String>> {
+    match (literal, env, file) {
+        (None, None, None) => Ok(None),
+        (Some(literal), None, None) => Ok(Some(literal.to_string())),
+        (None, Some(var), None) => std::env::var(var)
+            .map(Some)
+            .wrap_err_with(|| format!("--passphrase-env: read environment variable {}", var)),
+        (None, None, Some(path)) => {
+            let content = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("--passphrase-file: read {:?}", path))?;
+            let passphrase = content.strip_suffix('\n').unwrap_or(&content);
+            let passphrase = passphrase.strip_suffix('\r').unwrap_or(passphrase);
+            Ok(Some(passphrase.to_string()))
+        }
+        _ => Err(eyre!(
+            "more than one of --passphrase, --passphrase-env and --passphrase-file given, pick one"
+        )),
+    }
+}
+
+/// Opens a dlist or dblock, decrypting it with passphrase if it is encrypted
+pub fn open_backup_file(path: &Path, passphrase: Option<&str>) -> Result<Box<dyn ReadSeek>> {
+    let file = backend::open(path).wrap_err_with(|| format!("open {:?}", path))?;
+    decrypt_maybe(file, path, passphrase, &OnceLock::new())
+}
@@ -1,7 +1,16 @@
+use crate::backend;
+use crate::blockarchive::{open_block_archive, unsupported_compression, BlockArchive, BlockEntry};
+use crate::blockcache::BlockCache;
 use crate::blockhash::BlockIdHash;
+use crate::blockprovider::BlockProvider;
+use crate::dindex::read_dindex;
+use crate::flags::ZipReader;
+use crate::hashalgo::HashAlgo;
+use crate::indexcache::IndexCache;
+use crate::sevenzarchive::SevenZFolderCache;
 use crate::ziparchive::BlockLocation;
 use crate::ziparchive::MyCloneFileConfig;
-use crate::ziparchive::MyCloneFileReader;
+use crate::ziparchive::OpenArchiveLimit;
 use crate::ziparchive::ZipArchiveWrapper;
 use crate::ziparchive::ZipLocation;
 use base64::engine::general_purpose;
@@ -15,13 +24,17 @@ use rayon::prelude::ParallelIterator;
 use serde::Deserialize;
 use smallvec::SmallVec;
 use std::collections::HashMap;
-use std::io::Read;
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Mutex;
-use zip::ZipArchive;
+use std::sync::OnceLock;
 
 #[derive(Deserialize)]
 #[allow(dead_code)] // Will use all these fields in the future
@@ -42,16 +55,53 @@ struct Manifest {
     pub(self) app_version: String,
 }
 
+/// Created field of a dlist or dblock manifest, e.g. "20230102T030405Z"
+pub fn manifest_created(manifest_bytes: &[u8]) -> Result<String> {
+    let manifest: Manifest = serde_json::from_slice(manifest_bytes)?;
+    Ok(manifest.created)
+}
+
+/// AppVersion field of a manifest, the Duplicati version that wrote the volume
+pub fn manifest_app_version(manifest_bytes: &[u8]) -> Result<String> {
+    let manifest: Manifest = serde_json::from_slice(manifest_bytes)?;
+    Ok(manifest.app_version)
+}
+
 pub struct HashToPath {
     /// Maps hash (without base64) to location in dblock.zip
     ///
     /// May be faster, but it's memory-intensive
     hash2path: HashMap<SmallVec<[u8; 32]>, BlockLocation>,
+    diagnostics: IndexDiagnostics,
+}
+
+/// Oddities seen while indexing, a healthy backup has none
+#[derive(Clone, Debug, Default)]
+pub struct IndexDiagnostics {
+    /// Block names indexed again, only one of their locations is kept
+    pub duplicate_blocks: usize,
+    /// Entry names that aren't a base64 hash of hash_size() bytes, left out of the index
+    pub bad_names: usize,
+    /// One of them, dblocks are indexed in parallel
+    pub first_bad_name: Option<String>,
+}
+
+impl IndexDiagnostics {
+    pub fn is_healthy(&self) -> bool {
+        self.duplicate_blocks == 0 && self.bad_names == 0
+    }
+}
+impl Default for HashToPath {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl HashToPath {
     pub fn new() -> Self {
         Self {
             hash2path: HashMap::new(),
+            diagnostics: IndexDiagnostics::default(),
         }
     }
 
@@ -71,18 +121,28 @@ impl HashToPath {
         ziplocation: &Arc<ZipLocation>,
         entry_index: usize,
     ) {
-        self.hash2path.insert(
-            hash.into(),
+        let replaced = self.hash2path.insert(
+            hash,
             BlockLocation {
                 ziplocation: ziplocation.clone(),
                 file_index: entry_index as u32,
             },
         );
+        if replaced.is_some() {
+            self.diagnostics.duplicate_blocks += 1;
+        }
+    }
+
+    fn reject_name(&mut self, name: &str) {
+        self.diagnostics.bad_names += 1;
+        if self.diagnostics.first_bad_name.is_none() {
+            self.diagnostics.first_bad_name = Some(name.to_string());
+        }
     }
 }
 pub struct HashToBlocks {
     /// Maps zip file name to a singleton zip reader
-    zip2ziparchive: HashMap<String, ZipArchiveWrapper>,
+    zip2ziparchive: HashMap<String, Arc<ZipArchiveWrapper>>,
 
     /// zip_entry_name -> zip_name
     ///
@@ -124,16 +184,11 @@ impl HashToBlocks {
         None
     }
 
-    pub fn get_zip_archive(&self, zip_filename: &str) -> Option<ZipArchive<MyCloneFileReader>> {
-        let zip = self.zip2ziparchive.get(zip_filename);
-
-        zip.map(|zip| zip.archive.clone())
+    pub fn get_zip_archive(&self, zip_filename: &str) -> Option<Arc<ZipArchiveWrapper>> {
+        self.zip2ziparchive.get(zip_filename).cloned()
     }
 
-    pub fn get_zip_by_block_id(
-        &self,
-        block_id: &BlockIdHash,
-    ) -> Option<ZipArchive<MyCloneFileReader>> {
+    pub fn get_zip_by_block_id(&self, block_id: &BlockIdHash) -> Option<Arc<ZipArchiveWrapper>> {
         if let Some(hash2path) = &self.hash2path {
             let zname = hash2path.get_zip_path_by_block_id(block_id);
             let zname = zname.map(|n| n.to_string_lossy().to_string());
@@ -146,12 +201,12 @@ impl HashToBlocks {
     pub fn get_zip_by_block_id_purezip(
         &self,
         block_id: &BlockIdHash,
-    ) -> Option<ZipArchive<MyCloneFileReader>> {
+    ) -> Option<Arc<ZipArchiveWrapper>> {
         let buf = &mut [0u8; 48];
         let name_reencoded = block_id.as_base64_urlsafe(buf);
         for ziparch in self.zip2ziparchive.values() {
             if ziparch.contains_file_name(name_reencoded) {
-                return Some(ziparch.archive.clone());
+                return Some(ziparch.clone());
             }
         }
         None
@@ -161,18 +216,173 @@ impl HashToBlocks {
 pub struct DFileDatabase {
     inner: Arc<Mutex<HashToBlocks>>,
     manifest: Manifest,
+    /// From manifest.block_hash, names blocks and sizes the hashes in blocklists
+    block_hash: HashAlgo,
+    /// From manifest.file_hash, the hash of whole files in the dlist
+    file_hash: HashAlgo,
+    /// Grows block buffers by this much per read, None to let read_to_end decide
+    block_read_chunk: Option<usize>,
+    /// Bounds dblock files open at once, None for no bound
+    open_limit: Option<Arc<OpenArchiveLimit>>,
+    /// Dblocks indexed by an earlier run, new ones get appended
+    index_cache: Option<IndexCache>,
+    /// Replaces the dblocks as the source of block contents
+    provider: Option<Box<dyn BlockProvider>>,
+    /// Dblocks that can't be indexed are left out instead of failing
+    skip_bad_volumes: bool,
+    /// Decrypts AES-encrypted dblocks
+    passphrase: Option<String>,
+    /// Blocks read before, get_content_block looks here first
+    block_cache: Option<BlockCache>,
+    reader: ZipReader,
+    /// Decoded folders of 7z dblocks, two per thread so neighbours don't evict each other
+    sevenz_folders: Arc<SevenZFolderCache>,
+    /// Size of the dblocks indexed so far, as stored
+    stored_bytes: AtomicU64,
+    /// Blocks in the dblocks indexed so far, unique as they're named by their hash
+    stored_blocks: AtomicUsize,
 }
 
 impl DFileDatabase {
     pub fn new(manifest_bytes: &[u8], use_hash_to_path: bool) -> Result<Self> {
         let manifest: Manifest = serde_json::from_slice(manifest_bytes)?;
+        let algo = |name: &str| {
+            HashAlgo::from_manifest(name).ok_or_else(|| {
+                eyre!(
+                    "hash {:?} in the manifest is not supported, only SHA256, SHA1 and MD5",
+                    name
+                )
+            })
+        };
+        let block_hash = algo(&manifest.block_hash)?;
+        let file_hash = algo(&manifest.file_hash)?;
 
         let inner = Arc::new(Mutex::new(HashToBlocks::new(use_hash_to_path)));
-        let db = Self { inner, manifest };
+        let db = Self {
+            inner,
+            manifest,
+            block_hash,
+            file_hash,
+            block_read_chunk: None,
+            open_limit: None,
+            index_cache: None,
+            provider: None,
+            skip_bad_volumes: false,
+            passphrase: None,
+            block_cache: None,
+            reader: ZipReader::Buffered,
+            sevenz_folders: SevenZFolderCache::new(2 * rayon::current_num_threads()),
+            stored_bytes: AtomicU64::new(0),
+            stored_blocks: AtomicUsize::new(0),
+        };
         Ok(db)
     }
 
-    pub fn create_block_id_to_filenames(&self, paths: &[PathBuf]) -> Result<()> {
+    pub fn with_block_read_chunk(mut self, block_read_chunk: Option<usize>) -> Self {
+        self.block_read_chunk = block_read_chunk.filter(|&chunk| chunk > 0);
+        self
+    }
+
+    /// Readers wait instead of failing with "too many open files"
+    pub fn with_max_open_archives(mut self, max_open_archives: Option<usize>) -> Self {
+        self.open_limit = max_open_archives.map(OpenArchiveLimit::new);
+        self
+    }
+
+    /// Encrypted dblocks are decrypted while reading, plain ones are read as they are
+    pub fn with_passphrase(mut self, passphrase: Option<String>) -> Self {
+        self.passphrase = passphrase;
+        self
+    }
+
+    /// Mmap shares one map per dblock between threads instead of opening it per thread
+    pub fn with_reader(mut self, reader: ZipReader) -> Self {
+        self.reader = reader;
+        self
+    }
+
+    /// Keeps up to budget_bytes of decompressed blocks, None or 0 for no cache
+    pub fn with_block_cache(mut self, budget_bytes: Option<usize>) -> Self {
+        self.block_cache = budget_bytes.filter(|&bytes| bytes > 0).map(BlockCache::new);
+        self
+    }
+
+    /// (hits, misses) of the block cache, None without one
+    pub fn block_cache_stats(&self) -> Option<(usize, usize)> {
+        self.block_cache.as_ref().map(BlockCache::stats)
+    }
+
+    /// Their blocks are then missing, files needing them are reported per file
+    pub fn with_skip_bad_volumes(mut self, skip_bad_volumes: bool) -> Self {
+        self.skip_bad_volumes = skip_bad_volumes;
+        self
+    }
+
+    /// Needs hash_to_path, volumes loaded from the cache aren't opened to look up blocks
+    pub fn with_index_cache(mut self, index_cache: Option<IndexCache>) -> Self {
+        self.index_cache = index_cache;
+        self
+    }
+
+    /// get_content_block asks provider instead of the dblocks
+    ///
+    /// Block locations still come from the indexed dblocks only, so features that
+    /// look blocks up without reading them (sorting, completeness checks) don't see
+    /// the provider's blocks
+    pub fn with_block_provider(mut self, provider: Box<dyn BlockProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Registers the dblocks listed in dindex files without opening them
+    ///
+    /// A dblock is taken from a dindex only if it's next to the dindex with the
+    /// size the dindex recorded. Unreadable dindex files are warned about and left out.
+    /// Returns the dblocks registered, the rest have to be scanned
+    pub fn create_index_from_dindex(&self, dindex_paths: &[PathBuf]) -> Result<HashSet<PathBuf>> {
+        let registered = Mutex::new(HashSet::new());
+        let blocks = AtomicUsize::new(0);
+        dindex_paths
+            .par_iter()
+            .try_for_each(|dindex_path| -> Result<()> {
+                let volumes = match read_dindex(dindex_path, self.passphrase.as_deref()) {
+                    Ok(volumes) => volumes,
+                    Err(err) => {
+                        println!("warn: skipped dindex, its dblocks get scanned: {:#}", err);
+                        return Ok(());
+                    }
+                };
+                let dir = dindex_path.parent().unwrap_or(Path::new(""));
+                for volume in volumes {
+                    let zip_path = dir.join(&volume.name);
+                    let size = backend::stat(&zip_path).map(|stat| stat.len).ok();
+                    if size.is_none() || volume.volume_size.is_some_and(|len| Some(len) != size) {
+                        continue;
+                    }
+                    // Duplicati may write several dindex files for one dblock
+                    if !registered.lock().unwrap().insert(zip_path.clone()) {
+                        continue;
+                    }
+                    self.import_from_cache(&zip_path, &volume.block_names)
+                        .wrap_err_with(|| {
+                            format!("import {:?} from {:?}", zip_path, dindex_path)
+                        })?;
+                    blocks.fetch_add(volume.block_names.len(), Ordering::Relaxed);
+                }
+                Ok(())
+            })?;
+        let registered = registered.into_inner().unwrap();
+        println!(
+            "{} blocks in {} dblocks resolved from {} dindex files",
+            blocks.into_inner(),
+            registered.len(),
+            dindex_paths.len()
+        );
+        Ok(registered)
+    }
+
+    /// Indexes paths, returns the number of blocks found by opening dblocks
+    pub fn create_block_id_to_filenames(&self, paths: &[PathBuf]) -> Result<usize> {
         // Iterate through dblocks, adding them to the db
         let pb = ProgressBar::new(paths.len() as u64);
         pb.set_style(
@@ -182,78 +392,185 @@ impl DFileDatabase {
                 )?
                 .progress_chars("##-"),
         );
+        let from_cache = AtomicUsize::new(0);
+        let scanned_blocks = AtomicUsize::new(0);
+        let skipped = Mutex::new(Vec::new());
         paths.par_iter().try_for_each(|zip_path| -> Result<()> {
-            self.import_from_zip(zip_path)
-                .wrap_err_with(|| format!("import_from_zip: {:?}", zip_path))?;
+            let cached = self.index_cache.as_ref().and_then(|c| c.names(zip_path));
+            if let Some(names) = cached {
+                self.import_from_cache(zip_path, names)
+                    .wrap_err_with(|| format!("import_from_cache: {:?}", zip_path))?;
+                from_cache.fetch_add(1, Ordering::Relaxed);
+            } else {
+                let imported = self
+                    .import_from_zip(zip_path)
+                    .wrap_err_with(|| format!("import_from_zip: {:?}", zip_path));
+                match imported {
+                    Err(err) if self.skip_bad_volumes => skipped.lock().unwrap().push(err),
+                    imported => {
+                        scanned_blocks.fetch_add(imported?, Ordering::Relaxed);
+                    }
+                }
+            }
             pb.inc(1);
 
             Ok(())
         })?;
+        let skipped = skipped.into_inner().unwrap();
+        for err in &skipped {
+            println!("warn: skipped dblock: {:#}", err);
+        }
+        if !skipped.is_empty() {
+            println!(
+                "{} dblocks skipped, files with blocks in them are reported missing",
+                skipped.len()
+            );
+        }
+        if self.index_cache.is_some() {
+            println!(
+                "{} dblocks loaded from the index cache, {} indexed",
+                from_cache.load(Ordering::Relaxed),
+                paths.len() - from_cache.load(Ordering::Relaxed)
+            );
+        }
 
-        Ok(())
+        Ok(scanned_blocks.into_inner())
     }
 
-    pub fn import_from_zip(&self, zip_path: &PathBuf) -> Result<()> {
+    /// Opens a dblock, zip or 7z, and registers its entries, returns how many there are
+    pub fn import_from_zip(&self, zip_path: &PathBuf) -> Result<usize> {
         // In this stage, open the file
         let zip_path = Path::new(&zip_path).to_path_buf();
         let config = Arc::new(MyCloneFileConfig {
             path: zip_path.clone(),
             buf_capacity: AtomicU32::new(1024),
+            open_limit: self.open_limit.clone(),
+            passphrase: self.passphrase.clone(),
+            aes_keys: OnceLock::new(),
+            reader: self.reader,
+            mmap: OnceLock::new(),
+            sevenz_folders: self.sevenz_folders.clone(),
         });
-        let zipbuf = MyCloneFileReader::new(config.clone())?;
-        let ziparch = zip::ZipArchive::new(zipbuf)?;
+        let ziparch = open_block_archive(config.clone())?;
 
-        let arc_ziploc = Arc::new(ZipLocation { path: zip_path });
+        let arc_ziploc = Arc::new(ZipLocation {
+            path: zip_path.clone(),
+        });
 
         if self.inner.lock().unwrap().hash2path.is_some() {
-            self.register_hash_to_path(&ziparch, arc_ziploc.clone())?;
+            self.register_hash_to_path(ziparch.file_names_ordered(), arc_ziploc.clone())?;
+        }
+        if let Some(index_cache) = &self.index_cache {
+            let names = ziparch.file_names_ordered().map(String::from).collect();
+            index_cache.append(&zip_path, names)?;
         }
 
+        let entries = ziparch
+            .file_names_ordered()
+            .filter(|name| *name != "manifest")
+            .count();
+        self.count_stored(&zip_path, entries);
         self.register_zip_archive(config, arc_ziploc, ziparch);
 
+        Ok(entries)
+    }
+
+    /// Registers a dblock by its entry names (from the index cache or a dindex)
+    /// without opening it
+    pub fn import_from_cache(&self, zip_path: &Path, names: &[String]) -> Result<()> {
+        let config = Arc::new(MyCloneFileConfig {
+            path: zip_path.to_path_buf(),
+            buf_capacity: AtomicU32::new(32 * 1024),
+            open_limit: self.open_limit.clone(),
+            passphrase: self.passphrase.clone(),
+            aes_keys: OnceLock::new(),
+            reader: self.reader,
+            mmap: OnceLock::new(),
+            sevenz_folders: self.sevenz_folders.clone(),
+        });
+        let arc_ziploc = Arc::new(ZipLocation {
+            path: zip_path.to_path_buf(),
+        });
+        self.register_hash_to_path(names.iter().map(String::as_str), arc_ziploc.clone())?;
+        let blocks = names.iter().filter(|name| *name != "manifest").count();
+        self.count_stored(zip_path, blocks);
+
+        let path_str = zip_path.to_string_lossy().to_string();
+        let wrapper = ZipArchiveWrapper::unopened(arc_ziploc, config);
+        let mut inner = self.inner.lock().unwrap();
+        inner.zip2ziparchive.insert(path_str, Arc::new(wrapper));
         Ok(())
     }
+    fn count_stored(&self, zip_path: &Path, blocks: usize) {
+        let len = backend::stat(zip_path).map_or(0, |stat| stat.len);
+        self.stored_bytes.fetch_add(len, Ordering::Relaxed);
+        self.stored_blocks.fetch_add(blocks, Ordering::Relaxed);
+    }
+
+    /// Bytes and blocks in the dblocks indexed, of every version in the backup
+    pub fn stored_totals(&self) -> (u64, usize) {
+        (
+            self.stored_bytes.load(Ordering::Relaxed),
+            self.stored_blocks.load(Ordering::Relaxed),
+        )
+    }
+
     /// Remembers zip file names in a hashmap
     ///
-    /// zip_entry_name -> zip_name
-    pub fn register_hash_to_path(
+    /// zip_entry_name -> zip_name. Names that aren't block hashes are counted
+    /// in index_diagnostics and left out
+    pub fn register_hash_to_path<'a>(
         &self,
-        ziparch: &ZipArchive<MyCloneFileReader>,
+        file_names: impl Iterator<Item = &'a str>,
         ziplocation: Arc<ZipLocation>,
     ) -> Result<()> {
-        for (index, file_name) in ziparch.file_names_ordered().enumerate() {
-            // file_name is a hash in base64
-            let hash = general_purpose::URL_SAFE.decode(file_name)?;
-
-            if hash.len() > 32 {
-                Err(eyre!("warn: hash len:{} requires heap alloc", hash.len()))?
+        let hash_size = self.hash_size();
+        for (index, file_name) in file_names.enumerate() {
+            if file_name == "manifest" {
+                continue;
             }
+            // file_name is a hash in base64
+            let hash = general_purpose::URL_SAFE
+                .decode(file_name)
+                .ok()
+                .filter(|hash| hash.len() == hash_size);
 
             let mut inner = self.inner.lock().unwrap();
             if let Some(hash2path) = &mut inner.hash2path {
-                hash2path.insert_location(hash.into(), &ziplocation, index);
+                match hash {
+                    Some(hash) => hash2path.insert_location(hash.into(), &ziplocation, index),
+                    None => hash2path.reject_name(file_name),
+                }
             }
         }
         Ok(())
     }
 
+    /// Duplicate and malformed block names seen so far, None without hash_to_path
+    /// (the names are then never collected)
+    pub fn index_diagnostics(&self) -> Option<IndexDiagnostics> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .hash2path
+            .as_ref()
+            .map(|hash2path| hash2path.diagnostics.clone())
+    }
+
     pub fn register_zip_archive(
         &self,
         config: Arc<MyCloneFileConfig>,
         ziplocation: Arc<ZipLocation>,
-        ziparch: ZipArchive<MyCloneFileReader>,
+        ziparch: Box<dyn BlockArchive>,
     ) {
-        use std::sync::atomic::Ordering;
         config.buf_capacity.store(32 * 1024, Ordering::Relaxed);
         let path_str = ziplocation.path.to_string_lossy().to_string();
-        let wrapper = ZipArchiveWrapper {
-            ziplocation,
-            archive: ziparch,
-        };
+        // The clone opens its file on first read, so cached archives
+        // don't keep a file descriptor each
+        let wrapper = ZipArchiveWrapper::new(ziplocation, config, ziparch.clone_archive());
 
         {
             let mut inner = self.inner.lock().unwrap();
-            inner.zip2ziparchive.insert(path_str, wrapper);
+            inner.zip2ziparchive.insert(path_str, Arc::new(wrapper));
         }
     }
 
@@ -264,11 +581,13 @@ impl DFileDatabase {
             .get_location_by_block_id(block_id)
     }
 
+    /// Opens the archive outside the lock if it came from the index cache
     pub fn get_zip_by_block_id(
         &self,
         block_id: &BlockIdHash,
-    ) -> Option<ZipArchive<MyCloneFileReader>> {
-        self.inner.lock().unwrap().get_zip_by_block_id(block_id)
+    ) -> Result<Option<Box<dyn BlockArchive>>> {
+        let wrapper = self.inner.lock().unwrap().get_zip_by_block_id(block_id);
+        wrapper.map(|wrapper| wrapper.archive()).transpose()
     }
 
     pub fn get_content_block(
@@ -276,35 +595,242 @@ impl DFileDatabase {
         block_id: &BlockIdHash,
         block_buf: &mut Vec<u8>,
     ) -> Result<Option<usize>> {
-        let ziparch = self.get_zip_by_block_id(block_id);
+        let Some(cache) = &self.block_cache else {
+            return self.read_content_block(block_id, block_buf);
+        };
+        if let Some(block) = cache.get(block_id) {
+            block_buf.extend_from_slice(&block);
+            return Ok(Some(block.len()));
+        }
+        let start = block_buf.len();
+        let n = self.read_content_block(block_id, block_buf)?;
+        if n.is_some() {
+            cache.insert(block_id, &block_buf[start..]);
+        }
+        Ok(n)
+    }
+
+    /// Appends the block to block_buf, None if no dblock has it
+    fn read_content_block(
+        &self,
+        block_id: &BlockIdHash,
+        block_buf: &mut Vec<u8>,
+    ) -> Result<Option<usize>> {
+        if let Some(provider) = &self.provider {
+            return provider.get_block(block_id, block_buf);
+        }
+        let ziparch = self.get_zip_by_block_id(block_id)?;
 
         if let Some(mut ziparch) = ziparch {
-            let base64_buf = &mut [0u8; 48];
-            let name_reencoded = block_id.as_base64_urlsafe(base64_buf);
-            let mut block = ziparch
-                .by_name(name_reencoded)
+            let n = self
+                .read_block_entry(ziparch.as_mut(), block_id, block_buf)
                 .wrap_err("block file by name not found even though we indexed it before")?;
-            let n = block
-                .read_to_end(block_buf)
-                .wrap_err_with(|| format!("reading block file {:?}", block_id))?;
-
             Ok(Some(n))
         } else {
             Ok(None)
         }
     }
 
+    /// Appends the entry of ziparch named after block_id to block_buf, returns its length
+    pub fn read_block_entry(
+        &self,
+        ziparch: &mut dyn BlockArchive,
+        block_id: &BlockIdHash,
+        block_buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        self.with_block_entry(ziparch, block_id, |block| {
+            let size = block.size;
+            // Don't trust the header, read at most one byte too many
+            let mut limited = block.reader.take(self.block_size() as u64 + 1);
+            let n = match self.block_read_chunk {
+                Some(chunk) => read_to_end_chunked(&mut limited, block_buf, chunk),
+                None => limited.read_to_end(block_buf),
+            }
+            .wrap_err_with(|| format!("reading block file {:?}", block_id))?;
+            self.check_block_entry_len(block_id, n as u64, size)?;
+            Ok(n)
+        })
+    }
+
+    /// Decompresses the block straight into sink, returns its length, None if
+    /// no dblock has it
+    ///
+    /// Spares the copy into a block buffer where the bytes are only hashed. With
+    /// a block cache or a BlockProvider the block is read into a buffer as by
+    /// get_content_block, to be cached. A block that turns out too long or short
+    /// errors after sink got its bytes
+    pub fn read_block_into<W: Write>(
+        &self,
+        block_id: &BlockIdHash,
+        sink: &mut W,
+    ) -> Result<Option<usize>> {
+        if self.block_cache.is_some() || self.provider.is_some() {
+            let mut block_buf = Vec::new();
+            let n = self.get_content_block(block_id, &mut block_buf)?;
+            sink.write_all(&block_buf)?;
+            return Ok(n);
+        }
+        let Some(mut ziparch) = self.get_zip_by_block_id(block_id)? else {
+            return Ok(None);
+        };
+        let n = self
+            .with_block_entry(ziparch.as_mut(), block_id, |block| {
+                let size = block.size;
+                let mut limited = block.reader.take(self.block_size() as u64 + 1);
+                let n = io::copy(&mut limited, sink)
+                    .wrap_err_with(|| format!("reading block file {:?}", block_id))?;
+                self.check_block_entry_len(block_id, n, size)?;
+                Ok(n as usize)
+            })
+            .wrap_err("block file by name not found even though we indexed it before")?;
+        Ok(Some(n))
+    }
+
+    /// Calls read with the entry of ziparch named after block_id, if its header
+    /// size is possible
+    fn with_block_entry<T>(
+        &self,
+        ziparch: &mut dyn BlockArchive,
+        block_id: &BlockIdHash,
+        read: impl FnOnce(BlockEntry<'_>) -> Result<T>,
+    ) -> Result<T> {
+        let base64_buf = &mut [0u8; 48];
+        let name_reencoded = block_id.as_base64_urlsafe(base64_buf);
+        let mut block = ziparch.by_name(name_reencoded);
+        // e.g. zstd entries when zip is built without zstd
+        if matches!(&block, Err(err) if unsupported_compression(err)) {
+            // The failed entry still borrows the archive until dropped
+            drop(block);
+            block = ziparch.by_name_decompressed_here(name_reencoded);
+        }
+        let block = block?;
+
+        // A block can't exceed the blocksize, anything bigger is corruption
+        let max_len = self.block_size() as u64;
+        if block.size > max_len {
+            return Err(eyre!(
+                "block {} is {} bytes, more than blocksize {}",
+                block_id,
+                block.size,
+                max_len
+            ));
+        }
+        read(block)
+    }
+
+    /// n bytes were read from an entry whose header says size
+    fn check_block_entry_len(&self, block_id: &BlockIdHash, n: u64, size: u64) -> Result<()> {
+        let max_len = self.block_size() as u64;
+        if n > max_len {
+            return Err(eyre!(
+                "block {} decompressed to more than blocksize {}",
+                block_id,
+                max_len
+            ));
+        }
+        // Offsets of the following blocks of a file are counted in blocksizes,
+        // a short block would shift them
+        if n != size {
+            return Err(eyre!(
+                "block {} decompressed to {} bytes, its entry says {}",
+                block_id,
+                n,
+                size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Every indexed dblock, in no particular order
+    pub fn volumes(&self) -> Vec<Arc<ZipArchiveWrapper>> {
+        let inner = self.inner.lock().unwrap();
+        inner.zip2ziparchive.values().cloned().collect()
+    }
+
+    /// Settings in another volume's manifest that differ from the dlist's,
+    /// as "Blocksize 1048576 != 102400", the volume's value first
+    pub fn manifest_differences(&self, manifest_bytes: &[u8]) -> Result<Vec<String>> {
+        let other: Manifest = serde_json::from_slice(manifest_bytes)?;
+        let mut differences = Vec::new();
+        if other.block_size != self.manifest.block_size {
+            differences.push(format!(
+                "Blocksize {} != {}",
+                other.block_size, self.manifest.block_size
+            ));
+        }
+        if other.block_hash != self.manifest.block_hash {
+            differences.push(format!(
+                "BlockHash {} != {}",
+                other.block_hash, self.manifest.block_hash
+            ));
+        }
+        if other.file_hash != self.manifest.file_hash {
+            differences.push(format!(
+                "FileHash {} != {}",
+                other.file_hash, self.manifest.file_hash
+            ));
+        }
+        Ok(differences)
+    }
+
     pub fn block_size(&self) -> usize {
         self.manifest.block_size as usize
     }
 
     pub fn offset_size(&self) -> usize {
         // opts['hashes-per-block'] * opts['blocksize']
-        let hashes_per_block = self.manifest.block_size / 32; // Assumes SHA-256
-        (hashes_per_block * self.manifest.block_size) as usize
+        let hashes_per_block = self.block_size() / self.hash_size();
+        hashes_per_block * self.block_size()
     }
 
     pub fn hash_size(&self) -> usize {
-        32
+        self.block_hash.digest_len()
+    }
+
+    /// One hash out of a blocklist, which has to be hash_size() bytes
+    pub fn blocklist_hash(&self, bytes: &[u8]) -> Result<BlockIdHash> {
+        BlockIdHash::from_bytes(bytes)
+            .filter(|_| bytes.len() == self.hash_size())
+            .ok_or_else(|| {
+                eyre!(
+                    "blocklist hash is {} bytes, {} hashes are {}",
+                    bytes.len(),
+                    self.block_hash,
+                    self.hash_size()
+                )
+            })
+    }
+
+    pub fn block_hash_algo(&self) -> HashAlgo {
+        self.block_hash
+    }
+
+    pub fn file_hash_algo(&self) -> HashAlgo {
+        self.file_hash
+    }
+}
+
+/// Like read_to_end, but grows buf by at most chunk bytes at a time
+fn read_to_end_chunked<R: Read>(
+    rdr: &mut R,
+    buf: &mut Vec<u8>,
+    chunk: usize,
+) -> std::io::Result<usize> {
+    let start = buf.len();
+    loop {
+        let len = buf.len();
+        buf.resize(len + chunk, 0);
+        match rdr.read(&mut buf[len..]) {
+            Ok(0) => {
+                buf.truncate(len);
+                return Ok(len - start);
+            }
+            Ok(n) => buf.truncate(len + n),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => buf.truncate(len),
+            Err(e) => {
+                buf.truncate(len);
+                return Err(e);
+            }
+        }
     }
 }
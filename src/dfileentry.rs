@@ -51,10 +51,8 @@ impl FileEntry {
                 time: ientry.time.clone().ok_or_else(|| eyre!("time not found"))?,
             },
             "Folder" => FileType::Folder {
-                metablockhash: ientry
-                    .metablockhash
-                    .clone()
-                    .ok_or_else(|| eyre!("metablockhash not found"))?,
+                // Empty folders may come without a metadata block
+                metablockhash: ientry.metablockhash.clone().unwrap_or_default(),
             },
             _ => FileType::SymLink,
         };
@@ -87,6 +85,14 @@ impl FileEntry {
         }
     }
 
+    /// Blocks the entry refers to, counting repeats: its content blocks, the
+    /// blocklists naming them and its metadata block
+    pub fn block_refs(&self, block_size: u64) -> u64 {
+        let content = self.bytes_size().div_ceil(block_size);
+        let meta = u64::from(!self.metahash.is_empty());
+        content + self.block_lists.len() as u64 + meta
+    }
+
     pub fn bytes_size(&self) -> u64 {
         if let FileType::File { size, .. } = self.file_type {
             size as u64
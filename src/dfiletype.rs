@@ -11,6 +11,14 @@ pub enum FileType {
         metablockhash: String,
     },
     SymLink,
+    /// Named pipe, known from the metadata block
+    Fifo,
+    /// Character or block device node, known from the metadata block
+    Device {
+        block: bool,
+        major: u32,
+        minor: u32,
+    },
 }
 
 impl FileType {
@@ -29,4 +37,21 @@ impl FileType {
     pub fn is_folder(&self) -> bool {
         matches!(self, FileType::Folder { .. })
     }
+
+    /// Lowercase name for listings: file, folder, symlink, fifo, chardev or blockdev
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FileType::File { .. } => "file",
+            FileType::Folder { .. } => "folder",
+            FileType::SymLink => "symlink",
+            FileType::Fifo => "fifo",
+            FileType::Device { block: false, .. } => "chardev",
+            FileType::Device { block: true, .. } => "blockdev",
+        }
+    }
+
+    /// FIFO or device node
+    pub fn is_special(&self) -> bool {
+        matches!(self, FileType::Fifo | FileType::Device { .. })
+    }
 }
@@ -0,0 +1,74 @@
+use crate::crypto::open_backup_file;
+use crate::ziparchive::check_not_encrypted;
+use base64::engine::general_purpose;
+use base64::Engine;
+use eyre::{eyre, Context, Result};
+use serde::Deserialize;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// A `vol/<dblock name>` entry of a dindex
+#[derive(Deserialize)]
+struct VolumeJson {
+    blocks: Vec<VolumeBlockJson>,
+    #[serde(rename = "volumesize")]
+    volume_size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct VolumeBlockJson {
+    /// Standard base64, the dblock names its entry with the urlsafe form
+    hash: String,
+}
+
+/// Blocks of one dblock as listed by a dindex
+pub struct DindexVolume {
+    /// File name of the dblock, without a directory
+    pub name: String,
+    /// Entry names inside the dblock (urlsafe base64 hashes), in the order they were written
+    pub block_names: Vec<String>,
+    /// Size of the dblock file when the dindex was written
+    pub volume_size: Option<u64>,
+}
+
+/// Reads the `vol/` entries of a dindex, one per dblock it describes
+///
+/// The `list/` entries (copies of blocklists) are not needed to locate blocks
+pub fn read_dindex(dindex_path: &Path, passphrase: Option<&str>) -> Result<Vec<DindexVolume>> {
+    let mut reader = BufReader::new(open_backup_file(dindex_path, passphrase)?);
+    check_not_encrypted(&mut reader, dindex_path)?;
+    let mut zip = zip::ZipArchive::new(reader)
+        .wrap_err_with(|| format!("{:?} is not a readable zip", dindex_path))?;
+
+    let mut volumes = Vec::new();
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index)?;
+        let Some(name) = entry.name().strip_prefix("vol/").map(String::from) else {
+            continue;
+        };
+        let mut content = Vec::new();
+        entry
+            .read_to_end(&mut content)
+            .wrap_err_with(|| format!("read vol/{} from {:?}", name, dindex_path))?;
+        let volume: VolumeJson = serde_json::from_slice(&content)
+            .wrap_err_with(|| format!("parse vol/{} from {:?}", name, dindex_path))?;
+
+        let block_names = volume
+            .blocks
+            .iter()
+            .map(|block| {
+                let hash = general_purpose::STANDARD
+                    .decode(&block.hash)
+                    .map_err(|err| eyre!("block hash {:?}: {}", block.hash, err))?;
+                Ok(general_purpose::URL_SAFE.encode(hash))
+            })
+            .collect::<Result<Vec<_>>>()
+            .wrap_err_with(|| format!("vol/{} in {:?}", name, dindex_path))?;
+        volumes.push(DindexVolume {
+            name,
+            block_names,
+            volume_size: volume.volume_size,
+        });
+    }
+    Ok(volumes)
+}
@@ -0,0 +1,296 @@
+use crate::backend;
+use crate::crypto::{open_backup_file, ReadSeek};
+use crate::database::manifest_created;
+use crate::dfileentry::{parse_dlist_read, FileEntry};
+use crate::mtime::parse_backup_time;
+use crate::stripbom::{StripBom, StripBomBytes};
+use crate::ziparchive::check_not_encrypted;
+use eyre::eyre;
+use eyre::{Context, Result};
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// How files of kind are told apart, for messages that none were found
+pub fn describe_names(kind: VolumeKind, suffixes: &[String]) -> String {
+    match suffixes.is_empty() {
+        true => format!("named like *.{}.*", kind.name()),
+        false => format!("ending with {:?}", suffixes),
+    }
+}
+
+pub fn filename_ends_with<P: AsRef<Path>>(path: P, suffix: &str) -> bool {
+    path.as_ref()
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.ends_with(suffix))
+        .unwrap_or(false)
+}
+
+/// Also matches encrypted volumes, `<suffix>.aes`
+pub fn filename_ends_with_any<P: AsRef<Path>>(path: P, suffixes: &[String]) -> bool {
+    suffixes.iter().any(|suffix| {
+        filename_ends_with(path.as_ref(), suffix)
+            || filename_ends_with(path.as_ref(), &format!("{}.aes", suffix))
+    })
+}
+
+/// What a backup file is, from the kind in its name: "duplicati-b1.dblock.zip.aes"
+/// is a dblock whatever its compression or encryption
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VolumeKind {
+    Dlist,
+    Dblock,
+    Dindex,
+}
+
+impl VolumeKind {
+    /// The kind named by the last of the dot separated parts of the file name that
+    /// names one, if extensions follow it. None for other files
+    ///
+    /// ```
+    /// use rust_duplicati_restore::dlist::VolumeKind;
+    ///
+    /// assert_eq!(VolumeKind::of("x/duplicati-20240101T000000Z.dlist.zip"), Some(VolumeKind::Dlist));
+    /// assert_eq!(VolumeKind::of("duplicati-verification.json"), None);
+    /// ```
+    pub fn of<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let name = path.as_ref().file_name()?.to_str()?;
+        let mut parts: Vec<&str> = name.split('.').skip(1).collect();
+        // The kind is followed by at least the compression
+        parts.pop()?;
+        parts.iter().rev().find_map(|part| {
+            [Self::Dlist, Self::Dblock, Self::Dindex]
+                .into_iter()
+                .find(|kind| part.eq_ignore_ascii_case(kind.name()))
+        })
+    }
+
+    /// As in file names
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Dlist => "dlist",
+            Self::Dblock => "dblock",
+            Self::Dindex => "dindex",
+        }
+    }
+}
+
+/// True for backup files of kind: named with one of suffixes if there are any,
+/// else by the kind in their name
+pub fn is_volume<P: AsRef<Path>>(path: P, kind: VolumeKind, suffixes: &[String]) -> bool {
+    match suffixes.is_empty() {
+        true => VolumeKind::of(path) == Some(kind),
+        false => filename_ends_with_any(path, suffixes),
+    }
+}
+
+pub struct FileEntries {
+    pub entries: Vec<FileEntry>,
+}
+
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Opens a dlist, decrypted with passphrase if it is encrypted
+pub fn open_dlist_zip(
+    dlist_path: &Path,
+    passphrase: Option<&str>,
+) -> Result<BufReader<Box<dyn ReadSeek>>> {
+    let mut reader = BufReader::new(open_backup_file(dlist_path, passphrase)?);
+    check_not_encrypted(&mut reader, dlist_path)?;
+    Ok(reader)
+}
+
+/// Open dlist file and parse json inside
+pub fn parse_dlist_file<P: AsRef<Path>>(
+    dlist_path: P,
+    passphrase: Option<&str>,
+) -> Result<FileEntries> {
+    let dlist_reader = open_dlist_zip(dlist_path.as_ref(), passphrase)?;
+    let mut dlist_zip = zip::ZipArchive::new(dlist_reader)?;
+    let filelist_name = "filelist.json";
+    let dlist_file = dlist_zip.by_name(filelist_name)?;
+    let mut bufrdr = BufReader::with_capacity(32 * 1024, dlist_file);
+    // filelist.json may itself be gzip-compressed inside a stored zip entry
+    let is_gzip = bufrdr.fill_buf()?.starts_with(&GZIP_MAGIC);
+    let list = if is_gzip {
+        let gzrdr = BufReader::with_capacity(32 * 1024, GzDecoder::new(bufrdr));
+        parse_dlist_read(gzrdr)
+    } else {
+        parse_dlist_read(bufrdr)
+    };
+    let list = list.wrap_err_with(|| {
+        format!(
+            "parse_dlist {:?} / {:?}",
+            dlist_path.as_ref(),
+            filelist_name
+        )
+    })?;
+
+    Ok(list)
+}
+
+/// Dlist files in backup_dir, sorted by file name
+///
+/// Without extensions every file named like a dlist is one, see VolumeKind
+pub fn find_dlist_files(backup_dir: &str, extensions: &[String]) -> Result<Vec<PathBuf>> {
+    let mut dlist_file_paths: Vec<PathBuf> = backend::read_dir(Path::new(backup_dir))
+        .wrap_err_with(|| format!("read_dir({:?})", backup_dir))?
+        .into_iter()
+        .filter(|path| is_volume(path, VolumeKind::Dlist, extensions))
+        .collect();
+    if dlist_file_paths.is_empty() {
+        return Err(eyre!(
+            "no dlist file {} found in {:?}",
+            describe_names(VolumeKind::Dlist, extensions),
+            backup_dir
+        ));
+    }
+    dlist_file_paths.sort();
+    Ok(dlist_file_paths)
+}
+
+/// Sorts dlists by the Created time in their manifest, oldest first
+///
+/// File names can be renamed, the manifest can't. Keeps the file name order if
+/// any manifest can't be read. Copies of a dlist (same Created time and file list)
+/// are dropped. Returns the manifests read, by dlist path
+pub fn sort_dlists_by_created(
+    dlist_file_paths: &mut Vec<PathBuf>,
+    passphrase: Option<&str>,
+) -> HashMap<PathBuf, Vec<u8>> {
+    let mut manifests = HashMap::new();
+    let mut created = HashMap::new();
+    for path in dlist_file_paths.iter() {
+        let time = read_manifest(path, passphrase).and_then(|manifest| {
+            let time = parse_backup_time(&manifest_created(&manifest)?)?;
+            manifests.insert(path.clone(), manifest);
+            Ok(time)
+        });
+        match time {
+            Ok(time) => {
+                created.insert(path.clone(), time);
+            }
+            Err(err) => {
+                println!(
+                    "warn: can't read Created of {:?}, ordering dlists by file name: {:#}",
+                    path, err
+                );
+                return manifests;
+            }
+        }
+    }
+    dlist_file_paths.sort_by(|a, b| created[a].cmp(&created[b]).then_with(|| a.cmp(b)));
+
+    // Sorted, so copies are next to each other. The first by file name is kept
+    let mut kept: Vec<PathBuf> = Vec::with_capacity(dlist_file_paths.len());
+    for path in dlist_file_paths.drain(..) {
+        let mut copy_of = None;
+        for previous in kept.iter().rev() {
+            if created[previous] != created[&path] {
+                break;
+            }
+            match same_filelist(previous, &path, passphrase) {
+                Ok(true) => {
+                    copy_of = Some(previous);
+                    break;
+                }
+                Ok(false) => println!(
+                    "warn: {:?} and {:?} have the same Created time but different files, \
+                     the copies are inconsistent, pick one with --dlist",
+                    previous, path
+                ),
+                Err(err) => println!(
+                    "warn: can't compare {:?} with {:?}, keeping both: {:#}",
+                    path, previous, err
+                ),
+            }
+        }
+        match copy_of {
+            Some(previous) => println!("{:?} is a copy of {:?}, ignoring it", path, previous),
+            None => kept.push(path),
+        }
+    }
+    *dlist_file_paths = kept;
+    manifests
+}
+
+/// Drops the dlists created after before, so the newest left is the version to restore
+///
+/// dlist_file_paths must be sorted oldest first. Errors listing all Created
+/// times if no dlist is old enough
+pub fn keep_created_before(
+    dlist_file_paths: &mut Vec<PathBuf>,
+    manifests: &HashMap<PathBuf, Vec<u8>>,
+    before: &str,
+    passphrase: Option<&str>,
+) -> Result<()> {
+    let before = parse_backup_time(before).wrap_err_with(|| format!("--before {:?}", before))?;
+    let mut created = Vec::with_capacity(dlist_file_paths.len());
+    for path in dlist_file_paths.iter() {
+        let manifest = match manifests.get(path) {
+            Some(manifest) => manifest.clone(),
+            None => read_manifest(path, passphrase)?,
+        };
+        let time = parse_backup_time(&manifest_created(&manifest)?)
+            .wrap_err_with(|| format!("Created of {:?}", path))?;
+        created.push(time);
+    }
+
+    match created.iter().rposition(|time| *time <= before) {
+        Some(index) => {
+            println!("Newest version at or before {}: {}", before, created[index]);
+            dlist_file_paths.truncate(index + 1);
+            Ok(())
+        }
+        None => {
+            println!("Versions in the backup:");
+            for (path, time) in dlist_file_paths.iter().zip(&created) {
+                println!("  {}\t{:?}", time, path);
+            }
+            Err(eyre!("no backup version created at or before {}", before))
+        }
+    }
+}
+
+/// Whether two dlists list the same files, compared as filelist.json bytes
+pub fn same_filelist(a: &Path, b: &Path, passphrase: Option<&str>) -> Result<bool> {
+    let read = |path: &Path| -> Result<Vec<u8>> {
+        let mut zip = zip::ZipArchive::new(open_dlist_zip(path, passphrase)?)?;
+        let mut content = Vec::new();
+        zip.by_name("filelist.json")?
+            .read_to_end(&mut content)
+            .wrap_err_with(|| format!("read filelist.json from {:?}", path))?;
+        Ok(content)
+    };
+    Ok(read(a)?.strip_bom() == read(b)?.strip_bom())
+}
+
+/// A dlist is a zip with a manifest and a filelist.json
+pub fn check_dlist_file(dlist_path: &Path, passphrase: Option<&str>) -> Result<()> {
+    let file = open_dlist_zip(dlist_path, passphrase)
+        .wrap_err_with(|| format!("open dlist {:?}", dlist_path))?;
+    let mut zip = zip::ZipArchive::new(file)
+        .wrap_err_with(|| format!("{:?} is not a dlist: not a readable zip", dlist_path))?;
+    for name in ["manifest", "filelist.json"] {
+        if zip.by_name(name).is_err() {
+            return Err(eyre!("{:?} is not a dlist: no {} inside", dlist_path, name));
+        }
+    }
+    Ok(())
+}
+
+/// Open Manifest from zip
+pub fn read_manifest<P: AsRef<Path>>(dlist_path: P, passphrase: Option<&str>) -> Result<Vec<u8>> {
+    let manifest_file = open_dlist_zip(dlist_path.as_ref(), passphrase)?;
+    let mut manifest_zip = zip::ZipArchive::new(manifest_file)?;
+    let mut manifest_file = manifest_zip.by_name("manifest")?;
+    let mut manifest_contents = String::new();
+    manifest_file
+        .read_to_string(&mut manifest_contents)
+        .wrap_err_with(|| format!("read manifest from {:?}", dlist_path.as_ref()))?;
+    let manifest_contents = manifest_contents.strip_bom();
+    let manifest_contents = manifest_contents.trim();
+    Ok(manifest_contents.into())
+}
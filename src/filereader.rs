@@ -0,0 +1,257 @@
+use crate::{
+    blockhash::BlockIdHash,
+    blockmap::{block_map, BlockRef},
+    database::DFileDatabase,
+    dfileentry::FileEntry,
+    dfiletype::FileType,
+    restoring::{check_block_hash, HashMismatch},
+};
+use eyre::{eyre, Context, Result};
+use sha2::digest::DynDigest;
+use std::io::{self, Read, Write};
+
+/// Reads one backed-up file in order, straight from the dblocks
+///
+/// Holds a single block in memory, the next one is fetched
+/// with get_content_block when the current one is used up. Each block is
+/// checked against its hash as it's fetched and the whole file against the
+/// file hash at the end, a mismatch is an InvalidData error with a HashMismatch
+pub struct BlockFileReader<'a> {
+    db: &'a DFileDatabase,
+    path: String,
+    blocks: std::vec::IntoIter<BlockRef>,
+    block: Vec<u8>,
+    /// Bytes of block already read
+    pos: usize,
+    file_hash: BlockIdHash,
+    /// Taken when the file hash is checked, None for empty files
+    hasher: Option<Box<dyn DynDigest + Send>>,
+}
+
+fn find_entry<'a>(entries: &'a [FileEntry], path: &str) -> Result<&'a FileEntry> {
+    entries
+        .iter()
+        .find(|e| e.path == path)
+        .ok_or_else(|| eyre!("{:?} is not in the dlist", path))
+}
+
+/// Opens the file at path (as written in the dlist) for streaming
+///
+/// The blocklists are read here, the content blocks as the reader gets to them
+pub fn open_file<'a>(
+    db: &'a DFileDatabase,
+    entries: &[FileEntry],
+    path: &str,
+) -> Result<BlockFileReader<'a>> {
+    let entry = find_entry(entries, path)?;
+    let FileType::File { hash, size, .. } = &entry.file_type else {
+        return Err(eyre!("{:?} is not a file", path));
+    };
+    let blocks = block_map(entry, db)?;
+    Ok(BlockFileReader {
+        db,
+        path: entry.path.clone(),
+        blocks: blocks.into_iter(),
+        block: Vec::with_capacity(db.block_size()),
+        pos: 0,
+        file_hash: hash.clone(),
+        hasher: (*size > 0).then(|| db.file_hash_algo().hasher()),
+    })
+}
+
+/// Size of the file at path, as declared in the dlist
+pub fn file_size(entries: &[FileEntry], path: &str) -> Result<u64> {
+    let entry = find_entry(entries, path)?;
+    let FileType::File { size, .. } = &entry.file_type else {
+        return Err(eyre!("{:?} is not a file", path));
+    };
+    Ok((*size).max(0) as u64)
+}
+
+/// End of the range [offset, offset + length) of the file at path, an error if it's past the end
+pub fn check_range(entries: &[FileEntry], path: &str, offset: u64, length: u64) -> Result<u64> {
+    let size = file_size(entries, path)?;
+    offset
+        .checked_add(length)
+        .filter(|end| *end <= size)
+        .ok_or_else(|| {
+            eyre!(
+                "range of {} bytes at offset {} is past the end of {:?}, it has {} bytes",
+                length,
+                offset,
+                path,
+                size
+            )
+        })
+}
+
+/// Copies bytes [offset, offset + length) of the file at path into out
+///
+/// Only the blocklists and content blocks covering the range are read, each block
+/// is checked against its hash. The file hash can't be checked for a part of the file
+pub fn read_range(
+    db: &DFileDatabase,
+    entries: &[FileEntry],
+    path: &str,
+    offset: u64,
+    length: u64,
+    out: &mut impl Write,
+) -> Result<()> {
+    let end = check_range(entries, path, offset, length)?;
+    let entry = find_entry(entries, path)?;
+    if length == 0 {
+        return Ok(());
+    }
+
+    let block_size = db.block_size() as u64;
+    let first = offset / block_size;
+    let last = (end - 1) / block_size;
+    let mut buf = Vec::with_capacity(db.block_size());
+    for (index, hash) in (first..=last).zip(range_block_hashes(entry, db, first, last)?) {
+        buf.clear();
+        db.get_content_block(&hash, &mut buf)
+            .wrap_err_with(|| format!("read content block number {}", index))?
+            .ok_or_else(|| eyre!("Failed to find block {} for {:?}", hash, path))?;
+        check_block_hash(db.block_hash_algo(), &hash, &buf)
+            .wrap_err_with(|| format!("content block number {}", index))?;
+
+        let block_start = index * block_size;
+        let from = offset.saturating_sub(block_start) as usize;
+        let to = (end - block_start).min(block_size) as usize;
+        let part = buf.get(from..to).ok_or_else(|| {
+            eyre!(
+                "content block number {} of {:?} is {} bytes, too short",
+                index,
+                path,
+                buf.len()
+            )
+        })?;
+        out.write_all(part).wrap_err("write range")?;
+    }
+    Ok(())
+}
+
+/// Hashes of content blocks first..=last, reads only the blocklists listing them
+fn range_block_hashes(
+    entry: &FileEntry,
+    db: &DFileDatabase,
+    first: u64,
+    last: u64,
+) -> Result<Vec<BlockIdHash>> {
+    let FileType::File { hash, .. } = &entry.file_type else {
+        return Err(eyre!("{:?} is not a file", entry.path));
+    };
+    if entry.block_lists.is_empty() {
+        return Ok(vec![hash.clone()]);
+    }
+
+    let hashes_per_blocklist = (db.block_size() / db.hash_size()) as u64;
+    let mut hashes = Vec::new();
+    let mut hashes_buf = Vec::new();
+    for list_index in first / hashes_per_blocklist..=last / hashes_per_blocklist {
+        let blocklist = entry.block_lists.get(list_index as usize).ok_or_else(|| {
+            eyre!(
+                "{:?} has {} blocklists, block number {} needs more",
+                entry.path,
+                entry.block_lists.len(),
+                last
+            )
+        })?;
+        hashes_buf.clear();
+        db.get_content_block(blocklist, &mut hashes_buf)
+            .wrap_err_with(|| format!("read blocklist {}", blocklist))?
+            .ok_or_else(|| eyre!("Failed to find blocklist {}", blocklist))?;
+        let list_first = list_index * hashes_per_blocklist;
+        for (i, bhash) in hashes_buf.chunks(db.hash_size()).enumerate() {
+            let index = list_first + i as u64;
+            if (first..=last).contains(&index) {
+                hashes.push(db.blocklist_hash(bhash)?);
+            }
+        }
+    }
+    if hashes.len() as u64 != last - first + 1 {
+        return Err(eyre!(
+            "blocklists of {:?} have too few blocks for its size",
+            entry.path
+        ));
+    }
+    Ok(hashes)
+}
+
+impl BlockFileReader<'_> {
+    /// Replaces the current block with the next one, false at the end of the file
+    fn next_block(&mut self) -> io::Result<bool> {
+        let Some(block_ref) = self.blocks.next() else {
+            self.check_file_hash()?;
+            return Ok(false);
+        };
+        self.block.clear();
+        self.pos = 0;
+        let len = self
+            .db
+            .get_content_block(&block_ref.hash, &mut self.block)
+            .map_err(|err| io::Error::other(format!("{:#}", err)))?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "Failed to find block {} for {:?}",
+                        block_ref.hash, self.path
+                    ),
+                )
+            })?;
+        if len as u64 != block_ref.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "block {} of {:?} is {} bytes, expected {}",
+                    block_ref.hash, self.path, len, block_ref.len
+                ),
+            ));
+        }
+        check_block_hash(self.db.block_hash_algo(), &block_ref.hash, &self.block).map_err(
+            |err| match err.downcast::<HashMismatch>() {
+                Ok(mismatch) => io::Error::new(io::ErrorKind::InvalidData, mismatch),
+                Err(err) => io::Error::other(format!("{:#}", err)),
+            },
+        )?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&self.block);
+        }
+        Ok(true)
+    }
+
+    /// Once, after the last block
+    fn check_file_hash(&mut self) -> io::Result<()> {
+        let Some(hasher) = self.hasher.take() else {
+            return Ok(());
+        };
+        let calculated = hasher.finalize();
+        if *calculated != *self.file_hash.hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                HashMismatch {
+                    block: false,
+                    algo: self.db.file_hash_algo(),
+                    expected: self.file_hash.hash.to_vec(),
+                    calculated: calculated.to_vec(),
+                },
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Read for BlockFileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.block.len() {
+            if !self.next_block()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.block.len() - self.pos);
+        buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -1,11 +1,180 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::path::PathBuf;
 
-#[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+/// Which files --exclude-larger-than-free-space restores first
+#[derive(Clone, Copy, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TriageOrder {
+    /// as many files as possible
+    Smallest,
+    /// alphabetically, like the backup source tree
+    Path,
+}
+
+/// Path convention of the backed up or the restoring system
+#[derive(Clone, Copy, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathOs {
+    /// guess the source from dlist paths, target is this OS
+    Auto,
+    Windows,
+    Unix,
+}
+
+/// Checksum written by --write-sidecar-hash
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SidecarAlgorithm {
+    /// the file hash Duplicati stores, verified during the restore
+    Sha256,
+    /// computed while writing, not checked against the backup
+    Sha512,
+}
+
+/// Where --restore-mtime and --metadata-only take the modification time from
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    /// the `time` field of the dlist entry, no extra block read
+    Mtime,
+    /// CoreLastWritetime of the metadata block (100ns ticks), the dlist time if it's missing
+    Metadata,
+}
+
+/// Order in which the blocks of a multiblock file are written
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteOrder {
+    /// as the blocklists list them, seeking to each block's offset
+    Blocklist,
+    /// every block starts where the previous one ended, a gap or overlap fails the file
+    Ascending,
+}
+
+/// How dblock files are read
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZipReader {
+    /// a file descriptor and read buffer per reading thread
+    Buffered,
+    /// one memory map per dblock shared by all threads, for backups on fast local disks.
+    /// Encrypted dblocks are read buffered
+    Mmap,
+}
+
+/// What --skip-existing checks before leaving a file in the restore dir as it is
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipExisting {
+    /// same size as in the backup
+    Size,
+    /// same size and file hash as in the backup, reads the whole file
+    Hash,
+}
+
+/// File format of --report
+#[derive(Clone, Copy, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    /// spreadsheet friendly, written as files complete
+    Csv,
+    /// a single array, written at the end
+    Json,
+    /// one object per line, written as files complete
+    Jsonl,
+}
+
+/// Runs instead of a restore to --restore-dir
+#[derive(Subcommand, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Command {
+    /// restores in memory and compares every file byte for byte with a reference tree,
+    /// printing a tab separated line per difference and exiting nonzero if there is any
+    AssertMatches {
+        /// laid out like a restore dir, e.g. C/dir/file for C:\dir\file
+        reference_dir: PathBuf,
+    },
+    /// prints the entries of the dlist (type, size and path) without restoring anything
+    List {
+        /// also prints the content block hashes of every file, reads blocklists from the dblocks
+        #[arg(long)]
+        with_blocks: bool,
+
+        /// one JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+    /// prints the backup versions (index for --version, created time, Duplicati version,
+    /// file count) without indexing the dblocks
+    ListVersions,
+}
+
+#[derive(Parser, Serialize)]
+#[command(author, version, about, long_about = None, disable_version_flag = true)]
 pub struct RestoreFlags {
+    /// prints the program version
+    #[arg(short = 'V', long = "program-version", action = clap::ArgAction::Version)]
+    #[serde(skip_serializing)]
+    pub program_version: Option<bool>,
+
     /// the location of the backup
-    #[arg(short, long)]
-    pub backup_dir: String,
+    #[arg(
+        short,
+        long,
+        required_unless_present_all = ["dlist", "dblock_dir"],
+        required_unless_present_any = ["backup_archive", "backend"]
+    )]
+    pub backup_dir: Option<String>,
+
+    /// reads the backup from a tar or zip of the backup directory instead, without
+    /// extracting it. A zip has to be stored uncompressed (zip -0)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["backup_dir", "dblock_dir"])]
+    pub backup_archive: Option<String>,
+
+    /// reads the backup from a server instead: s3://bucket/prefix with the usual AWS
+    /// credentials, or sftp://user@host/path. Needs a build with the s3 or sftp feature
+    #[arg(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["backup_dir", "backup_archive", "dblock_dir"]
+    )]
+    pub backend: Option<String>,
+
+    /// private key to log in to an sftp:// --backend with, the ssh agent is asked otherwise
+    #[arg(long, value_name = "FILE", requires = "backend")]
+    pub identity: Option<PathBuf>,
+
+    /// restores from this dlist file instead of the newest one in the backup dir
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["best_effort_latest", "since_version"]
+    )]
+    pub dlist: Option<String>,
+
+    /// directory with the dblock files, defaults to the backup dir
+    #[arg(long, value_name = "DIR")]
+    pub dblock_dir: Option<String>,
+
+    /// file name suffix of dlist files, repeatable. By default every file with .dlist.
+    /// in its name is one, e.g. x.dlist.zip.aes
+    #[arg(long = "dlist-extension", value_name = "SUFFIX")]
+    pub dlist_extensions: Vec<String>,
+
+    /// file name suffix of dblock files, repeatable, by default .dblock. in the name.
+    /// Files ending with .7z are read as 7z volumes, the rest as zip
+    #[arg(long = "dblock-extension", value_name = "SUFFIX")]
+    pub dblock_extensions: Vec<String>,
+
+    /// file name suffix of dindex files, repeatable, by default .dindex. in the name.
+    /// Their block lists spare opening every dblock while indexing, implies --hash-to-path
+    #[arg(long = "dindex-extension", value_name = "SUFFIX")]
+    pub dindex_extensions: Vec<String>,
+
+    /// opens every dblock to index it even if there are dindex files
+    #[arg(long)]
+    pub ignore_dindex: bool,
 
     /// a location to restore to
     #[arg(short, long, value_name = "FILE")]
@@ -15,19 +184,354 @@ pub struct RestoreFlags {
     #[arg(short, long, default_value_t = 4)]
     pub threads_rayon: usize,
 
+    /// threads for indexing dblocks and reading dindex files, defaults to --threads-rayon.
+    /// 1 or 2 on a spinning disk, more than --threads-rayon for remote backends
+    #[arg(long, value_name = "N")]
+    pub threads_io: Option<usize>,
+
+    /// pins each rayon thread to one CPU, helps on multi-socket (NUMA) machines.
+    /// No-op on platforms without affinity support
+    #[arg(long)]
+    pub pin_threads: bool,
+
     /// displays progress bar in CLI
     #[arg(short, long)]
     pub progress_bar: bool,
 
+    /// prints sizes as KiB/MiB/GiB/TiB instead of bytes, reports keep bytes
+    #[arg(long)]
+    pub human_readable: bool,
+
+    /// progress bar follows predicted restore time (4KB per file + size) instead of bytes written
+    #[arg(long)]
+    pub progress_predicted: bool,
+
     /// true if use additional hashmap to speed up hashed name lookup. Increases memory usage.
     #[arg(long)]
     pub hash_to_path: bool,
 
-    /// true to restore windows backup on linux
+    /// remembers indexed dblocks in FILE, so an interrupted or repeated run
+    /// only indexes the dblocks not in it yet. Implies --hash-to-path
+    #[arg(long, value_name = "FILE")]
+    pub index_cache: Option<PathBuf>,
+
+    /// indexes the other dblocks when one can't be read (empty, truncated or corrupt),
+    /// files with blocks in it are then reported missing
+    #[arg(long)]
+    pub skip_bad_volumes: bool,
+
+    /// passphrase of an encrypted backup, decrypts .aes dlists and dblocks
+    #[arg(long, conflicts_with_all = ["passphrase_env", "passphrase_file"])]
+    #[serde(skip_serializing)]
+    pub passphrase: Option<String>,
+
+    /// reads the passphrase from this environment variable, keeps it out of argv
+    #[arg(long, value_name = "VARNAME", conflicts_with = "passphrase_file")]
+    pub passphrase_env: Option<String>,
+
+    /// reads the passphrase from this file, a trailing newline is dropped
+    #[arg(long, value_name = "PATH")]
+    pub passphrase_file: Option<PathBuf>,
+
+    /// deprecated, use --source-os. true reads paths as windows, false as unix
     #[arg(long)]
     pub replace_backslash_to_slash: Option<bool>,
 
+    /// path convention of the backed up system, decides separators and drive letter handling
+    #[arg(long, value_enum, default_value = "auto")]
+    pub source_os: PathOs,
+
+    /// naming rules of the restore target, e.g. windows when restoring onto an NTFS disk from linux
+    #[arg(long, value_enum, default_value = "auto")]
+    pub target_os: PathOs,
+
+    /// sets modification time of restored files, with sub-second precision
+    #[arg(long)]
+    pub restore_mtime: bool,
+
+    /// where the restored modification time comes from; creation time is not restored
+    #[arg(long, value_enum, default_value = "mtime")]
+    pub created_time_from: TimeSource,
+
+    /// sets the unix mode bits of restored files from their metadata block, needs ownership of the files
+    #[arg(long)]
+    pub restore_permissions: bool,
+
+    /// order of block writes within a file, ascending checks that writes never seek backwards or skip
+    #[arg(long, value_enum, default_value = "blocklist")]
+    pub write_order: WriteOrder,
+
+    /// reads this many blocks ahead of the writer in large files, helps on high-latency storage
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub prefetch: usize,
+
+    /// fetches up to N blocks of one large file at once and writes them in place, the file hash is then read back from disk. Ignored with --write-order ascending
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    pub intra_file_parallelism: usize,
+
+    /// only creates the directory structure, skips files
+    #[arg(long, conflicts_with = "only_files")]
+    pub only_folders: bool,
+
+    /// only restores files, parent directories are created as needed
+    #[arg(long)]
+    pub only_files: bool,
+
+    /// lists paths the target filesystem would reject (e.g. ':' on windows) and exits
+    #[arg(long)]
+    pub validate_paths: bool,
+
+    /// renames paths the target filesystem would reject, see --validate-paths
+    #[arg(long)]
+    pub sanitize_paths: bool,
+
+    /// shortens file names over 255 bytes, a hash of the full name keeps them unique
+    #[arg(long)]
+    pub truncate_long_names: bool,
+
+    /// renames a file whose target path is already taken, e.g. by a name that differs only
+    /// in case on Windows, instead of overwriting. Only paths within the backup count,
+    /// files already in the restore dir are still overwritten
+    #[arg(long)]
+    pub rename_on_conflict: bool,
+
+    /// restores every file directly into the restore dir, named after its whole path
+    /// ("C_Users_me_a.txt"), for paths too long for Windows. duplicati-flatten-map.json
+    /// there maps the names back to the original paths
+    #[arg(long, conflicts_with_all = ["only_folders", "sanitize_paths", "truncate_long_names"])]
+    pub flatten: bool,
+
+    /// on Windows, restores under the \\?\ form of the restore dir so paths can be longer
+    /// than 260 characters. --long-paths false turns that off
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set, value_name = "BOOL")]
+    pub long_paths: bool,
+
+    /// how renamed names look, {name} is the stem, {ext} the extension with its dot, {n} a counter
+    #[arg(long, value_name = "TEMPLATE", default_value = "{name} ({n}){ext}")]
+    pub conflict_suffix: String,
+
+    /// restores files starting in the same dblock on this many workers, in sorted order,
+    /// instead of spreading them over all threads. 1 is best for HDDs
+    #[arg(long, value_name = "N")]
+    pub workers_per_volume: Option<usize>,
+
+    /// creates FIFOs and device nodes (unix, devices need root), they are skipped otherwise.
+    /// Only backups with unix:filetype metadata have them, Duplicati doesn't write it
+    #[arg(long)]
+    pub restore_special: bool,
+
+    /// recreates directory junctions of Windows backups as junctions (Windows only),
+    /// they become plain folders otherwise
+    #[arg(long)]
+    pub restore_reparse: bool,
+
+    /// dblock files open at once, readers wait when reached.
+    /// Defaults to 3/4 of the open files limit (ulimit -n).
+    /// Each of the --threads-rayon threads has at most one dblock open at a time,
+    /// a limit below the thread count makes threads wait for each other instead of failing
+    #[arg(long, value_name = "N", visible_alias = "max-open-files")]
+    pub max_open_archives: Option<usize>,
+
+    /// grows block buffers by at most this many bytes per read, bounds memory with many threads
+    #[arg(long, value_name = "BYTES")]
+    pub block_read_chunk: Option<usize>,
+
+    /// how dblock files are read
+    #[arg(long, value_enum, default_value = "buffered")]
+    pub reader: ZipReader,
+
+    /// keeps up to this many MiB of decompressed blocks in memory, so blocks shared by many
+    /// files are read from the dblocks once. Off by default
+    #[arg(long, value_name = "MB")]
+    pub block_cache_mb: Option<usize>,
+
+    /// reads all dlists and restores, per file, the newest version whose blocks are all present
+    #[arg(long)]
+    pub best_effort_latest: bool,
+
+    /// checks every block against its own hash instead of hashing each whole file,
+    /// blocks are then hashed in parallel (with --prefetch), but the file hash from the dlist goes unchecked
+    #[arg(long)]
+    pub per_block_hash: bool,
+
+    /// reads the blocklists of each file and checks all its blocks are indexed before creating it,
+    /// a file that can't be complete then leaves nothing behind
+    #[arg(long)]
+    pub validate_before_write: bool,
+
+    /// when the restore won't fit on the target, restores files in --triage-order until it's nearly full
+    /// and lists the skipped ones, instead of failing with disk full
+    #[arg(long)]
+    pub exclude_larger_than_free_space: bool,
+
+    /// order for --exclude-larger-than-free-space
+    #[arg(long, value_enum, default_value = "smallest")]
+    pub triage_order: TriageOrder,
+
+    /// restores only files added or changed since backup version N (0 is the newest)
+    #[arg(long, value_name = "N", conflicts_with = "best_effort_latest")]
+    pub since_version: Option<usize>,
+
+    /// restores backup version N instead of the newest (0), 1 is the one before and so on.
+    /// Newer versions are ignored, --since-version and --best-effort-latest count from N
+    #[arg(long, value_name = "N", conflicts_with = "dlist")]
+    pub version: Option<usize>,
+
+    /// restores the newest backup version created at or before TIME (RFC 3339,
+    /// e.g. 2024-01-31T12:00:00Z), newer versions are ignored like with --version
+    #[arg(long, value_name = "TIME", conflicts_with_all = ["dlist", "version"])]
+    pub before: Option<String>,
+
+    /// restores only paths matching GLOB (or below a matching folder), repeatable.
+    /// Matched with / separators against the path under the restore dir, e.g. C/Users/me/**
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// skips paths matching GLOB and everything below a matching folder, repeatable
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// after restoring, re-reads the blocks from another copy of the backup (e.g. a mounted remote)
+    /// and checks the restored files match what it reassembles
+    #[arg(long, value_name = "DIR", conflicts_with = "verify_only")]
+    pub compare_remote: Option<String>,
+
+    /// leaves files already in the restore dir alone if they match the backup, so an
+    /// interrupted restore can be resumed. size compares the size, hash also the file hash
+    #[arg(long, value_enum, value_name = "CHECK", conflicts_with_all = ["verify_only", "metadata_only"])]
+    pub skip_existing: Option<SkipExisting>,
+
+    /// threads for passes that re-read and hash files already on disk (--compare-remote, --skip-existing hash),
+    /// defaults to --threads-rayon
+    #[arg(long, value_name = "N")]
+    pub checksum_parallelism: Option<usize>,
+
+    /// writes <file>.<ext> with the checksum of every restored file, checkable with sha256sum -c
+    #[arg(long)]
+    pub write_sidecar_hash: bool,
+
+    /// checksum for --write-sidecar-hash
+    #[arg(long, value_enum, default_value = "sha256")]
+    pub sidecar_algorithm: SidecarAlgorithm,
+
+    /// file extension for --write-sidecar-hash, defaults to the algorithm name
+    #[arg(long, value_name = "EXT")]
+    pub sidecar_extension: Option<String>,
+
+    /// stops before restoring anything if blocks are missing from the dblock volumes,
+    /// by default files with missing blocks fail one by one and the rest is restored
+    #[arg(long)]
+    pub abort_on_missing_volume: bool,
+
+    /// dry run: resolves every block of the selected files in the dblock index, lists the
+    /// missing ones per file and exits 1 if any are missing. Nothing is written
+    #[arg(long)]
+    pub check_blocks: bool,
+
+    /// reads the manifest of every dblock and exits 1 if its Blocksize, BlockHash or FileHash
+    /// differ from the dlist's, e.g. volumes of two backups mixed in one folder
+    #[arg(long)]
+    pub check_manifest_consistency: bool,
+
+    /// reads every block of every dblock volume and checks it hashes to its name (bitrot),
+    /// lists the ones that don't and exits 1 if any. Needs no restore dir, nothing is written
+    #[arg(long)]
+    pub verify_blocks: bool,
+
+    /// aborts the restore once N files have failed and were skipped
+    #[arg(long, value_name = "N")]
+    pub fail_fast_after: Option<usize>,
+
+    /// keeps restoring other files when one fails for any reason, lists the failed
+    /// files at the end and exits nonzero. Their partial content stays as <name>.partial
+    #[arg(long)]
+    pub continue_on_error: bool,
+
+    /// restores files with identical content once and reflinks the copies (Btrfs, XFS),
+    /// falls back to a normal restore where the filesystem can't
+    #[arg(long, conflicts_with = "write_sidecar_hash")]
+    pub reflink_dups: bool,
+
+    /// only sets mtime and permissions of files already in the restore dir, if their size
+    /// matches the backup. Reads no content blocks
+    #[arg(long, conflicts_with = "verify_only")]
+    pub metadata_only: bool,
+
+    /// prints the effective settings as JSON, including defaults and derived values, and exits
+    #[arg(long)]
+    pub print_config: bool,
+
     /// true to verify without writing files to disk
     #[arg(long)]
     pub verify_only: bool,
+
+    /// with --verify-only, stops at the first file that doesn't match its hash instead
+    /// of listing them all at the end
+    #[arg(long, requires = "verify_only")]
+    pub fail_fast: bool,
+
+    /// restores only files whose content blocks are all listed in FILE (one hash per line,
+    /// base64 or hex), reports files that are partially or not covered
+    #[arg(long, value_name = "FILE")]
+    pub blocks_file: Option<PathBuf>,
+
+    /// prints every entry of the chosen version (type, size and path) and exits,
+    /// without indexing the dblocks
+    #[arg(long)]
+    pub list_files: bool,
+
+    /// like --list-files, one JSON object per line
+    #[arg(long)]
+    pub list_files_json: bool,
+
+    /// restores only this file and its parent folders, path as in the dlist or
+    /// under the restore dir (C:\dir\f, C:/dir/f and C/dir/f are the same)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["include", "exclude"])]
+    pub only_path: Option<String>,
+
+    /// restores only the entries under this folder, path as with --only-path. They keep
+    /// their full path under the restore dir unless --strip-prefix
+    #[arg(long, value_name = "PREFIX")]
+    pub subtree: Option<String>,
+
+    /// with --subtree, leaves PREFIX out of restore paths: the entries under it go right
+    /// into the restore dir
+    #[arg(long, requires = "subtree", conflicts_with = "flatten")]
+    pub strip_prefix: bool,
+
+    /// extracts only this file (path as in the dlist) into --output, reading just the blocks it needs
+    #[arg(long, value_name = "PATH", requires = "output")]
+    pub single_file: Option<String>,
+
+    /// where --single-file writes the extracted bytes
+    #[arg(long, value_name = "FILE", requires = "single_file")]
+    pub output: Option<PathBuf>,
+
+    /// first byte --single-file extracts
+    #[arg(long, default_value_t = 0, value_name = "N", requires = "single_file")]
+    pub offset: u64,
+
+    /// bytes --single-file extracts, to the end of the file if not given
+    #[arg(long, value_name = "M", requires = "single_file")]
+    pub length: Option<u64>,
+
+    /// writes a row per restored file (path, size, hash, status, error) for auditing
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<String>,
+
+    /// format of --report
+    #[arg(long, value_enum, default_value = "csv", requires = "report")]
+    pub report_format: ReportFormat,
+
+    /// writes a JSON summary of the run (dlist, manifest, counts, elapsed time, failed files)
+    /// to PATH, also when the run fails
+    #[arg(long, value_name = "PATH")]
+    pub report_json: Option<PathBuf>,
+
+    /// writes a line per block read (time, thread, hash, volume, offset) for debugging slow restores
+    #[arg(long, value_name = "FILE")]
+    pub trace_file: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
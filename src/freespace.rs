@@ -0,0 +1,63 @@
+use crate::{dfileentry::FileEntry, flags::TriageOrder, FileEntries};
+use eyre::{Context, Result};
+use std::path::Path;
+
+/// Files are charged whole allocation units, a guess that fits common filesystems
+const ALLOCATION_UNIT: u64 = 4096;
+
+pub struct Triage {
+    pub entries: FileEntries,
+    /// Path and size of files left out for lack of space
+    pub skipped: Vec<(String, u64)>,
+}
+
+/// Free bytes on the filesystem that will hold restore_dir, which may not exist yet
+pub fn available_space_for(restore_dir: &Path) -> Result<u64> {
+    let existing = restore_dir
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("."));
+    fs2::available_space(existing).wrap_err_with(|| format!("free space of {:?}", existing))
+}
+
+fn on_disk_size(entry: &FileEntry) -> u64 {
+    entry.bytes_size().div_ceil(ALLOCATION_UNIT) * ALLOCATION_UNIT
+}
+
+/// Keeps files, in the given order, while they fit into budget bytes
+///
+/// Folders are always kept. A file that doesn't fit is skipped, later ones may still fit
+pub fn triage_to_free_space(entries: Vec<FileEntry>, budget: u64, order: TriageOrder) -> Triage {
+    let mut files: Vec<usize> = (0..entries.len())
+        .filter(|&i| entries[i].is_file())
+        .collect();
+    match order {
+        TriageOrder::Smallest => files.sort_by_key(|&i| entries[i].bytes_size()),
+        TriageOrder::Path => files.sort_by(|&a, &b| entries[a].path.cmp(&entries[b].path)),
+    }
+
+    let mut keep = vec![true; entries.len()];
+    let mut used = 0u64;
+    for i in files {
+        let cost = on_disk_size(&entries[i]);
+        if used + cost <= budget {
+            used += cost;
+        } else {
+            keep[i] = false;
+        }
+    }
+
+    let mut kept = Vec::with_capacity(entries.len());
+    let mut skipped = Vec::new();
+    for (entry, keep) in entries.into_iter().zip(keep) {
+        if keep {
+            kept.push(entry);
+        } else {
+            skipped.push((entry.path.clone(), entry.bytes_size()));
+        }
+    }
+    Triage {
+        entries: FileEntries { entries: kept },
+        skipped,
+    }
+}
@@ -0,0 +1,72 @@
+use md5::Md5;
+use sha1::Sha1;
+use sha2::digest::DynDigest;
+use sha2::Sha256;
+use std::fmt;
+use std::io::{self, Read};
+
+/// Hash algorithm named by the BlockHash or FileHash field of a manifest
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HashAlgo {
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+impl HashAlgo {
+    /// SHA384 and SHA512 aren't supported, block names are at most 32 bytes (BlockIdHash)
+    pub fn from_manifest(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "SHA256" => Some(Self::Sha256),
+            "SHA1" => Some(Self::Sha1),
+            "MD5" => Some(Self::Md5),
+            _ => None,
+        }
+    }
+
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha1 => 20,
+            Self::Md5 => 16,
+        }
+    }
+
+    pub fn hasher(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Self::Sha256 => Box::new(Sha256::default()),
+            Self::Sha1 => Box::new(Sha1::default()),
+            Self::Md5 => Box::new(Md5::default()),
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> Box<[u8]> {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Digest of everything reader returns
+    pub fn digest_reader(self, mut reader: impl Read) -> io::Result<Box<[u8]>> {
+        let mut hasher = self.hasher();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return Ok(hasher.finalize()),
+                Ok(n) => hasher.update(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha256 => "SHA256",
+            Self::Sha1 => "SHA1",
+            Self::Md5 => "MD5",
+        })
+    }
+}
@@ -1,7 +1,7 @@
 pub struct HexDisplayBytes<'a>(pub &'a [u8]);
 impl<'a> std::fmt::Display for HexDisplayBytes<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for byte in self.0.as_ref().iter() {
+        for byte in self.0.iter() {
             let (high, low) = byte2hex(*byte, HEX_CHARS_LOWER);
 
             write!(f, "{}{}", high as char, low as char)?;
@@ -14,7 +14,7 @@ impl<'a> std::fmt::Display for HexDisplayBytes<'a> {
 pub struct EscapeWholeString<'a>(pub &'a [u8]);
 impl<'a> std::fmt::Display for EscapeWholeString<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for byte in self.0.as_ref().iter() {
+        for byte in self.0.iter() {
             let (high, low) = byte2hex(*byte, HEX_CHARS_LOWER);
 
             write!(f, "\\x{}{}", high as char, low as char)?;
@@ -28,7 +28,7 @@ pub struct EscapeRawString<'a>(pub &'a [u8]);
 impl<'a> std::fmt::Display for EscapeRawString<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "\"")?;
-        for &b in self.0.as_ref().iter() {
+        for &b in self.0.iter() {
             escape_byte_maybe(f, b)?;
         }
         write!(f, "\"")?;
@@ -50,6 +50,28 @@ fn escape_byte_maybe(f: &mut std::fmt::Formatter<'_>, b: u8) -> std::fmt::Result
 
 const HEX_CHARS_LOWER: &[u8; 16] = b"0123456789abcdef";
 
+/// Inverse of HexDisplayBytes, accepts upper and lower case
+///
+/// None on odd length or non-hex characters
+pub fn hex2bytes(hex: &str) -> Option<Vec<u8>> {
+    let pairs = hex.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs
+        .map(|pair| Some(hex2nibble(pair[0])? << 4 | hex2nibble(pair[1])?))
+        .collect()
+}
+
+fn hex2nibble(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
 /// returns 2 chars representing byte in hex
 fn byte2hex(byte: u8, table: &[u8; 16]) -> (u8, u8) {
     let high = table[((byte & 0xf0) >> 4) as usize];
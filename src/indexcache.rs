@@ -0,0 +1,108 @@
+use crate::backend;
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+/// One indexed dblock, a line in the cache file
+#[derive(Serialize, Deserialize)]
+struct CachedVolume {
+    path: PathBuf,
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    /// Entry names (base64 block hashes) in zip order
+    names: Vec<String>,
+}
+
+/// Index of dblocks, appended one volume at a time while indexing
+///
+/// An interrupted run leaves the volumes indexed so far, the next run
+/// loads those and only opens the rest. A dblock whose size or mtime
+/// changed since is indexed again
+pub struct IndexCache {
+    loaded: HashMap<PathBuf, CachedVolume>,
+    file: Mutex<File>,
+}
+
+impl IndexCache {
+    /// Loads cache_path if it exists. A torn last line is cut off
+    pub fn open(cache_path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(cache_path)
+            .wrap_err_with(|| format!("open index cache {:?}", cache_path))?;
+
+        let mut loaded = HashMap::new();
+        let mut valid_len = 0u64;
+        let mut reader = BufReader::new(&mut file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n = reader
+                .read_line(&mut line)
+                .wrap_err_with(|| format!("read index cache {:?}", cache_path))?;
+            if n == 0 || !line.ends_with('\n') {
+                break;
+            }
+            let Ok(volume) = serde_json::from_str::<CachedVolume>(&line) else {
+                break;
+            };
+            valid_len += n as u64;
+            // Later lines replace earlier ones, a volume is appended again after it changed
+            loaded.insert(volume.path.clone(), volume);
+        }
+        file.set_len(valid_len)
+            .wrap_err_with(|| format!("truncate index cache {:?}", cache_path))?;
+
+        Ok(Self {
+            loaded,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Entry names of zip_path, if cached and the file is unchanged since
+    pub fn names(&self, zip_path: &Path) -> Option<&[String]> {
+        let cached = self.loaded.get(zip_path)?;
+        let (size, mtime_secs, mtime_nanos) = file_stamp(zip_path).ok()?;
+        (cached.size == size
+            && cached.mtime_secs == mtime_secs
+            && cached.mtime_nanos == mtime_nanos)
+            .then_some(cached.names.as_slice())
+    }
+
+    /// Appends an indexed volume, one write per line so parallel appends don't mix
+    pub fn append(&self, zip_path: &Path, names: Vec<String>) -> Result<()> {
+        let (size, mtime_secs, mtime_nanos) = file_stamp(zip_path)?;
+        let volume = CachedVolume {
+            path: zip_path.to_path_buf(),
+            size,
+            mtime_secs,
+            mtime_nanos,
+            names,
+        };
+        let mut line = serde_json::to_vec(&volume)?;
+        line.push(b'\n');
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(&line)
+            .wrap_err("append to index cache")?;
+        Ok(())
+    }
+}
+
+/// Size and mtime, to notice a dblock that changed after it was cached
+fn file_stamp(path: &Path) -> Result<(u64, u64, u32)> {
+    let stat = backend::stat(path).wrap_err_with(|| format!("stat {:?}", path))?;
+    let mtime = stat.modified.duration_since(UNIX_EPOCH)?;
+    Ok((stat.len, mtime.as_secs(), mtime.subsec_nanos()))
+}
@@ -0,0 +1,43 @@
+use crate::cli::EXIT_INTERRUPTED;
+use eyre::{Context, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C stop the restore once the files being written are done, a
+/// second Ctrl-C exits at once
+///
+/// Either way nothing is left half written under its final name, files cut
+/// off stay as <name>.partial and --skip-existing picks up the rest
+pub fn install_ctrlc_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        println!("\nInterrupted, finishing the files in progress. Ctrl-C again to quit now");
+    })
+    .wrap_err("install Ctrl-C handler")
+}
+
+/// True once Ctrl-C was pressed, restore_entry then starts no more entries
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Error of an entry not started because of Ctrl-C
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted by Ctrl-C")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+pub fn is_interrupted(err: &eyre::Report) -> bool {
+    err.chain()
+        .any(|cause| cause.downcast_ref::<Interrupted>().is_some())
+}
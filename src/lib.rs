@@ -0,0 +1,162 @@
+#![warn(rust_2018_idioms)]
+//! Restores Duplicati backups without Duplicati
+//!
+//! [restore] covers a plain restore or verify. The modules below it are the
+//! pieces the command line tool is built from: [parse_dlist_file] reads the file
+//! list of a backup version, [DFileDatabase] finds blocks in the dblocks and
+//! [restore_entry] writes one entry with them.
+
+mod assertmatches;
+mod backend;
+mod bestversion;
+mod blockarchive;
+mod blockcache;
+pub mod blockhash;
+pub mod blockmap;
+pub mod blockprovider;
+mod blocksfile;
+mod blocksink;
+mod blocktrace;
+mod bundle;
+mod bytesize;
+pub mod cli;
+mod completeness;
+pub mod crypto;
+pub mod database;
+pub mod dfileentry;
+pub mod dfiletype;
+mod dindex;
+pub mod dlist;
+pub mod filereader;
+pub mod flags;
+mod freespace;
+mod hashalgo;
+mod hexdisplay;
+mod indexcache;
+pub mod interrupt;
+mod listing;
+mod manifestcheck;
+pub mod metadata;
+mod metadataonly;
+pub mod mtime;
+pub mod pathcheck;
+mod pathfilter;
+pub mod pathstyle;
+pub mod progress;
+mod reflink;
+mod report;
+pub mod restoring;
+#[cfg(feature = "s3")]
+mod s3;
+mod sevenzarchive;
+#[cfg(feature = "sftp")]
+mod sftp;
+mod skipexisting;
+mod sorting;
+mod special;
+mod stripbom;
+mod symlinks;
+mod verifyblocks;
+mod versiondiff;
+mod ziparchive;
+
+pub use crate::blockprovider::BlockProvider;
+pub use crate::blocksink::{BlockSink, FileSink, MemoryBlockSink, PositionedFile};
+pub use crate::database::DFileDatabase;
+pub use crate::dlist::{parse_dlist_file, read_manifest, FileEntries};
+pub use crate::restoring::{restore_entry, RestoreParams, RestoreSummary};
+
+use crate::cli::{run, RunOutcome};
+use crate::flags::RestoreFlags;
+use clap::Parser;
+use eyre::{eyre, Result};
+use std::path::{Path, PathBuf};
+
+/// What [restore] restores, everything else is left at the command line defaults
+pub struct RestoreConfig {
+    /// Folder with the dlist, dblock and dindex files
+    pub backup_dir: PathBuf,
+    /// None only verifies the blocks, nothing is written
+    pub restore_dir: Option<PathBuf>,
+    /// For encrypted backups
+    pub passphrase: Option<String>,
+    /// Backup version, 0 is the newest
+    pub version: Option<usize>,
+    /// Globs as with --include
+    pub include: Vec<String>,
+    /// Globs as with --exclude
+    pub exclude: Vec<String>,
+    /// Size of the rayon thread pool, only the first restore of a process sets it up
+    pub threads: usize,
+}
+
+impl RestoreConfig {
+    /// Restores the newest version of everything with 4 threads
+    pub fn new(backup_dir: impl Into<PathBuf>, restore_dir: Option<PathBuf>) -> Self {
+        Self {
+            backup_dir: backup_dir.into(),
+            restore_dir,
+            passphrase: None,
+            version: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            threads: 4,
+        }
+    }
+
+    pub fn with_passphrase(mut self, passphrase: impl Into<String>) -> Self {
+        self.passphrase = Some(passphrase.into());
+        self
+    }
+
+    pub fn with_version(mut self, version: usize) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn with_include(mut self, glob: impl Into<String>) -> Self {
+        self.include.push(glob.into());
+        self
+    }
+
+    pub fn with_exclude(mut self, glob: impl Into<String>) -> Self {
+        self.exclude.push(glob.into());
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+}
+
+/// Restores or verifies a backup as the command line tool would with config as flags
+///
+/// Progress is printed to stdout. Returns the summary of what was restored, all
+/// zero if the filters left nothing
+pub fn restore(config: RestoreConfig) -> Result<RestoreSummary> {
+    let path_arg = |path: &Path| {
+        path.to_str()
+            .map(String::from)
+            .ok_or_else(|| eyre!("{:?} is not valid UTF-8", path))
+    };
+    let backup_dir = path_arg(&config.backup_dir)?;
+    let mut args =
+        RestoreFlags::try_parse_from(["rust-duplicati-restore", "--backup-dir", &backup_dir])?;
+    args.restore_dir = config.restore_dir.as_deref().map(path_arg).transpose()?;
+    args.verify_only = config.restore_dir.is_none();
+    args.passphrase = config.passphrase;
+    args.version = config.version;
+    args.include = config.include;
+    args.exclude = config.exclude;
+    args.threads_rayon = config.threads;
+
+    match run(args)? {
+        RunOutcome::Restored(summary) => Ok(summary),
+        RunOutcome::NothingToRestore => Ok(RestoreSummary::default()),
+        RunOutcome::Interrupted => Err(interrupt::Interrupted.into()),
+        RunOutcome::Finished | RunOutcome::PrintedConfig | RunOutcome::Listed => {
+            Err(eyre!("the restore ended without a summary"))
+        }
+    }
+}
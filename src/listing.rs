@@ -0,0 +1,45 @@
+use crate::{blockmap::block_hashes, database::DFileDatabase, dfileentry::FileEntry};
+use eyre::{Context, Result};
+use serde_json::json;
+
+/// Prints the entries of a dlist, `type<TAB>size<TAB>path` per line or one JSON object per line
+///
+/// With db every file is followed by its content block hashes (base64, as in the
+/// dlist), indented by a tab, or in a "blocks" array with json. Blocklists are read for that
+pub fn list_entries(entries: &[FileEntry], db: Option<&DFileDatabase>, json: bool) -> Result<()> {
+    let mut base64_buf = [0u8; 64];
+    for entry in entries {
+        let blocks = match db {
+            Some(db) if entry.is_file() => block_hashes(entry, db)
+                .wrap_err_with(|| format!("list blocks of {:?}", entry.path))?
+                .iter()
+                .map(|hash| hash.as_base64(&mut base64_buf).to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        if json {
+            let mut line = json!({
+                "path": entry.path,
+                "type": entry.file_type.kind(),
+                "size": entry.bytes_size(),
+            });
+            if db.is_some() && entry.is_file() {
+                line["blocks"] = json!(blocks);
+            }
+            println!("{}", line);
+            continue;
+        }
+
+        let kind = entry.file_type.kind();
+        if entry.is_file() {
+            println!("{}\t{}\t{}", kind, entry.bytes_size(), entry.path);
+        } else {
+            println!("{}\t-\t{}", kind, entry.path);
+        }
+        for block in &blocks {
+            println!("\t{}", block);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use crate::{database::DFileDatabase, stripbom::StripBom, ziparchive::ZipArchiveWrapper};
+use eyre::{eyre, Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::io::Read;
+
+/// What a dblock's manifest says that the dlist's doesn't
+enum VolumeManifest {
+    Matches,
+    /// Duplicati writes one into every volume, some tools leave it out
+    Missing,
+    Differs(Vec<String>),
+}
+
+/// Reads the manifest of every indexed dblock and errors if its Blocksize,
+/// BlockHash or FileHash differ from the dlist's (--check-manifest-consistency)
+///
+/// Volumes of two backups mixed in one folder would otherwise be read with the
+/// wrong block size and hash. Lists every differing dblock before failing
+pub fn check_manifest_consistency(db: &DFileDatabase) -> Result<()> {
+    let volumes = db.volumes();
+    println!("Checking the manifests of {} dblocks", volumes.len());
+    let pb = ProgressBar::new(volumes.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "[{elapsed_precise}] {wide_bar:40.cyan/blue} {pos:>7}/{len:7} {msg} [{eta_precise}]",
+            )?
+            .progress_chars("##-"),
+    );
+    let checked = volumes
+        .par_iter()
+        .map(|volume| {
+            let checked = check_volume(db, volume)
+                .wrap_err_with(|| format!("manifest of {:?}", volume.ziplocation.path));
+            pb.inc(1);
+            checked.map(|checked| (volume, checked))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    pb.finish_and_clear();
+
+    let (mut missing, mut differing) = (0, 0);
+    for (volume, checked) in checked {
+        match checked {
+            VolumeManifest::Matches => {}
+            VolumeManifest::Missing => missing += 1,
+            VolumeManifest::Differs(differences) => {
+                differing += 1;
+                println!(
+                    "manifest of {:?} differs from the dlist's: {}",
+                    volume.ziplocation.path,
+                    differences.join(", ")
+                );
+            }
+        }
+    }
+    if missing > 0 {
+        println!("warn: {} dblocks have no manifest, not checked", missing);
+    }
+    if differing > 0 {
+        return Err(eyre!(
+            "{} dblocks were written with other settings than the dlist, they may belong to another backup",
+            differing
+        ));
+    }
+    println!("The dblock manifests match the dlist's");
+    Ok(())
+}
+
+fn check_volume(db: &DFileDatabase, volume: &ZipArchiveWrapper) -> Result<VolumeManifest> {
+    let mut archive = volume.archive()?;
+    if !archive.contains_file_name("manifest") {
+        return Ok(VolumeManifest::Missing);
+    }
+    let mut manifest = String::new();
+    archive
+        .by_name("manifest")?
+        .reader
+        .read_to_string(&mut manifest)?;
+    let differences = db.manifest_differences(manifest.strip_bom().trim().as_bytes())?;
+    if differences.is_empty() {
+        Ok(VolumeManifest::Matches)
+    } else {
+        Ok(VolumeManifest::Differs(differences))
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{
+    blockhash::BlockIdHash, database::DFileDatabase, dfileentry::FileEntry, dfiletype::FileType,
+    mtime::filetime_from_dotnet_ticks, stripbom::StripBomBytes,
+};
+use eyre::{eyre, Context, Result};
+use filetime::FileTime;
+use std::{collections::HashMap, path::Path};
+
+/// Windows file attributes, flag names like "Directory, ReparsePoint" or a number
+pub const KEY_CORE_ATTRIBUTES: &str = "CoreAttributes";
+/// Modification time in .NET ticks, UTC
+pub const KEY_CORE_LAST_WRITETIME: &str = "CoreLastWritetime";
+
+/// Unix file type of special files: "fifo", "chardev" or "blockdev"
+///
+/// Not a key Duplicati writes: its unix metadata is unix:uid-gid-perm,
+/// unix:owner-name and unix:group-name, and it doesn't back up device nodes.
+/// This and KEY_UNIX_RDEV are only read if a backup has them, e.g. one
+/// written by another tool in Duplicati's format
+pub const KEY_UNIX_FILETYPE: &str = "unix:filetype";
+/// Device number of chardev and blockdev, "major:minor"
+pub const KEY_UNIX_RDEV: &str = "unix:rdev";
+/// Owner and mode bits, "uid-gid-perm" in decimal
+pub const KEY_UNIX_UID_GID_PERM: &str = "unix:uid-gid-perm";
+
+/// Metadata block of an entry, a json object of strings
+///
+/// e.g. {"CoreLastWritetime": "638...", "unix:uid-gid-perm": "1000-1000-420"}
+pub struct Metadata {
+    pub values: HashMap<String, String>,
+}
+
+impl Metadata {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let values =
+            serde_json::from_slice(bytes.strip_bom()).wrap_err("parse metadata block json")?;
+        Ok(Self { values })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Mode bits (e.g. 0o644) from unix:uid-gid-perm, None if not backed up on unix
+    pub fn unix_mode(&self) -> Result<Option<u32>> {
+        let Some(value) = self.get(KEY_UNIX_UID_GID_PERM) else {
+            return Ok(None);
+        };
+        let mode = value
+            .rsplit('-')
+            .next()
+            .and_then(|perm| perm.parse::<u32>().ok())
+            .ok_or_else(|| eyre!("bad {} value {:?}", KEY_UNIX_UID_GID_PERM, value))?;
+        Ok(Some(mode & 0o7777))
+    }
+
+    /// Directory junction, mount point or directory symlink, per CoreAttributes
+    pub fn is_directory_reparse_point(&self) -> bool {
+        const DIRECTORY: u32 = 0x10;
+        const REPARSE_POINT: u32 = 0x400;
+        let Some(value) = self.get(KEY_CORE_ATTRIBUTES) else {
+            return false;
+        };
+        let attributes = match value.trim().parse::<u32>() {
+            Ok(bits) => bits,
+            Err(_) => value.split(',').fold(0, |bits, name| match name.trim() {
+                "Directory" => bits | DIRECTORY,
+                "ReparsePoint" => bits | REPARSE_POINT,
+                _ => bits,
+            }),
+        };
+        attributes & (DIRECTORY | REPARSE_POINT) == DIRECTORY | REPARSE_POINT
+    }
+
+    /// From CoreLastWritetime, None if the key is missing
+    pub fn last_write_time(&self) -> Result<Option<FileTime>> {
+        let Some(value) = self.get(KEY_CORE_LAST_WRITETIME) else {
+            return Ok(None);
+        };
+        let ticks = value
+            .parse::<i64>()
+            .map_err(|_| eyre!("bad {} value {:?}", KEY_CORE_LAST_WRITETIME, value))?;
+        Ok(Some(filetime_from_dotnet_ticks(ticks)))
+    }
+
+    /// Fifo or Device if the metadata says so, None for anything else
+    ///
+    /// ```
+    /// use rust_duplicati_restore::dfiletype::FileType;
+    /// use rust_duplicati_restore::metadata::Metadata;
+    ///
+    /// let parse = |json: &str| Metadata::parse(json.as_bytes()).unwrap().special_file_type();
+    /// let fifo = parse(r#"{"unix:filetype": "fifo"}"#).unwrap();
+    /// assert_eq!(fifo, Some(FileType::Fifo));
+    /// let null = parse(r#"{"unix:filetype": "chardev", "unix:rdev": "1:3"}"#).unwrap();
+    /// let device = FileType::Device { block: false, major: 1, minor: 3 };
+    /// assert_eq!(null, Some(device));
+    /// let sda = parse(r#"{"unix:filetype": "blockdev", "unix:rdev": "8:0"}"#).unwrap();
+    /// assert!(matches!(sda, Some(FileType::Device { block: true, major: 8, minor: 0 })));
+    ///
+    /// assert_eq!(parse(r#"{"unix:filetype": "socket"}"#).unwrap(), None);
+    /// assert_eq!(parse(r#"{"unix:uid-gid-perm": "0-0-420"}"#).unwrap(), None);
+    /// assert!(parse(r#"{"unix:filetype": "chardev"}"#).is_err());
+    /// assert!(parse(r#"{"unix:filetype": "chardev", "unix:rdev": "1"}"#).is_err());
+    /// assert!(parse(r#"{"unix:filetype": "blockdev", "unix:rdev": "8:x"}"#).is_err());
+    /// ```
+    pub fn special_file_type(&self) -> Result<Option<FileType>> {
+        let block = match self.get(KEY_UNIX_FILETYPE) {
+            Some("fifo") => return Ok(Some(FileType::Fifo)),
+            Some("chardev") => false,
+            Some("blockdev") => true,
+            _ => return Ok(None),
+        };
+        let rdev = self
+            .get(KEY_UNIX_RDEV)
+            .ok_or_else(|| eyre!("device without {}", KEY_UNIX_RDEV))?;
+        let (major, minor) = rdev
+            .split_once(':')
+            .and_then(|(major, minor)| Some((major.parse().ok()?, minor.parse().ok()?)))
+            .ok_or_else(|| eyre!("bad {} value {:?}", KEY_UNIX_RDEV, rdev))?;
+        Ok(Some(FileType::Device {
+            block,
+            major,
+            minor,
+        }))
+    }
+}
+
+/// Sets the mode bits, a no-op off unix
+pub fn set_unix_mode(path: &Path, mode: u32) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .wrap_err_with(|| format!("set mode {:o} of {:?}", mode, path))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+/// Reads and parses a metadata block, None if it's in no indexed dblock
+pub fn fetch_metadata(
+    db: &DFileDatabase,
+    hash: &BlockIdHash,
+    buf: &mut Vec<u8>,
+) -> Result<Option<Metadata>> {
+    buf.clear();
+    let found = db
+        .get_content_block(hash, buf)
+        .wrap_err_with(|| format!("read metadata block {}", hash))?;
+    if found.is_none() {
+        return Ok(None);
+    }
+    Metadata::parse(buf).map(Some)
+}
+
+/// Modification time from the metadata block of entry, None if the block or the key is missing
+pub fn fetch_last_write_time(db: &DFileDatabase, entry: &FileEntry) -> Result<Option<FileTime>> {
+    let Some(metahash) = BlockIdHash::from_base64(&entry.metahash) else {
+        return Ok(None);
+    };
+    match fetch_metadata(db, &metahash, &mut Vec::new())? {
+        Some(metadata) => metadata.last_write_time(),
+        None => Ok(None),
+    }
+}
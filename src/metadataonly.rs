@@ -0,0 +1,99 @@
+use crate::{
+    blockhash::BlockIdHash,
+    dfileentry::FileEntry,
+    dfiletype::FileType,
+    flags::TimeSource,
+    metadata::{fetch_metadata, set_unix_mode},
+    mtime::{set_file_mtime, set_file_mtime_from_backup},
+    restoring::{calculate_path, RestoreParams},
+};
+use eyre::{Context, Result};
+use rayon::prelude::*;
+use std::{io::ErrorKind, path::Path};
+
+/// What --metadata-only did with the files of the dlist
+#[derive(Default)]
+pub struct MetadataOnlyCounts {
+    pub applied: usize,
+    pub missing: usize,
+    pub size_mismatch: usize,
+}
+
+enum Outcome {
+    Applied,
+    Missing,
+    SizeMismatch,
+}
+
+/// Sets mtime and mode bits of files already in the restore dir, reads no content blocks
+///
+/// A file is only touched if its size matches the backup, others are skipped with a warning
+pub fn restore_metadata_only(
+    entries: &[FileEntry],
+    params: &RestoreParams<'_>,
+) -> Result<MetadataOnlyCounts> {
+    let outcomes: Vec<Outcome> = entries
+        .par_iter()
+        .filter(|e| e.is_file())
+        .filter_map(|e| calculate_path(e, params).map(|(path, _)| (e, path)))
+        .map_init(Vec::new, |buf, (entry, path)| {
+            apply_metadata(entry, &path, params, buf)
+                .wrap_err_with(|| format!("apply metadata to {:?}", path))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut counts = MetadataOnlyCounts::default();
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Applied => counts.applied += 1,
+            Outcome::Missing => counts.missing += 1,
+            Outcome::SizeMismatch => counts.size_mismatch += 1,
+        }
+    }
+    Ok(counts)
+}
+
+fn apply_metadata(
+    entry: &FileEntry,
+    path: &Path,
+    params: &RestoreParams<'_>,
+    buf: &mut Vec<u8>,
+) -> Result<Outcome> {
+    let FileType::File { size, time, .. } = &entry.file_type else {
+        return Ok(Outcome::Missing);
+    };
+    let on_disk = match std::fs::metadata(path) {
+        Ok(on_disk) => on_disk,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            println!("warn: not on disk, skipping {:?}", path);
+            return Ok(Outcome::Missing);
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if on_disk.len() != (*size).max(0) as u64 {
+        println!(
+            "warn: size is {} instead of {}, skipping {:?}",
+            on_disk.len(),
+            size,
+            path
+        );
+        return Ok(Outcome::SizeMismatch);
+    }
+
+    let mut write_time = None;
+    if let Some(metahash) = BlockIdHash::from_base64(&entry.metahash) {
+        if let Some(metadata) = fetch_metadata(&params.db, &metahash, buf)? {
+            if let Some(mode) = metadata.unix_mode()? {
+                set_unix_mode(path, mode)?;
+            }
+            if params.mtime_source == TimeSource::Metadata {
+                write_time = metadata.last_write_time()?;
+            }
+        }
+    }
+    match write_time {
+        Some(ft) => set_file_mtime(path, ft)?,
+        None => set_file_mtime_from_backup(path, time)?,
+    }
+    Ok(Outcome::Applied)
+}
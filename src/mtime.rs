@@ -0,0 +1,41 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use eyre::{eyre, Context, Result};
+use filetime::FileTime;
+use std::path::Path;
+
+/// Parses the `time` field of a dlist entry, keeping fractional seconds
+///
+/// Accepts RFC3339 as well as Duplicati's compact `20230102T030405Z` form
+pub fn parse_backup_time(time: &str) -> Result<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(time) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(time, "%Y%m%dT%H%M%S%.fZ")
+        .map(|t| DateTime::<Utc>::from_naive_utc_and_offset(t, Utc))
+        .map_err(|_| eyre!("unrecognized backup time format: {:?}", time))
+}
+
+/// .NET ticks (100ns since 0001-01-01 UTC) at the unix epoch
+const DOTNET_TICKS_AT_UNIX_EPOCH: i64 = 621_355_968_000_000_000;
+
+/// Converts .NET ticks, as Duplicati writes them into metadata blocks
+pub fn filetime_from_dotnet_ticks(ticks: i64) -> FileTime {
+    let since_epoch = ticks - DOTNET_TICKS_AT_UNIX_EPOCH;
+    FileTime::from_unix_time(
+        since_epoch.div_euclid(10_000_000),
+        (since_epoch.rem_euclid(10_000_000) * 100) as u32,
+    )
+}
+
+/// Sets mtime with nanosecond precision, the filesystem rounds if it has to
+pub fn set_file_mtime_from_backup(path: &Path, time: &str) -> Result<()> {
+    let t = parse_backup_time(time)?;
+    let ft = FileTime::from_unix_time(t.timestamp(), t.timestamp_subsec_nanos());
+    set_file_mtime(path, ft)
+}
+
+pub fn set_file_mtime(path: &Path, ft: FileTime) -> Result<()> {
+    filetime::set_file_mtime(path, ft).wrap_err_with(|| format!("set mtime of {:?}", path))?;
+
+    Ok(())
+}
@@ -0,0 +1,381 @@
+use crate::{dfileentry::FileEntry, hexdisplay::HexDisplayBytes, pathstyle::PathStyle};
+use eyre::{eyre, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+};
+
+const WINDOWS_RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Longest file name in bytes on most filesystems (ext4, btrfs, xfs; NTFS counts 255 UTF-16 units)
+///
+/// UTF-8 never takes fewer bytes than UTF-16 units, so the byte limit is safe everywhere
+pub const NAME_MAX: usize = 255;
+/// Hex digits of the name hash kept by truncate_long_name
+const TRUNCATE_HASH_LEN: usize = 8;
+/// Written to the restore dir by --flatten, flattened name -> dlist path
+pub const FLATTEN_MAP_NAME: &str = "duplicati-flatten-map.json";
+
+/// What renaming fixes
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Fix {
+    /// Everything the target rejects, --sanitize-paths
+    All,
+    /// Only names over NAME_MAX, --truncate-long-names
+    LongNames,
+}
+
+/// How a name that is taken gets renamed, e.g. "{name} ({n}){ext}" (--conflict-suffix)
+///
+/// {name} is the file stem, {ext} the extension with its dot or nothing, {n} counts from 1
+#[derive(Clone)]
+pub struct ConflictSuffix {
+    template: String,
+}
+
+impl ConflictSuffix {
+    pub fn parse(template: &str) -> Result<Self> {
+        if !template.contains("{n}") {
+            return Err(eyre!("conflict suffix {:?} has no {{n}}", template));
+        }
+        Ok(Self {
+            template: template.to_string(),
+        })
+    }
+
+    fn apply(&self, candidate: &Path, n: usize) -> PathBuf {
+        let stem = candidate
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let ext = candidate
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        let name = self
+            .template
+            .replace("{name}", &stem)
+            .replace("{ext}", &ext)
+            .replace("{n}", &n.to_string());
+        candidate.with_file_name(name)
+    }
+}
+
+impl Default for ConflictSuffix {
+    fn default() -> Self {
+        Self {
+            template: "{name} ({n}){ext}".to_string(),
+        }
+    }
+}
+
+pub struct PathProblem {
+    /// Path as stored in the dlist
+    pub path: String,
+    pub reason: &'static str,
+    pub suggested: PathBuf,
+}
+
+/// Why the target filesystem would reject this file name, None if it's fine
+pub fn component_problem(name: &str, windows: bool) -> Option<&'static str> {
+    if name.contains('\0') {
+        return Some("contains NUL byte");
+    }
+    if let Some(problem) = long_name_problem(name) {
+        return Some(problem);
+    }
+    if !windows {
+        return None;
+    }
+    if name.chars().any(|c| (c as u32) < 32) {
+        return Some("contains control character");
+    }
+    if name.contains(WINDOWS_RESERVED_CHARS) {
+        return Some("contains one of <>:\"|?*");
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("ends with dot or space");
+    }
+    let stem = name.split('.').next().unwrap_or(name);
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(stem))
+    {
+        return Some("reserved device name");
+    }
+    None
+}
+
+pub fn long_name_problem(name: &str) -> Option<&'static str> {
+    if name.len() > NAME_MAX {
+        return Some("name longer than 255 bytes");
+    }
+    None
+}
+
+/// Cuts a name to NAME_MAX bytes, keeping the extension
+///
+/// A hash of the full name goes before the extension, so names sharing a long prefix stay unique
+pub fn truncate_long_name(name: &str) -> String {
+    if name.len() <= NAME_MAX {
+        return name.to_string();
+    }
+    let digest = Sha256::digest(name.as_bytes());
+    let hash = HexDisplayBytes(&digest[..]).to_string();
+    let suffix = format!("~{}", &hash[..TRUNCATE_HASH_LEN]);
+    let (stem, ext) = match name.rfind('.') {
+        // Extensions this long are not really extensions
+        Some(dot) if dot > 0 && name.len() - dot <= 16 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut keep = NAME_MAX - suffix.len() - ext.len();
+    while !stem.is_char_boundary(keep) {
+        keep -= 1;
+    }
+    format!("{}{}{}", &stem[..keep], suffix, ext)
+}
+
+/// Replaces everything the target would reject with '_', then truncates long names
+pub fn sanitize_component(name: &str, windows: bool) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            let bad =
+                c == '\0' || (windows && ((c as u32) < 32 || WINDOWS_RESERVED_CHARS.contains(&c)));
+            if bad {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if windows {
+        let trimmed = out.trim_end_matches(['.', ' ']);
+        if trimmed.len() != out.len() {
+            out = format!("{trimmed}_");
+        }
+        if component_problem(&truncate_long_name(&out), windows).is_some() {
+            out.insert(0, '_');
+        }
+    }
+    truncate_long_name(&out)
+}
+
+fn first_problem(relative: &Path, windows: bool, fix: Fix) -> Option<&'static str> {
+    relative.components().find_map(|c| match c {
+        Component::Normal(name) => {
+            let name = name.to_string_lossy();
+            match fix {
+                Fix::All => component_problem(&name, windows),
+                Fix::LongNames => long_name_problem(&name),
+            }
+        }
+        _ => None,
+    })
+}
+
+fn fix_component(name: &str, windows: bool, fix: Fix) -> String {
+    match fix {
+        Fix::All => sanitize_component(name, windows),
+        Fix::LongNames => truncate_long_name(name),
+    }
+}
+
+fn sanitize_path(relative: &Path, windows: bool, fix: Fix) -> PathBuf {
+    relative
+        .components()
+        .map(|c| match c {
+            Component::Normal(name) => {
+                PathBuf::from(fix_component(&name.to_string_lossy(), windows, fix))
+            }
+            other => PathBuf::from(other.as_os_str()),
+        })
+        .collect()
+}
+
+/// Key of a path in a claimed set, case-folded where the target ignores case
+fn claim_key(path: &Path, fold_case: bool) -> PathBuf {
+    if fold_case {
+        PathBuf::from(path.to_string_lossy().to_lowercase())
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Applies suffix with n = 1, 2, ... until the name is free
+fn unclaimed_name(
+    candidate: PathBuf,
+    claimed: &HashSet<PathBuf>,
+    suffix: &ConflictSuffix,
+    fold_case: bool,
+) -> PathBuf {
+    if !claimed.contains(&claim_key(&candidate, fold_case)) {
+        return candidate;
+    }
+    (1..)
+        .map(|n| suffix.apply(&candidate, n))
+        .find(|p| !claimed.contains(&claim_key(p, fold_case)))
+        .expect("some suffix is free")
+}
+
+/// Fixed names for every entry with a problem, collisions get a numbered suffix
+///
+/// Parents are renamed before children, so a renamed folder keeps its files
+pub fn sanitized_renames(
+    entries: &[FileEntry],
+    style: PathStyle,
+    fix: Fix,
+    suffix: &ConflictSuffix,
+) -> HashMap<String, PathBuf> {
+    let windows = style.target_windows;
+    let mut relatives: Vec<(&str, PathBuf)> = entries
+        .iter()
+        .map(|e| (e.path.as_str(), style.relative_target_path(&e.path)))
+        .collect();
+    relatives.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut claimed: HashSet<PathBuf> = relatives
+        .iter()
+        .filter(|(_, rel)| first_problem(rel, windows, fix).is_none())
+        .map(|(_, rel)| rel.clone())
+        .collect();
+    // relative dir before -> after renaming
+    let mut renamed_dirs: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut renames = HashMap::new();
+    for (dfile_path, rel) in relatives {
+        let parent = rel.parent().unwrap_or(Path::new(""));
+        let new_parent = renamed_dirs.get(parent).cloned();
+        let needs_rename = first_problem(&rel, windows, fix).is_some();
+        if new_parent.is_none() && !needs_rename {
+            continue;
+        }
+        let new_parent = new_parent.unwrap_or_else(|| sanitize_path(parent, windows, fix));
+        let name = rel
+            .file_name()
+            .map(|n| fix_component(&n.to_string_lossy(), windows, fix))
+            .unwrap_or_default();
+        let target = unclaimed_name(new_parent.join(name), &claimed, suffix, false);
+        claimed.insert(target.clone());
+        renamed_dirs.insert(rel.clone(), target.clone());
+        renames.insert(dfile_path.to_string(), target);
+    }
+    renames
+}
+
+/// Lists every entry the target filesystem would reject, with the name --sanitize-paths would use
+pub fn find_path_problems(entries: &[FileEntry], style: PathStyle) -> Vec<PathProblem> {
+    let renames = sanitized_renames(entries, style, Fix::All, &ConflictSuffix::default());
+    let mut problems: Vec<PathProblem> = entries
+        .iter()
+        .filter_map(|e| {
+            let rel = style.relative_target_path(&e.path);
+            let reason = first_problem(&rel, style.target_windows, Fix::All)?;
+            let suggested = renames.get(&e.path).cloned().unwrap_or(rel);
+            Some(PathProblem {
+                path: e.path.clone(),
+                reason,
+                suggested,
+            })
+        })
+        .collect();
+    problems.sort_by(|a, b| a.path.cmp(&b.path));
+    problems
+}
+
+/// Renames entries whose target path is already taken, adds them to renames
+///
+/// Catches what renaming made collide and, on a case-insensitive target, names that
+/// differ only in case. Folders claim their paths first and are never renamed, same
+/// folders merge. Runs before restoring, so parallel writes can't pick the same name.
+/// Files already in the restore dir aren't looked at, they get overwritten.
+/// Returns how many entries were renamed
+pub fn conflict_renames(
+    entries: &[FileEntry],
+    style: PathStyle,
+    renames: &mut HashMap<String, PathBuf>,
+    suffix: &ConflictSuffix,
+) -> usize {
+    let fold_case = style.target_windows;
+    let target_of = |e: &FileEntry| {
+        renames
+            .get(&e.path)
+            .cloned()
+            .unwrap_or_else(|| style.relative_target_path(&e.path))
+    };
+    let mut claimed: HashSet<PathBuf> = entries
+        .iter()
+        .filter(|e| e.is_folder())
+        .map(|e| claim_key(&target_of(e), fold_case))
+        .collect();
+    let mut others: Vec<(&FileEntry, PathBuf)> = entries
+        .iter()
+        .filter(|e| !e.is_folder())
+        .map(|e| (e, target_of(e)))
+        .collect();
+    others.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+
+    let mut renamed = Vec::new();
+    for (entry, target) in others {
+        let free = unclaimed_name(target.clone(), &claimed, suffix, fold_case);
+        claimed.insert(claim_key(&free, fold_case));
+        if free != target {
+            renamed.push((entry.path.clone(), free));
+        }
+    }
+    let count = renamed.len();
+    renames.extend(renamed);
+    count
+}
+
+/// A name directly under the restore dir for every entry but folders, for --flatten
+///
+/// The components of the target path are sanitized and joined with '_', e.g.
+/// "C/Users/me/a.txt" -> "C_Users_me_a.txt". Names that collide, also with
+/// FLATTEN_MAP_NAME, get a numbered suffix
+pub fn flattened_renames(
+    entries: &[FileEntry],
+    style: PathStyle,
+    suffix: &ConflictSuffix,
+) -> HashMap<String, PathBuf> {
+    let windows = style.target_windows;
+    let mut others: Vec<(&str, PathBuf)> = entries
+        .iter()
+        .filter(|e| !e.is_folder())
+        .map(|e| (e.path.as_str(), style.relative_target_path(&e.path)))
+        .collect();
+    others.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut claimed = HashSet::from([claim_key(Path::new(FLATTEN_MAP_NAME), windows)]);
+    let mut renames = HashMap::new();
+    for (dfile_path, rel) in others {
+        let joined = rel
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("_");
+        let name = PathBuf::from(sanitize_component(&joined, windows));
+        let target = unclaimed_name(name, &claimed, suffix, windows);
+        claimed.insert(claim_key(&target, windows));
+        renames.insert(dfile_path.to_string(), target);
+    }
+    renames
+}
+
+/// dlist paths with a component over NAME_MAX, these fail with ENAMETOOLONG when restored
+pub fn find_long_names(entries: &[FileEntry], style: PathStyle) -> Vec<&str> {
+    entries
+        .iter()
+        .filter(|e| {
+            let rel = style.relative_target_path(&e.path);
+            first_problem(&rel, false, Fix::LongNames).is_some()
+        })
+        .map(|e| e.path.as_str())
+        .collect()
+}
@@ -0,0 +1,159 @@
+use crate::{dfileentry::FileEntry, pathstyle::PathStyle, FileEntries};
+use eyre::{eyre, Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::{Path, PathBuf};
+
+/// --include and --exclude globs, matched against the path under the restore dir
+///
+/// Paths use '/' whatever the source OS, e.g. "C/Users/me/Documents/a.txt" for
+/// "C:\Users\me\Documents\a.txt". `*` stays within a directory, `**` crosses them.
+/// A matching directory matches everything below it
+pub struct PathFilter {
+    /// None to include everything not excluded
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    /// None if there are no globs, nothing to filter
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Option<Self>> {
+        if include.is_empty() && exclude.is_empty() {
+            return Ok(None);
+        }
+        let include = match include.is_empty() {
+            true => None,
+            false => Some(build_glob_set(include).wrap_err("--include")?),
+        };
+        let exclude = build_glob_set(exclude).wrap_err("--exclude")?;
+        Ok(Some(Self { include, exclude }))
+    }
+
+    /// Keeps the entries that are included and not excluded, directly or by a parent
+    pub fn apply(&self, file_entries: FileEntries, path_style: PathStyle) -> FileEntries {
+        let entries: Vec<FileEntry> = file_entries
+            .entries
+            .into_iter()
+            .filter(|entry| self.matches(&path_style.relative_target_path(&entry.path)))
+            .collect();
+        FileEntries { entries }
+    }
+
+    fn matches(&self, relative: &Path) -> bool {
+        let mut included = self.include.is_none();
+        let mut prefix = String::new();
+        for component in relative.iter() {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(&component.to_string_lossy());
+            if self.exclude.is_match(&prefix) {
+                return false;
+            }
+            included |= self
+                .include
+                .as_ref()
+                .is_some_and(|set| set.is_match(&prefix));
+        }
+        included
+    }
+}
+
+/// Keeps the entry at path and the folders above it, for --only-path
+///
+/// path is compared after the same normalization as restore paths, so "C:\dir\f",
+/// "C:/dir/f" and "C/dir/f" all find C:\dir\f. Errors listing the entries
+/// sharing the longest prefix with path if none matches
+pub fn only_path(
+    file_entries: FileEntries,
+    path: &str,
+    path_style: PathStyle,
+) -> Result<FileEntries> {
+    let wanted = normalized(path, path_style);
+    let found = file_entries
+        .entries
+        .iter()
+        .any(|entry| normalized(&entry.path, path_style) == wanted);
+    if !found {
+        print_closest(&file_entries.entries, &wanted, path_style);
+        return Err(eyre!("{:?} is not in the backup", path));
+    }
+
+    let entries: Vec<FileEntry> = file_entries
+        .entries
+        .into_iter()
+        .filter(|entry| {
+            let relative = normalized(&entry.path, path_style);
+            relative == wanted || (entry.is_folder() && wanted.starts_with(&relative))
+        })
+        .collect();
+    Ok(FileEntries { entries })
+}
+
+/// Keeps the entries at prefix and below it, for --subtree
+///
+/// prefix is normalized like --only-path, and matches whole path components:
+/// "C:\dir" keeps C:\dir\f but not C:\dir2. Returns the normalized prefix too,
+/// --strip-prefix removes it from restore paths. Errors if nothing is under it
+pub fn subtree(
+    file_entries: FileEntries,
+    prefix: &str,
+    path_style: PathStyle,
+) -> Result<(FileEntries, PathBuf)> {
+    let root = normalized(prefix, path_style);
+    let entries: Vec<FileEntry> = file_entries
+        .entries
+        .into_iter()
+        .filter(|entry| normalized(&entry.path, path_style).starts_with(&root))
+        .collect();
+    if entries.is_empty() {
+        return Err(eyre!(
+            "nothing in the backup is under --subtree {:?}",
+            prefix
+        ));
+    }
+    Ok((FileEntries { entries }, root))
+}
+
+/// Restore path of a dlist path or a user given one, with either separator
+fn normalized(path: &str, path_style: PathStyle) -> PathBuf {
+    let separator = if path_style.source_windows { '\\' } else { '/' };
+    let unified: String = path
+        .chars()
+        .map(|c| if c == '/' || c == '\\' { separator } else { c })
+        .collect();
+    path_style.relative_target_path(&unified)
+}
+
+fn print_closest(entries: &[FileEntry], wanted: &Path, path_style: PathStyle) {
+    let wanted = wanted.to_string_lossy();
+    let common = |entry: &FileEntry| {
+        let candidate = normalized(&entry.path, path_style);
+        let candidate = candidate.to_string_lossy();
+        candidate
+            .chars()
+            .zip(wanted.chars())
+            .take_while(|(a, b)| a == b)
+            .count()
+    };
+    let Some(longest) = entries.iter().map(common).max() else {
+        return;
+    };
+    println!("No entry at {:?}, closest by prefix:", wanted);
+    for entry in entries.iter().filter(|e| common(e) == longest).take(10) {
+        println!("  {}", entry.path);
+    }
+}
+
+fn build_glob_set(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        let glob = glob.trim_end_matches('/');
+        builder.add(
+            GlobBuilder::new(glob)
+                .literal_separator(true)
+                .build()
+                .wrap_err_with(|| format!("bad glob {:?}", glob))?,
+        );
+    }
+    Ok(builder.build()?)
+}
@@ -0,0 +1,91 @@
+use crate::{dfileentry::FileEntry, flags::PathOs};
+use std::path::PathBuf;
+
+/// How dlist paths map to paths under the restore dir
+#[derive(Clone, Copy, Debug)]
+pub struct PathStyle {
+    /// dlist paths use '\' and may start with a drive letter
+    pub source_windows: bool,
+    /// Target filesystem has Windows naming rules
+    pub target_windows: bool,
+}
+
+impl PathStyle {
+    /// Auto source looks at the dlist paths, auto target is the OS we run on
+    pub fn new(source: PathOs, target: PathOs, entries: &[FileEntry]) -> Self {
+        let source_windows = match source {
+            PathOs::Windows => true,
+            PathOs::Unix => false,
+            PathOs::Auto => entries.iter().any(|e| looks_like_windows_path(&e.path)),
+        };
+        let target_windows = match target {
+            PathOs::Windows => true,
+            PathOs::Unix => false,
+            PathOs::Auto => cfg!(windows),
+        };
+        Self {
+            source_windows,
+            target_windows,
+        }
+    }
+
+    /// Path of a backed up entry relative to the restore dir, before any renaming
+    ///
+    /// The source root is dropped, so the result never escapes the restore dir:
+    /// "C:\dir\f" becomes C/dir/f, "\\server\share\f" server/share/f and "/home/f" home/f
+    pub fn relative_target_path(&self, dfile_path: &str) -> PathBuf {
+        let separator = if self.source_windows { '\\' } else { '/' };
+        dfile_path
+            .split(separator)
+            .filter(|c| !c.is_empty() && *c != "." && *c != "..")
+            .enumerate()
+            .map(|(i, c)| {
+                if self.source_windows && i == 0 && is_drive(c) {
+                    // "C:" becomes a "C" directory
+                    c[..1].to_string()
+                } else if !self.source_windows && self.target_windows {
+                    // A unix name may contain '\', which would split it on Windows
+                    c.replace('\\', "_")
+                } else {
+                    c.to_string()
+                }
+            })
+            .collect()
+    }
+}
+
+fn is_drive(component: &str) -> bool {
+    let b = component.as_bytes();
+    b.len() == 2 && b[0].is_ascii_alphabetic() && b[1] == b':'
+}
+
+/// Drive letter (C:\) or UNC (\\server) path
+fn looks_like_windows_path(path: &str) -> bool {
+    let drive = path.get(..2).map(is_drive).unwrap_or(false)
+        && matches!(path.as_bytes().get(2), None | Some(b'\\'));
+    drive || path.starts_with("\\\\")
+}
+
+/// restore_dir in the \\?\ form on Windows, so paths under it can exceed MAX_PATH (260)
+///
+/// The prefix turns off Win32 path normalization, so the dir is made absolute
+/// ('/', '.' and '..' resolved) first. Other platforms get it back unchanged
+pub fn extended_length_path(restore_dir: &str) -> std::io::Result<String> {
+    if !cfg!(windows) {
+        return Ok(restore_dir.to_string());
+    }
+    let absolute = std::path::absolute(restore_dir)?;
+    Ok(with_extended_prefix(&absolute.to_string_lossy()))
+}
+
+/// "C:\x" -> "\\?\C:\x", "\\server\share" -> "\\?\UNC\server\share",
+/// already prefixed and device paths ("\\.\") stay as they are
+fn with_extended_prefix(absolute: &str) -> String {
+    if absolute.starts_with(r"\\?\") || absolute.starts_with(r"\\.\") {
+        return absolute.to_string();
+    }
+    match absolute.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{}", unc),
+        None => format!(r"\\?\{}", absolute),
+    }
+}
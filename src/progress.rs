@@ -0,0 +1,91 @@
+use crate::dfileentry::FileEntry;
+use pbr::{ProgressBar, Units};
+use std::io::Stdout;
+use std::sync::Mutex;
+
+/// Receives the progress of a restore, called from all restoring threads
+///
+/// Folders are restored first, then files. finish ends each of the two phases
+pub trait RestoreProgress: Send + Sync {
+    /// A folder was created (or checked, when only verifying)
+    fn on_folder(&self);
+
+    /// Content bytes of a file were written (or verified)
+    fn on_file_bytes(&self, n: u64);
+
+    /// A file was restored completely
+    fn on_file(&self, _entry: &FileEntry) {}
+
+    /// The current phase is done
+    fn finish(&self);
+}
+
+/// The --progress-bar of the command line tool, a pbr bar per phase
+pub struct PbrProgress {
+    folder_count: u64,
+    file_total: u64,
+    /// Counts the predicted time of finished files instead of bytes as they are written
+    predicted: bool,
+    bar: Mutex<Option<PhaseBar>>,
+}
+
+enum PhaseBar {
+    Folders(ProgressBar<Stdout>),
+    Files(ProgressBar<Stdout>),
+}
+
+impl PbrProgress {
+    /// file_total is in bytes, or the sum of FileEntry::predicted_time if predicted
+    pub fn new(folder_count: usize, file_total: u64, predicted: bool) -> Self {
+        Self {
+            folder_count: folder_count as u64,
+            file_total,
+            predicted,
+            bar: Mutex::new(None),
+        }
+    }
+
+    fn add_to_files_bar(&self, n: u64) {
+        let mut bar = self.bar.lock().unwrap();
+        if !matches!(*bar, Some(PhaseBar::Files(_))) {
+            let mut files = ProgressBar::new(self.file_total);
+            files.set_units(Units::Bytes);
+            *bar = Some(PhaseBar::Files(files));
+        }
+        if let Some(PhaseBar::Files(files)) = bar.as_mut() {
+            files.add(n);
+        }
+    }
+}
+
+impl RestoreProgress for PbrProgress {
+    fn on_folder(&self) {
+        let mut bar = self.bar.lock().unwrap();
+        if !matches!(*bar, Some(PhaseBar::Folders(_))) {
+            *bar = Some(PhaseBar::Folders(ProgressBar::new(self.folder_count)));
+        }
+        if let Some(PhaseBar::Folders(folders)) = bar.as_mut() {
+            folders.inc();
+        }
+    }
+
+    fn on_file_bytes(&self, n: u64) {
+        if !self.predicted {
+            self.add_to_files_bar(n);
+        }
+    }
+
+    fn on_file(&self, entry: &FileEntry) {
+        if self.predicted {
+            self.add_to_files_bar(entry.predicted_time());
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(PhaseBar::Folders(mut bar) | PhaseBar::Files(mut bar)) =
+            self.bar.lock().unwrap().take()
+        {
+            bar.tick();
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{
+    blockhash::BlockIdHash,
+    blocksink::partial_path_of,
+    dfileentry::FileEntry,
+    dfiletype::FileType,
+    restoring::{calculate_path, restore_file_metadata, RestoreParams},
+};
+use eyre::Result;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+/// Files with the same content as an earlier file in the list, for --reflink-dups
+///
+/// Returns (duplicate, first file with its content) pairs. Empty files are left out,
+/// there's nothing to share
+pub fn find_duplicates(entries: &[FileEntry]) -> Vec<(&FileEntry, &FileEntry)> {
+    let mut first_by_content: BTreeMap<(&BlockIdHash, i64), &FileEntry> = BTreeMap::new();
+    let mut duplicates = Vec::new();
+    for entry in entries {
+        let FileType::File { hash, size, .. } = &entry.file_type else {
+            continue;
+        };
+        if *size <= 0 {
+            continue;
+        }
+        match first_by_content.get(&(hash, *size)) {
+            Some(first) => duplicates.push((entry, *first)),
+            None => {
+                first_by_content.insert((hash, *size), entry);
+            }
+        }
+    }
+    duplicates
+}
+
+/// Reflinks dup to first, which is already restored. Ok(false) if dup has to be
+/// restored normally: the filesystem can't reflink, or first wasn't restored
+///
+/// With --write-sidecar-hash dup is always restored normally, its sidecar
+/// needs the hash computed while writing
+pub fn reflink_duplicate(
+    dup: &FileEntry,
+    first: &FileEntry,
+    params: &RestoreParams<'_>,
+) -> Result<bool> {
+    let (Some((src, _)), Some((dst, _)), FileType::File { time, .. }) = (
+        calculate_path(first, params),
+        calculate_path(dup, params),
+        &dup.file_type,
+    ) else {
+        return Ok(false);
+    };
+    if params.sidecar.is_some() || reflink_file(&src, &dst).is_err() {
+        return Ok(false);
+    }
+    restore_file_metadata(&dst, dup, time, params)?;
+    if let Some(progress) = &params.progress {
+        progress.on_file_bytes(dup.bytes_size());
+        progress.on_file(dup);
+    }
+    Ok(true)
+}
+
+/// Creates dst sharing the extents of src (FICLONE), replacing dst if it exists
+///
+/// The clone is made under the .partial name of dst and renamed over it once
+/// done, so a failure (e.g. Unsupported where the filesystem or OS can't)
+/// leaves dst as it was
+pub fn reflink_file(src: &Path, dst: &Path) -> io::Result<()> {
+    let src_file = File::open(src)?;
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial_path = partial_path_of(dst);
+    let partial_file = File::create(&partial_path)?;
+    let result = ficlone(&src_file, &partial_file);
+    drop(partial_file);
+    let result = result.and_then(|()| fs::rename(&partial_path, dst));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial_path);
+    }
+    result
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn ficlone(src: &File, dst: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let ret = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn ficlone(_src: &File, _dst: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are only supported on Linux",
+    ))
+}
@@ -0,0 +1,198 @@
+use crate::{
+    dfileentry::FileEntry, dfiletype::FileType, flags::ReportFormat, restoring::RestoreSummary,
+};
+use eyre::{Context, Result};
+use serde::Serialize;
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// One restored (or failed) file in the --report output
+#[derive(Serialize)]
+pub struct ReportRow {
+    pub path: String,
+    pub size: u64,
+    /// SHA-256 of the content, hex
+    pub hash: String,
+    /// "ok" or "failed"
+    pub status: &'static str,
+    pub error: Option<String>,
+}
+
+impl ReportRow {
+    pub fn new(entry: &FileEntry, error: Option<String>) -> Self {
+        let hash = match &entry.file_type {
+            FileType::File { hash, .. } => hash.to_string(),
+            _ => String::new(),
+        };
+        Self {
+            path: entry.path.clone(),
+            size: entry.bytes_size(),
+            hash,
+            status: if error.is_some() { "failed" } else { "ok" },
+            error,
+        }
+    }
+
+    fn to_csv_line(&self) -> String {
+        format!(
+            "{},{},{},{},{}\n",
+            csv_field(&self.path),
+            self.size,
+            self.hash,
+            self.status,
+            csv_field(self.error.as_deref().unwrap_or(""))
+        )
+    }
+}
+
+/// Quotes a field if it has a comma, quote or line break (RFC 4180)
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Audit report of a restore, one row per file
+///
+/// csv and jsonl rows are written as files complete, so memory stays flat on huge backups.
+/// json has to be a single array and is buffered until finish
+pub struct RestoreReport {
+    format: ReportFormat,
+    writer: Mutex<BufWriter<File>>,
+    /// Only used by ReportFormat::Json
+    buffered: Mutex<Vec<ReportRow>>,
+}
+
+impl RestoreReport {
+    pub fn create<P: AsRef<Path>>(path: P, format: ReportFormat) -> Result<Self> {
+        let file = File::create(path.as_ref())
+            .wrap_err_with(|| format!("create report file {:?}", path.as_ref()))?;
+        let mut writer = BufWriter::new(file);
+        if let ReportFormat::Csv = format {
+            writeln!(writer, "path,size,hash,status,error")?;
+        }
+
+        Ok(Self {
+            format,
+            writer: Mutex::new(writer),
+            buffered: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn record(&self, row: ReportRow) -> Result<()> {
+        let line = match self.format {
+            ReportFormat::Json => {
+                self.buffered.lock().unwrap().push(row);
+                return Ok(());
+            }
+            ReportFormat::Jsonl => {
+                let mut line = serde_json::to_string(&row)?;
+                line.push('\n');
+                line
+            }
+            ReportFormat::Csv => row.to_csv_line(),
+        };
+        self.writer
+            .lock()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .wrap_err("write report row")?;
+        Ok(())
+    }
+
+    /// Writes buffered rows (json) and flushes, the report is incomplete without it
+    pub fn finish(&self) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        if let ReportFormat::Json = self.format {
+            let rows = std::mem::take(&mut *self.buffered.lock().unwrap());
+            serde_json::to_writer_pretty(&mut *writer, &rows).wrap_err("write json report")?;
+            writeln!(writer)?;
+        }
+        writer.flush().wrap_err("flush report file")?;
+        Ok(())
+    }
+}
+
+/// A file that failed with --continue-on-error, or didn't match its hash with --verify-only
+#[derive(Debug, Serialize)]
+pub struct FailedFile {
+    pub path: String,
+    /// Error chain, outermost first
+    pub errors: Vec<String>,
+    /// Skipped because some of its blocks are in missing dblock volumes
+    pub blocks_missing: bool,
+}
+
+impl FailedFile {
+    pub fn new(path: &str, err: &eyre::Report) -> Self {
+        Self {
+            path: path.to_string(),
+            errors: err.chain().map(|cause| cause.to_string()).collect(),
+            blocks_missing: false,
+        }
+    }
+
+    pub fn blocks_missing(path: &str, err: &eyre::Report) -> Self {
+        Self {
+            blocks_missing: true,
+            ..Self::new(path, err)
+        }
+    }
+}
+
+/// Error of a restore that went on past failed files, of a verify that found
+/// files not matching their hash, or of a run that skipped files with missing
+/// blocks. main reads the list from it
+#[derive(Debug)]
+pub struct FailedFiles(pub Vec<FailedFile>);
+
+impl fmt::Display for FailedFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files failed", self.0.len())
+    }
+}
+
+impl std::error::Error for FailedFiles {}
+
+/// Bumped when a field of RunReport changes meaning or goes away
+pub const RUN_REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Outcome of a whole restore or verify run, for --report-json
+#[derive(Serialize)]
+pub struct RunReport<'a> {
+    pub schema_version: u32,
+    /// "restore" or "verify"
+    pub mode: &'static str,
+    pub dlist: &'a Path,
+    /// The dlist's manifest as stored (Created, AppVersion, Blocksize, ...)
+    pub manifest: serde_json::Value,
+    pub summary: RestoreSummary,
+    pub elapsed_secs: f64,
+    /// "ok", "nothing-to-restore" or "failed"
+    pub status: &'static str,
+    /// Error chain that ended the run, outermost first
+    pub error: Option<Vec<String>>,
+    /// Files of failed_files skipped because of missing dblock volumes
+    pub skipped_missing_blocks: usize,
+    /// With --continue-on-error, hash mismatches of a verify, and skipped files
+    pub failed_files: &'a [FailedFile],
+}
+
+impl RunReport<'_> {
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path.as_ref())
+            .wrap_err_with(|| format!("create report file {:?}", path.as_ref()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self).wrap_err("write json run report")?;
+        writeln!(writer)?;
+        writer.flush().wrap_err("flush run report")?;
+        Ok(())
+    }
+}
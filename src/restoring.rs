@@ -1,15 +1,35 @@
 use crate::{
-    blockhash::BlockIdHash, database::DFileDatabase, dfileentry::FileEntry, dfiletype::FileType,
+    blockhash::BlockIdHash,
+    blocksink::{BlockSink, DirBlockSink, FileSink, PositionedFile},
+    blocktrace::BlockTrace,
+    completeness::missing_blocks,
+    database::DFileDatabase,
+    dfileentry::FileEntry,
+    dfiletype::FileType,
+    flags::{PathOs, SidecarAlgorithm, TimeSource, WriteOrder},
+    hashalgo::HashAlgo,
     hexdisplay::HexDisplayBytes,
+    interrupt::{interrupted, Interrupted},
+    metadata::{fetch_last_write_time, fetch_metadata, set_unix_mode},
+    mtime::{set_file_mtime, set_file_mtime_from_backup},
+    pathstyle::PathStyle,
+    progress::RestoreProgress,
+    report::RestoreReport,
+    special::{restore_special_file, special_kind},
+    FileEntries,
 };
 use eyre::eyre;
 use eyre::{Context, Result};
-use sha2::{Digest, Sha256};
+use rayon::prelude::*;
+use serde::Serialize;
+use sha2::digest::DynDigest;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::{
-    cell::RefCell,
-    fs::{self, File},
-    io::{Seek, SeekFrom, Write},
+    cell::{Cell, RefCell},
+    fs,
     path::{Path, PathBuf},
 };
 
@@ -17,6 +37,14 @@ use std::{
 pub struct RestoreContext {
     pub block_buffer: RefCell<Vec<u8>>,
     pub block_hashes_buffer: RefCell<Vec<u8>>,
+    /// Content bytes of the file being restored told to the progress so far
+    file_bytes_reported: Cell<u64>,
+}
+
+impl Default for RestoreContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RestoreContext {
@@ -24,6 +52,7 @@ impl RestoreContext {
         Self {
             block_buffer: RefCell::new(Vec::with_capacity(8 * 1024)),
             block_hashes_buffer: RefCell::new(Vec::with_capacity(8 * 1024)),
+            file_bytes_reported: Cell::new(0),
         }
     }
 }
@@ -31,6 +60,8 @@ impl RestoreContext {
 struct RestoreFileContext<'a> {
     restore_context: &'a RestoreContext,
     db: &'a DFileDatabase,
+    trace: Option<&'a BlockTrace>,
+    progress: Option<&'a dyn RestoreProgress>,
 
     entry: &'a FileEntry,
     hash: &'a BlockIdHash,
@@ -38,39 +69,347 @@ struct RestoreFileContext<'a> {
 
     debug_location: bool,
     strict_block_size: bool,
-    hasher: RefCell<Option<sha2::Sha256>>,
+    /// Number of content blocks fetched ahead of the writer, 0 to disable
+    prefetch: usize,
+    /// See RestoreParams::intra_file_parallelism
+    intra_file_parallelism: usize,
+    /// Hashes each block instead of the whole file, see RestoreParams::per_block_hash
+    per_block_hash: bool,
+    write_order: WriteOrder,
+    /// The manifest's FileHash algorithm
+    hasher: RefCell<Option<Box<dyn DynDigest + Send>>>,
+    /// Only if the sidecar can't reuse hasher (another algorithm)
+    sidecar_hasher: RefCell<Option<Box<dyn DynDigest + Send>>>,
+    /// Sum of content block lengths, compared with size at the end
+    restored_bytes: Cell<u64>,
 
     /// None if only verifying
     absolute_path: Option<&'a PathBuf>,
     /// None if only verifying
     relative_file_path: Option<&'a PathBuf>,
 
-    out_file: RefCell<Option<File>>,
+    /// None if only verifying
+    out_file: RefCell<Option<Box<dyn FileSink + 'a>>>,
 }
 
+#[derive(Clone, Copy, Debug, Default, Serialize)]
 pub struct RestoreSummary {
     pub file_count: usize,
     pub folder_count: usize,
     pub total_bytes: u64,
     pub predicted_bytes: u64,
+    /// Size of the dblocks, what the whole backup takes on the remote
+    pub physical_bytes: u64,
+    /// Blocks stored in the dblocks, for every version
+    pub unique_block_count: usize,
+    /// Blocks the entries refer to, a block counted once per reference
+    pub referenced_block_count: u64,
+}
+
+impl RestoreSummary {
+    /// Adds what the indexed dblocks hold and how often entries refer to blocks
+    pub fn with_storage(mut self, entries: &[FileEntry], db: &DFileDatabase) -> Self {
+        let (physical_bytes, unique_block_count) = db.stored_totals();
+        let block_size = db.block_size().max(1) as u64;
+        self.physical_bytes = physical_bytes;
+        self.unique_block_count = unique_block_count;
+        self.referenced_block_count = entries.iter().map(|f| f.block_refs(block_size)).sum();
+        self
+    }
+
+    /// Block references per stored block, None if no dblock was indexed. Below 1
+    /// when older versions hold blocks the restored one doesn't use
+    pub fn dedup_ratio(&self) -> Option<f64> {
+        (self.unique_block_count > 0)
+            .then(|| self.referenced_block_count as f64 / self.unique_block_count as f64)
+    }
 }
 
 pub struct RestoreParams<'a> {
     pub db: Arc<DFileDatabase>,
     pub restore_path: Option<&'a str>,
-    pub replace_backslash_to_slash: bool,
+    pub path_style: PathStyle,
+    /// Sets file mtime, from the dlist `time` field or the metadata block
+    pub restore_mtime: bool,
+    pub mtime_source: TimeSource,
+    /// Sets file mode bits from the metadata block
+    pub restore_permissions: bool,
+    /// Content blocks read ahead per multiblock file, 0 to read one at a time
+    pub prefetch: usize,
+    /// Content blocks of one multiblock file fetched and written at once, 1 to write in order
+    ///
+    /// Only used with sinks that take positioned writes from several threads, and
+    /// when the file hash can be read back from what was written
+    pub intra_file_parallelism: usize,
+    /// Verifies every block (blocklists too) against its hash and skips the whole-file hash
+    ///
+    /// SHA-256 can't be combined from ranges, so the file hash only works sequentially.
+    /// Block hashes are checked where blocks are fetched, in parallel with prefetching.
+    /// Verified blocklists pin the block order, but a dlist whose file hash
+    /// disagrees with its own blocklists would go unnoticed
+    pub per_block_hash: bool,
+    /// Checks that all blocks of a file are indexed before its sink file is created
+    pub validate_before_write: bool,
+    /// Creates FIFOs and device nodes instead of skipping them
+    pub restore_special: bool,
+    /// Creates directory junctions instead of plain folders, on Windows
+    pub restore_reparse: bool,
+    pub write_order: WriteOrder,
+    /// Writes a checksum file next to every restored file
+    pub sidecar: Option<SidecarHash>,
+    /// Receives file content, None if only verifying
+    pub sink: Option<Box<dyn BlockSink>>,
+    /// dlist path -> relative restore path, for entries that must not keep their name
+    pub path_renames: HashMap<String, PathBuf>,
+    /// Removed from the front of every relative restore path (--strip-prefix), the
+    /// folder at it becomes the restore dir itself
+    pub strip_prefix: Option<PathBuf>,
     pub summary: RestoreSummary,
+    /// None unless --trace-file is given
+    pub trace: Option<BlockTrace>,
+    /// None unless --report is given
+    pub report: Option<RestoreReport>,
+    /// Told about every folder and file restored
+    pub progress: Option<Arc<dyn RestoreProgress>>,
 }
+
+impl<'a> RestoreParams<'a> {
+    pub fn builder() -> RestoreParamsBuilder<'a> {
+        RestoreParamsBuilder::default()
+    }
+}
+
+/// RestoreParams for library use, with the defaults of the command line tool
+///
+/// Restoring everything of a dlist into a folder:
+///
+/// ```no_run
+/// use rust_duplicati_restore::restoring::RestoreContext;
+/// use rust_duplicati_restore::{parse_dlist_file, read_manifest, restore_entry};
+/// use rust_duplicati_restore::{DFileDatabase, RestoreParams};
+/// use std::path::PathBuf;
+/// use std::sync::Arc;
+///
+/// # fn main() -> eyre::Result<()> {
+/// let dlist = "/mnt/backup/duplicati-20240102T030405Z.dlist.zip";
+/// let db = DFileDatabase::new(&read_manifest(dlist, None)?, false)?;
+/// db.create_block_id_to_filenames(&[PathBuf::from(
+///     "/mnt/backup/duplicati-b0001.dblock.zip",
+/// )])?;
+/// let entries = parse_dlist_file(dlist, None)?;
+///
+/// let params = RestoreParams::builder()
+///     .with_db(Arc::new(db))
+///     .with_restore_path("/tmp/restored")
+///     .build(&entries)?;
+/// let context = RestoreContext::new();
+/// // Folders come first in a dlist, so they exist before their files
+/// for entry in &entries.entries {
+///     restore_entry(entry, &params, &context)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Checking the blocks against their hashes without writing anything, with
+/// backslashes as separators whatever the dlist paths look like:
+///
+/// ```no_run
+/// # use rust_duplicati_restore::restoring::RestoreContext;
+/// # use rust_duplicati_restore::{parse_dlist_file, read_manifest, restore_entry};
+/// # use rust_duplicati_restore::{DFileDatabase, RestoreParams};
+/// # use std::sync::Arc;
+/// # fn main() -> eyre::Result<()> {
+/// # let dlist = "/mnt/backup/duplicati-20240102T030405Z.dlist.zip";
+/// # let db = DFileDatabase::new(&read_manifest(dlist, None)?, false)?;
+/// # let entries = parse_dlist_file(dlist, None)?;
+/// let params = RestoreParams::builder()
+///     .with_db(Arc::new(db))
+///     .verify_only()
+///     .replace_backslash(Some(true))
+///     .build(&entries)?;
+/// for entry in entries.entries.iter().filter(|entry| entry.is_file()) {
+///     restore_entry(entry, &params, &RestoreContext::new())?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct RestoreParamsBuilder<'a> {
+    db: Option<Arc<DFileDatabase>>,
+    restore_path: Option<&'a str>,
+    verify_only: bool,
+    replace_backslash: Option<bool>,
+    restore_mtime: bool,
+    restore_permissions: bool,
+    create_parent_dirs: bool,
+    sink: Option<Box<dyn BlockSink>>,
+    progress: Option<Arc<dyn RestoreProgress>>,
+}
+
+impl<'a> RestoreParamsBuilder<'a> {
+    /// Required, the dblocks to read blocks from
+    pub fn with_db(mut self, db: Arc<DFileDatabase>) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Folder to restore into, required unless verify_only or with_sink
+    pub fn with_restore_path(mut self, restore_path: &'a str) -> Self {
+        self.restore_path = Some(restore_path);
+        self
+    }
+
+    /// Reads and checks every block, writes nothing
+    pub fn verify_only(mut self) -> Self {
+        self.verify_only = true;
+        self
+    }
+
+    /// Some(true) reads dlist paths as Windows paths ('\' separators, drive letters),
+    /// Some(false) as unix paths. None guesses from the paths, like --source-os auto
+    pub fn replace_backslash(mut self, replace_backslash: Option<bool>) -> Self {
+        self.replace_backslash = replace_backslash;
+        self
+    }
+
+    /// Sets file mtime from the dlist `time` field
+    pub fn with_restore_mtime(mut self, restore_mtime: bool) -> Self {
+        self.restore_mtime = restore_mtime;
+        self
+    }
+
+    /// Sets file mode bits from the metadata blocks
+    pub fn with_restore_permissions(mut self, restore_permissions: bool) -> Self {
+        self.restore_permissions = restore_permissions;
+        self
+    }
+
+    /// Creates missing folders above files, needed if entries leaves folders out
+    pub fn with_create_parent_dirs(mut self, create_parent_dirs: bool) -> Self {
+        self.create_parent_dirs = create_parent_dirs;
+        self
+    }
+
+    /// Where file content goes instead of the restore path, e.g. a MemoryBlockSink
+    ///
+    /// Without a restore path only files are restored, into the sink under their
+    /// relative paths
+    pub fn with_sink(mut self, sink: Box<dyn BlockSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Told about every folder and file restored, e.g. to drive a GUI progress bar
+    pub fn with_progress(mut self, progress: Arc<dyn RestoreProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// entries are the ones to be restored, for the summary and the path guess
+    pub fn build(self, entries: &FileEntries) -> Result<RestoreParams<'a>> {
+        let db = self
+            .db
+            .ok_or_else(|| eyre!("RestoreParamsBuilder: with_db is required"))?;
+        let restore_path = match (self.restore_path, self.verify_only) {
+            (Some(_), true) => {
+                return Err(eyre!(
+                    "RestoreParamsBuilder: with_restore_path and verify_only exclude each other"
+                ))
+            }
+            (_, true) if self.sink.is_some() => {
+                return Err(eyre!(
+                    "RestoreParamsBuilder: with_sink and verify_only exclude each other"
+                ))
+            }
+            (None, false) if self.sink.is_none() => {
+                return Err(eyre!(
+                    "RestoreParamsBuilder: with_restore_path, with_sink or verify_only is required"
+                ))
+            }
+            (restore_path, _) => restore_path,
+        };
+        let source_os = match self.replace_backslash {
+            Some(true) => PathOs::Windows,
+            Some(false) => PathOs::Unix,
+            None => PathOs::Auto,
+        };
+        let create_parent_dirs = self.create_parent_dirs;
+
+        Ok(RestoreParams {
+            db,
+            restore_path,
+            path_style: PathStyle::new(source_os, PathOs::Auto, &entries.entries),
+            restore_mtime: self.restore_mtime,
+            mtime_source: TimeSource::Mtime,
+            restore_permissions: self.restore_permissions,
+            prefetch: 0,
+            intra_file_parallelism: 1,
+            per_block_hash: false,
+            validate_before_write: false,
+            restore_special: false,
+            restore_reparse: false,
+            write_order: WriteOrder::Blocklist,
+            sidecar: None,
+            sink: self.sink.or_else(|| {
+                restore_path.map(|dir| -> Box<dyn BlockSink> {
+                    Box::new(DirBlockSink::new(dir, create_parent_dirs))
+                })
+            }),
+            path_renames: HashMap::new(),
+            strip_prefix: None,
+            summary: calculate_summary(&entries.entries),
+            trace: None,
+            report: None,
+            progress: self.progress,
+        })
+    }
+}
+
+pub fn calculate_summary(entries: &[FileEntry]) -> RestoreSummary {
+    let file_count = entries.iter().filter(|f| f.is_file()).count();
+    let folder_count = entries.iter().filter(|f| f.is_folder()).count();
+    let predicted_bytes: u64 = entries.iter().map(|f| f.predicted_time()).sum();
+    let total_bytes: u64 = entries.iter().map(|f| f.bytes_size()).sum();
+    RestoreSummary {
+        file_count,
+        folder_count,
+        total_bytes,
+        predicted_bytes,
+        ..Default::default()
+    }
+}
+
+pub struct SidecarHash {
+    pub algorithm: SidecarAlgorithm,
+    /// Without the dot, e.g. "sha256"
+    pub extension: String,
+}
+
+/// Path under the restore dir, after renaming and stripping the prefix
+pub fn relative_path(entry: &FileEntry, params: &RestoreParams<'_>) -> PathBuf {
+    let relative = match params.path_renames.get(&entry.path) {
+        Some(renamed) => renamed.clone(),
+        None => params.path_style.relative_target_path(&entry.path),
+    };
+    let Some(prefix) = &params.strip_prefix else {
+        return relative;
+    };
+    match relative.strip_prefix(prefix) {
+        // The prefix was a file, it keeps its name in the restore dir
+        Ok(rest) if rest.as_os_str().is_empty() && !entry.is_folder() => {
+            relative.file_name().map(PathBuf::from).unwrap_or_default()
+        }
+        Ok(rest) => rest.to_path_buf(),
+        Err(_) => relative,
+    }
+}
+
 /// Returns Some(absolute, relative)
 pub fn calculate_path(entry: &FileEntry, params: &RestoreParams<'_>) -> Option<(PathBuf, PathBuf)> {
     if let Some(restore_path) = &params.restore_path {
         let root_path = Path::new(restore_path);
-        let dfile_path = &entry.path[0..];
-        let mut dfile_path = dfile_path.replacen(":\\", "\\", 1);
-        if params.replace_backslash_to_slash {
-            dfile_path = dfile_path.replace('\\', "/");
-        }
-        let relative_file_path = PathBuf::from(&dfile_path);
+        let relative_file_path = relative_path(entry, params);
 
         let path = Path::join(root_path, &relative_file_path);
         Some((path, relative_file_path))
@@ -84,6 +423,9 @@ pub fn restore_entry(
     params: &RestoreParams<'_>,
     restore_context: &RestoreContext,
 ) -> Result<()> {
+    if interrupted() {
+        return Err(Interrupted.into());
+    }
     let paths = calculate_path(entry, params);
     let absolute_path = paths.as_ref().map(|v| &v.0);
     let relative_file_path = paths.as_ref().map(|v| &v.1);
@@ -93,9 +435,13 @@ pub fn restore_entry(
             if let Some(path) = absolute_path {
                 fs::create_dir_all(path)?;
             }
+            if let Some(progress) = &params.progress {
+                progress.on_folder();
+            }
         }
-        FileType::File { hash, size, .. } => {
-            restore_file(
+        FileType::File { hash, size, time } => {
+            restore_context.file_bytes_reported.set(0);
+            let restored = restore_file(
                 params,
                 restore_context,
                 hash,
@@ -103,12 +449,86 @@ pub fn restore_entry(
                 absolute_path,
                 relative_file_path,
                 entry,
-            )?;
+            );
+            if let Some(progress) = &params.progress {
+                // A failed file counts whole too, the bar ends at the total either way
+                let reported = restore_context.file_bytes_reported.get();
+                progress.on_file_bytes(entry.bytes_size().saturating_sub(reported));
+            }
+            restored?;
+            if let Some(path) = absolute_path {
+                restore_file_metadata(path, entry, time, params)?;
+            }
+            if let Some(progress) = &params.progress {
+                progress.on_file(entry);
+            }
+        }
+        FileType::Fifo | FileType::Device { .. } => {
+            if !params.restore_special {
+                println!(
+                    "warn: skipping {} {:?}, use --restore-special to create it",
+                    special_kind(&entry.file_type),
+                    entry.path
+                );
+            } else if let Some(path) = absolute_path {
+                restore_special_file(path, &entry.file_type)?;
+            }
         }
         _ => (),
     }
     Ok(())
 }
+/// Sets what --restore-mtime and --restore-permissions ask for on a restored file
+pub fn restore_file_metadata(
+    path: &Path,
+    entry: &FileEntry,
+    time: &str,
+    params: &RestoreParams<'_>,
+) -> Result<()> {
+    if params.restore_mtime {
+        set_restored_mtime(path, entry, time, params)?;
+    }
+    if params.restore_permissions {
+        restore_permissions(path, entry, params)?;
+    }
+    Ok(())
+}
+
+/// Sets mtime of a restored file from the source --created-time-from picks
+pub fn set_restored_mtime(
+    path: &Path,
+    entry: &FileEntry,
+    time: &str,
+    params: &RestoreParams<'_>,
+) -> Result<()> {
+    if params.mtime_source == TimeSource::Metadata {
+        if let Some(ft) = fetch_last_write_time(&params.db, entry)? {
+            return set_file_mtime(path, ft);
+        }
+    }
+    set_file_mtime_from_backup(path, time)
+}
+
+/// Sets the mode bits backed up in the metadata block of entry
+///
+/// Nothing is changed if the block is in no indexed dblock or was backed up off unix
+pub fn restore_permissions(
+    path: &Path,
+    entry: &FileEntry,
+    params: &RestoreParams<'_>,
+) -> Result<()> {
+    let Some(metahash) = BlockIdHash::from_base64(&entry.metahash) else {
+        return Ok(());
+    };
+    let Some(metadata) = fetch_metadata(&params.db, &metahash, &mut Vec::new())? else {
+        return Ok(());
+    };
+    match metadata.unix_mode()? {
+        Some(mode) => set_unix_mode(path, mode),
+        None => Ok(()),
+    }
+}
+
 fn restore_file(
     params: &RestoreParams<'_>,
     restore_context: &RestoreContext,
@@ -118,21 +538,87 @@ fn restore_file(
     relative_file_path: Option<&PathBuf>,
     entry: &FileEntry,
 ) -> Result<()> {
-    let hasher = if size > 0 { Some(Sha256::new()) } else { None };
-    let out_file = if let Some(path) = &absolute_path {
-        Some(File::create(path)?)
+    check_declared_size(&params.db, entry, size)?;
+    if params.validate_before_write {
+        let hashes_buf = &mut restore_context.block_hashes_buffer.borrow_mut();
+        let missing = missing_blocks(entry, &params.db, hashes_buf)?;
+        if let Some(first) = missing.first() {
+            return Err(eyre!(
+                "{} blocks missing (first {}), nothing written",
+                missing.len(),
+                first
+            ));
+        }
+    }
+
+    let file_hash = params.db.file_hash_algo();
+    let sidecar_algorithm = params.sidecar.as_ref().map(|sidecar| sidecar.algorithm);
+    let sidecar_reuses_hasher =
+        sidecar_algorithm == Some(SidecarAlgorithm::Sha256) && file_hash == HashAlgo::Sha256;
+    // A reusing sidecar needs the whole-file hash even with per_block_hash
+    let hasher = if size > 0 && (!params.per_block_hash || sidecar_reuses_hasher) {
+        Some(file_hash.hasher())
     } else {
         None
     };
+    let sidecar_hasher: Option<Box<dyn DynDigest + Send>> = match sidecar_algorithm {
+        Some(SidecarAlgorithm::Sha512) => Some(Box::new(Sha512::new())),
+        Some(SidecarAlgorithm::Sha256) if !sidecar_reuses_hasher => Some(Box::new(Sha256::new())),
+        _ => None,
+    };
+    let mut out_file = match &params.sink {
+        Some(sink) => Some(sink.create_file(&relative_path(entry, params))?),
+        None => None,
+    };
+    if let (Some(out_file), true) = (out_file.as_mut(), size > 0) {
+        out_file.preallocate(size as u64)?;
+    }
+
+    // Zero-byte files reference the shared empty block, nothing to read
+    let empty_block = hash.is_empty_block(params.db.block_hash_algo());
+    if empty_block || size == 0 {
+        if size != 0 {
+            return Err(eyre!(
+                "file references the empty block but has size {}",
+                size
+            ));
+        }
+        // Nothing is hashed here, the hash must still be one of no bytes. Empty
+        // files may carry the empty block's hash, which follows the BlockHash
+        let empty_hash = file_hash.digest(b"");
+        if !empty_block && hash.hash.as_slice() != &*empty_hash {
+            return Err(eyre!(
+                "declared size is 0, but file hash {} != {} of an empty file",
+                hash,
+                HexDisplayBytes(&empty_hash)
+            ));
+        }
+        if let Some(out_file) = out_file {
+            out_file.finish()?;
+        }
+        let digest = match sidecar_hasher {
+            Some(h) => h.finalize().to_vec(),
+            None => Sha256::digest(b"").to_vec(),
+        };
+        return write_sidecar_maybe(params, absolute_path, &digest);
+    }
     let context = RestoreFileContext {
         restore_context,
         entry,
         db: &params.db,
+        trace: params.trace.as_ref(),
+        progress: params.progress.as_deref(),
         debug_location: false,
         strict_block_size: true,
+        prefetch: params.prefetch,
+        intra_file_parallelism: params.intra_file_parallelism,
+        per_block_hash: params.per_block_hash,
+        write_order: params.write_order,
         hash,
         size,
         hasher: RefCell::new(hasher),
+        sidecar_hasher: RefCell::new(sidecar_hasher),
+        restored_bytes: Cell::new(0),
         absolute_path,
         relative_file_path,
         out_file: RefCell::new(out_file),
@@ -145,7 +631,89 @@ fn restore_file(
         restore_file_multiblock(&context)?;
     }
 
-    check_file_hash(&context)?;
+    check_restored_size(&context)?;
+    let file_hash = check_file_hash(&context)?;
+    let sidecar_hasher = context.sidecar_hasher.take();
+    if let Some(out_file) = context.out_file.into_inner() {
+        out_file.finish()?;
+    }
+    if let Some(digest) = sidecar_hasher.map(|h| h.finalize().to_vec()).or(file_hash) {
+        write_sidecar_maybe(params, absolute_path, &digest)?;
+    }
+    Ok(())
+}
+
+/// Writes "<hex>  <name>" to <file>.<ext>, the format sha256sum -c reads
+fn write_sidecar_maybe(
+    params: &RestoreParams<'_>,
+    absolute_path: Option<&PathBuf>,
+    digest: &[u8],
+) -> Result<()> {
+    let (Some(sidecar), Some(path)) = (&params.sidecar, absolute_path) else {
+        return Ok(());
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut sidecar_name = path.file_name().unwrap_or_default().to_os_string();
+    sidecar_name.push(format!(".{}", sidecar.extension));
+    let sidecar_path = path.with_file_name(sidecar_name);
+    fs::write(
+        &sidecar_path,
+        format!("{}  {}\n", HexDisplayBytes(digest), name),
+    )
+    .wrap_err_with(|| format!("write sidecar {:?}", sidecar_path))?;
+    Ok(())
+}
+
+/// Number of blocklist hashes must match the declared size, or the dlist is inconsistent
+fn check_declared_size(db: &DFileDatabase, entry: &FileEntry, size: i64) -> Result<()> {
+    let size = size.max(0) as u64;
+    let expected_blocklists = if size <= db.block_size() as u64 {
+        0
+    } else {
+        size.div_ceil(db.offset_size() as u64)
+    };
+    let actual = entry.block_lists.len() as u64;
+    if actual != expected_blocklists {
+        return Err(eyre!(
+            "declared size {} needs {} blocklists, but dlist has {}",
+            size,
+            expected_blocklists,
+            actual
+        ));
+    }
+    Ok(())
+}
+
+/// Catches truncated and over-long files even where the file hash is skipped
+fn check_restored_size(ctx: &RestoreFileContext<'_>) -> Result<()> {
+    let restored = ctx.restored_bytes.get();
+    if restored != ctx.size as u64 {
+        return Err(eyre!(
+            "{:?} reassembled to {} bytes, but declared size is {}",
+            ctx.entry.path,
+            restored,
+            ctx.size
+        ));
+    }
+    Ok(())
+}
+
+/// Dblock entries are bounded when read, blocks from a BlockProvider are not
+fn check_block_len(
+    ctx: &RestoreFileContext<'_>,
+    block_hash: &BlockIdHash,
+    len: usize,
+) -> Result<()> {
+    let full_block = ctx.db.block_size();
+    if len > full_block {
+        return Err(eyre!(
+            "block {} of {:?} is {} bytes, more than blocksize {}",
+            block_hash,
+            ctx.entry.path,
+            len,
+            full_block
+        ));
+    }
     Ok(())
 }
 
@@ -155,21 +723,50 @@ fn restore_file_singleblock(ctx: &RestoreFileContext<'_>) -> Result<()> {
     if ctx.size <= 0 {
         return Ok(());
     }
+    if streams_blocks(ctx) {
+        let n = ctx
+            .db
+            .read_block_into(ctx.hash, &mut HasherSink(ctx))?
+            .ok_or_else(|| eyre!("Missing block {} for {:?}", ctx.hash, ctx.absolute_path))?;
+        check_block_len(ctx, ctx.hash, n)?;
+        trace_block_maybe(ctx, ctx.hash, n, Some(0))?;
+        add_restored_bytes(ctx, n);
+        return Ok(());
+    }
 
     let buf = &mut ctx.restore_context.block_buffer.borrow_mut();
     buf.clear();
     let block = ctx.db.get_content_block(ctx.hash, buf)?;
-    let _len = block.ok_or(|| eyre!("Missing block {} for {:?}", ctx.hash, ctx.absolute_path));
+    block.ok_or_else(|| eyre!("Missing block {} for {:?}", ctx.hash, ctx.absolute_path))?;
+    check_block_len(ctx, ctx.hash, buf.len())?;
+    if ctx.per_block_hash {
+        check_block_hash(ctx.db.block_hash_algo(), ctx.hash, buf)?;
+    }
+    trace_block_maybe(ctx, ctx.hash, buf.len(), Some(0))?;
 
     if let Some(out_file) = ctx.out_file.borrow_mut().as_mut() {
         out_file
-            .write_all(buf.as_slice())
+            .write_block(0, buf.as_slice())
             .wrap_err("write single-block file")?;
     }
     update_hasher_maybe(ctx, buf);
+    add_restored_bytes(ctx, buf.len());
 
     Ok(())
 }
+fn trace_block_maybe(
+    ctx: &RestoreFileContext<'_>,
+    hash: &BlockIdHash,
+    bytes: usize,
+    out_offset: Option<u64>,
+) -> Result<()> {
+    if let Some(trace) = ctx.trace {
+        let loc = ctx.db.get_block_id_location(hash);
+        trace.record(hash, loc.as_ref(), bytes, out_offset)?;
+    }
+    Ok(())
+}
+
 fn debug_block_restore_maybe(ctx: &RestoreFileContext<'_>, is_multi: bool) {
     if !ctx.debug_location {
         return;
@@ -193,6 +790,17 @@ fn debug_block_restore_maybe(ctx: &RestoreFileContext<'_>, is_multi: bool) {
 fn restore_file_multiblock(ctx: &RestoreFileContext<'_>) -> Result<()> {
     debug_block_restore_maybe(ctx, true);
 
+    if ctx.intra_file_parallelism > 1 && ctx.write_order == WriteOrder::Blocklist {
+        let out_file = ctx.out_file.borrow();
+        let positioned = out_file.as_ref().and_then(|out_file| out_file.positioned());
+        let needs_file_hash =
+            ctx.hasher.borrow().is_some() || ctx.sidecar_hasher.borrow().is_some();
+        // Verifying without a file can't hash it afterwards
+        if positioned.is_some() || (out_file.is_none() && !needs_file_hash) {
+            return restore_file_multiblock_parallel(ctx, positioned);
+        }
+    }
+
     // Each blockid points to a list of blockids
     for (main_hash_index, main_hash) in ctx.entry.block_lists.iter().enumerate() {
         restore_file_multiblock_main(ctx, main_hash_index, main_hash)?;
@@ -201,11 +809,52 @@ fn restore_file_multiblock(ctx: &RestoreFileContext<'_>) -> Result<()> {
     Ok(())
 }
 
+/// True if blocks only go to the file hashers, they can then be read without
+/// a block buffer (DFileDatabase::read_block_into)
+///
+/// Blocks to write or to check by their own hash are read whole
+fn streams_blocks(ctx: &RestoreFileContext<'_>) -> bool {
+    ctx.out_file.borrow().is_none() && !ctx.per_block_hash
+}
+
+/// Feeds what is written to it to the file hashers
+struct HasherSink<'c, 'a>(&'c RestoreFileContext<'a>);
+
+impl std::io::Write for HasherSink<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        update_hasher_maybe(self.0, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 fn update_hasher_maybe(ctx: &RestoreFileContext<'_>, buf: &[u8]) {
     let mut hasher = ctx.hasher.borrow_mut();
     if let Some(h) = hasher.as_mut() {
         h.update(buf);
     }
+    if let Some(h) = ctx.sidecar_hasher.borrow_mut().as_mut() {
+        h.update(buf);
+    }
+}
+
+fn add_restored_bytes(ctx: &RestoreFileContext<'_>, n: usize) {
+    ctx.restored_bytes.set(ctx.restored_bytes.get() + n as u64);
+    report_file_bytes(ctx, n as u64);
+}
+
+/// Tells the progress about n more bytes, up to the declared size of the file
+fn report_file_bytes(ctx: &RestoreFileContext<'_>, n: u64) {
+    let Some(progress) = ctx.progress else {
+        return;
+    };
+    let reported = &ctx.restore_context.file_bytes_reported;
+    let n = n.min((ctx.size.max(0) as u64).saturating_sub(reported.get()));
+    reported.set(reported.get() + n);
+    progress.on_file_bytes(n);
 }
 
 fn restore_file_multiblock_block(
@@ -216,47 +865,170 @@ fn restore_file_multiblock_block(
     last_block_size: &mut Option<usize>,
 ) -> Result<()> {
     //let bhash = base64::encode(bhash);
-    let block_hash = BlockIdHash::from_bytes(block_hash)
-        .ok_or_else(|| eyre!("binary hash len is not 32 bytes"))?;
+    let block_hash = ctx.db.blocklist_hash(block_hash)?;
+    if streams_blocks(ctx) {
+        let n = ctx
+            .db
+            .read_block_into(&block_hash, &mut HasherSink(ctx))
+            .wrap_err_with(|| {
+                format!(
+                    "get one of content blocks (number {}): {}",
+                    block_index, block_hash
+                )
+            })?
+            .ok_or_else(|| {
+                eyre!(
+                    "Failed to find block {} for {:?}",
+                    block_hash,
+                    ctx.absolute_path
+                )
+            })?;
+        place_multiblock_block(ctx, block_index, &block_hash, n, blockhashoffset)?;
+        add_restored_bytes(ctx, n);
+        return check_strict_block(ctx, n, last_block_size);
+    }
     let buf = &mut ctx.restore_context.block_buffer.borrow_mut();
     buf.clear();
-    let block = ctx
-        .db
-        .get_content_block(&block_hash, buf)
-        .wrap_err_with(|| {
-            format!(
-                "get one of content blocks (number {}): {}",
-                block_index, block_hash
-            )
-        })?;
+    fetch_content_block(
+        ctx.db,
+        block_index,
+        &block_hash,
+        buf,
+        ctx.absolute_path,
+        ctx.per_block_hash,
+    )?;
+
+    write_multiblock_block(
+        ctx,
+        block_index,
+        &block_hash,
+        buf,
+        blockhashoffset,
+        last_block_size,
+    )
+}
+
+/// Reads one content block of a multiblock file into buf, optionally checking its hash
+fn fetch_content_block(
+    db: &DFileDatabase,
+    block_index: usize,
+    block_hash: &BlockIdHash,
+    buf: &mut Vec<u8>,
+    absolute_path: Option<&PathBuf>,
+    verify_hash: bool,
+) -> Result<()> {
+    let block = db.get_content_block(block_hash, buf).wrap_err_with(|| {
+        format!(
+            "get one of content blocks (number {}): {}",
+            block_index, block_hash
+        )
+    })?;
 
     let _block_len = block.ok_or_else(|| {
         eyre!(
             "Failed to find block {} for {:?}",
             block_hash,
-            ctx.absolute_path
+            absolute_path
         )
     })?;
+    if verify_hash {
+        check_block_hash(db.block_hash_algo(), block_hash, buf)
+            .wrap_err_with(|| format!("content block number {}", block_index))?;
+    }
+
+    Ok(())
+}
+
+/// Blocks are named by the hash of their content, algo is the manifest's BlockHash
+pub fn check_block_hash(algo: HashAlgo, block_hash: &BlockIdHash, buf: &[u8]) -> Result<()> {
+    let calculated_hash: &[u8] = &algo.digest(buf);
+    let expected_hash = block_hash.hash.as_slice();
+    if expected_hash != calculated_hash {
+        return Err(HashMismatch {
+            block: true,
+            algo,
+            expected: expected_hash.to_vec(),
+            calculated: calculated_hash.to_vec(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Content that doesn't match the hash stored for it, of a block or a whole file
+///
+/// Found in an error's chain, --verify-only lists the file and goes on to the next
+#[derive(Debug)]
+pub struct HashMismatch {
+    pub block: bool,
+    pub algo: HashAlgo,
+    pub expected: Vec<u8>,
+    pub calculated: Vec<u8>,
+}
+
+impl std::fmt::Display for HashMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{} hash is invalid: expected != calculated, {} != {}",
+            if self.block { "block " } else { "" },
+            self.algo,
+            HexDisplayBytes(&self.expected),
+            HexDisplayBytes(&self.calculated)
+        )
+    }
+}
+
+impl std::error::Error for HashMismatch {}
 
+fn write_multiblock_block(
+    ctx: &RestoreFileContext<'_>,
+    block_index: usize,
+    block_hash: &BlockIdHash,
+    buf: &[u8],
+    blockhashoffset: usize,
+    last_block_size: &mut Option<usize>,
+) -> Result<()> {
+    let offset = place_multiblock_block(ctx, block_index, block_hash, buf.len(), blockhashoffset)?;
     if let Some(out_file) = ctx.out_file.borrow_mut().as_mut() {
-        let full_block = ctx.db.block_size();
-        let offset = (blockhashoffset + block_index * full_block) as u64;
-        out_file
-            .seek(SeekFrom::Start(offset))
-            .wrap_err("seek blockhashoffset + bi * full_block")?;
         out_file
-            .write_all(buf.as_slice())
+            .write_block(offset, buf)
             .wrap_err("write (multi) block")?;
     }
     update_hasher_maybe(ctx, buf);
-    check_strict_block(ctx, buf, last_block_size)?;
+    add_restored_bytes(ctx, buf.len());
+    check_strict_block(ctx, buf.len(), last_block_size)?;
 
     Ok(())
 }
 
+/// Checks a content block of len bytes before it's written, returns its offset in the file
+fn place_multiblock_block(
+    ctx: &RestoreFileContext<'_>,
+    block_index: usize,
+    block_hash: &BlockIdHash,
+    len: usize,
+    blockhashoffset: usize,
+) -> Result<u64> {
+    let full_block = ctx.db.block_size();
+    let offset = (blockhashoffset + block_index * full_block) as u64;
+    check_block_len(ctx, block_hash, len)?;
+    trace_block_maybe(ctx, block_hash, len, Some(offset))?;
+    // restored_bytes is where the previous block ended
+    if ctx.write_order == WriteOrder::Ascending && offset != ctx.restored_bytes.get() {
+        return Err(eyre!(
+            "block number {} would be written at offset {}, the previous block ended at {}",
+            block_index,
+            offset,
+            ctx.restored_bytes.get()
+        ));
+    }
+    Ok(offset)
+}
+
 fn check_strict_block(
     ctx: &RestoreFileContext<'_>,
-    buf: &[u8],
+    len: usize,
     last_block_size: &mut Option<usize>,
 ) -> Result<()> {
     if !ctx.strict_block_size {
@@ -272,7 +1044,7 @@ fn check_strict_block(
             ))?;
         }
     }
-    *last_block_size = Some(buf.len());
+    *last_block_size = Some(len);
 
     Ok(())
 }
@@ -285,6 +1057,25 @@ fn restore_file_multiblock_main(
     let blockhashoffset = main_hash_index * ctx.db.offset_size();
 
     let hashes_buf: &mut Vec<u8> = &mut ctx.restore_context.block_hashes_buffer.borrow_mut();
+    fetch_blocklist(ctx, main_hash, hashes_buf)?;
+
+    let mut last_block_size = None;
+    if ctx.prefetch > 0 {
+        return restore_blocks_prefetched(ctx, hashes_buf, blockhashoffset, &mut last_block_size);
+    }
+    for (bi, bhash) in hashes_buf.chunks(ctx.db.hash_size()).enumerate() {
+        restore_file_multiblock_block(ctx, bi, bhash, blockhashoffset, &mut last_block_size)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the blocklist main_hash (binary block hashes) into hashes_buf
+fn fetch_blocklist(
+    ctx: &RestoreFileContext<'_>,
+    main_hash: &BlockIdHash,
+    hashes_buf: &mut Vec<u8>,
+) -> Result<()> {
     let binary_hashes_len = {
         hashes_buf.clear();
         ctx.db
@@ -300,17 +1091,112 @@ fn restore_file_multiblock_main(
         )
     })?;
 
-    let mut last_block_size = None;
-    for (bi, bhash) in hashes_buf.chunks(ctx.db.hash_size()).enumerate() {
-        restore_file_multiblock_block(ctx, bi, bhash, blockhashoffset, &mut last_block_size)?;
+    if ctx.per_block_hash {
+        check_block_hash(ctx.db.block_hash_algo(), main_hash, hashes_buf)
+            .wrap_err_with(|| format!("blocklist {}", main_hash))?;
+    }
+    trace_block_maybe(ctx, main_hash, hashes_buf.len(), None)
+}
+
+/// Fetches up to ctx.intra_file_parallelism blocks at once on the rayon pool and
+/// writes each at its offset, out is None if only verifying
+///
+/// The file hash needs the blocks in order, so it is fed from the written file afterwards
+fn restore_file_multiblock_parallel(
+    ctx: &RestoreFileContext<'_>,
+    out: Option<&dyn PositionedFile>,
+) -> Result<()> {
+    let db = ctx.db;
+    let full_block = db.block_size();
+    let mut blocks = Vec::new();
+    {
+        let hashes_buf: &mut Vec<u8> = &mut ctx.restore_context.block_hashes_buffer.borrow_mut();
+        for (main_hash_index, main_hash) in ctx.entry.block_lists.iter().enumerate() {
+            fetch_blocklist(ctx, main_hash, hashes_buf)?;
+            let blockhashoffset = main_hash_index * db.offset_size();
+            for (bi, bhash) in hashes_buf.chunks(db.hash_size()).enumerate() {
+                let block_hash = db.blocklist_hash(bhash)?;
+                let offset = (blockhashoffset + bi * full_block) as u64;
+                blocks.push((bi, block_hash, offset));
+            }
+        }
+    }
+
+    // restore_file preallocated the file to size
+    let size = ctx.size as u64;
+    let restored = AtomicU64::new(0);
+    let (trace, progress) = (ctx.trace, ctx.progress);
+    let (absolute_path, verify_hash) = (ctx.absolute_path, ctx.per_block_hash);
+    let strict_block_size = ctx.strict_block_size;
+    let written = blocks
+        .par_iter()
+        .with_min_len(blocks.len().div_ceil(ctx.intra_file_parallelism))
+        .try_for_each_init(
+            || Vec::with_capacity(full_block),
+            |buf, (bi, block_hash, offset)| -> Result<()> {
+                buf.clear();
+                fetch_content_block(db, *bi, block_hash, buf, absolute_path, verify_hash)?;
+                // Any block but the last one being short would leave a hole
+                let expected = size.saturating_sub(*offset).min(full_block as u64);
+                if strict_block_size && buf.len() as u64 != expected {
+                    return Err(eyre!(
+                        "block number {} at offset {} is {} bytes, expected {}",
+                        bi,
+                        offset,
+                        buf.len(),
+                        expected
+                    ));
+                }
+                if let Some(trace) = trace {
+                    let loc = db.get_block_id_location(block_hash);
+                    trace.record(block_hash, loc.as_ref(), buf.len(), Some(*offset))?;
+                }
+                if let Some(out) = out {
+                    out.write_block_at(*offset, buf)
+                        .wrap_err("write (multi) block")?;
+                }
+                restored.fetch_add(buf.len() as u64, Ordering::Relaxed);
+                if let Some(progress) = progress {
+                    progress.on_file_bytes(buf.len() as u64);
+                }
+                Ok(())
+            },
+        );
+    ctx.restored_bytes.set(restored.into_inner());
+    // Reported by the workers, the lengths are checked so they add up to at most size
+    let reported = &ctx.restore_context.file_bytes_reported;
+    reported.set(reported.get() + ctx.restored_bytes.get());
+    written?;
+
+    match out {
+        Some(out) => hash_written_file(ctx, out),
+        None => Ok(()),
     }
+}
 
+/// Feeds the file hashers with the first ctx.size bytes of out, in order
+fn hash_written_file(ctx: &RestoreFileContext<'_>, out: &dyn PositionedFile) -> Result<()> {
+    if ctx.hasher.borrow().is_none() && ctx.sidecar_hasher.borrow().is_none() {
+        return Ok(());
+    }
+    let buf = &mut ctx.restore_context.block_buffer.borrow_mut();
+    let size = ctx.size as u64;
+    let mut offset = 0;
+    while offset < size {
+        let len = (size - offset).min(ctx.db.block_size() as u64) as usize;
+        buf.resize(len, 0);
+        out.read_block_at(offset, buf)
+            .wrap_err_with(|| format!("read back block at offset {}", offset))?;
+        update_hasher_maybe(ctx, buf);
+        offset += len as u64;
+    }
     Ok(())
 }
 
-fn check_file_hash(ctx: &RestoreFileContext<'_>) -> Result<()> {
+/// Returns the verified hash, None if it wasn't computed
+fn check_file_hash(ctx: &RestoreFileContext<'_>) -> Result<Option<Vec<u8>>> {
     if ctx.size == 0 {
-        return Ok(());
+        return Ok(None);
     }
     let hasher = {
         let mut hasher = None;
@@ -319,18 +1205,20 @@ fn check_file_hash(ctx: &RestoreFileContext<'_>) -> Result<()> {
         hasher
     };
     if hasher.is_none() {
-        return Ok(());
+        return Ok(None);
     }
     let hasher = hasher.unwrap();
 
-    let calculated_hash: &[u8] = &hasher.finalize()[..];
+    let calculated_hash: &[u8] = &hasher.finalize();
     let expected_hash = ctx.hash.hash.as_slice();
     if expected_hash != calculated_hash {
-        return Err(eyre!(
-            "hash is invalid: expected != calculated, {} != {}",
-            HexDisplayBytes(expected_hash),
-            HexDisplayBytes(calculated_hash)
-        ));
+        return Err(HashMismatch {
+            block: false,
+            algo: ctx.db.file_hash_algo(),
+            expected: expected_hash.to_vec(),
+            calculated: calculated_hash.to_vec(),
+        }
+        .into());
     }
     let debug_hash = false;
     if debug_hash {
@@ -341,5 +1229,67 @@ fn check_file_hash(ctx: &RestoreFileContext<'_>) -> Result<()> {
         );
     }
 
+    Ok(Some(calculated_hash.to_vec()))
+}
+
+type PrefetchedBlock = Result<(usize, BlockIdHash, Vec<u8>)>;
+
+/// Fetches up to ctx.prefetch blocks ahead on a helper thread, writes them in order here
+///
+/// Memory is bounded by a pool of prefetch + 1 buffers that travel between the two threads
+fn restore_blocks_prefetched(
+    ctx: &RestoreFileContext<'_>,
+    hashes_buf: &[u8],
+    blockhashoffset: usize,
+    last_block_size: &mut Option<usize>,
+) -> Result<()> {
+    let (full_tx, full_rx) = crossbeam_channel::bounded::<PrefetchedBlock>(ctx.prefetch);
+    let (free_tx, free_rx) = crossbeam_channel::bounded::<Vec<u8>>(ctx.prefetch + 1);
+    for _ in 0..=ctx.prefetch {
+        free_tx
+            .send(Vec::with_capacity(ctx.db.block_size()))
+            .expect("free pool has room");
+    }
+
+    let db = ctx.db;
+    let hash_size = db.hash_size();
+    let absolute_path = ctx.absolute_path;
+    let verify_hash = ctx.per_block_hash;
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for (bi, bhash) in hashes_buf.chunks(hash_size).enumerate() {
+                // Writer is gone (error), stop fetching
+                let Ok(mut buf) = free_rx.recv() else {
+                    return;
+                };
+                buf.clear();
+                let fetched = db.blocklist_hash(bhash).and_then(|hash| {
+                    fetch_content_block(db, bi, &hash, &mut buf, absolute_path, verify_hash)?;
+                    Ok((bi, hash, buf))
+                });
+                let failed = fetched.is_err();
+                if full_tx.send(fetched).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        // Channels are moved in, so an early error return disconnects the fetcher
+        write_prefetched_blocks(ctx, full_rx, free_tx, blockhashoffset, last_block_size)
+    })
+}
+
+fn write_prefetched_blocks(
+    ctx: &RestoreFileContext<'_>,
+    full_rx: crossbeam_channel::Receiver<PrefetchedBlock>,
+    free_tx: crossbeam_channel::Sender<Vec<u8>>,
+    blockhashoffset: usize,
+    last_block_size: &mut Option<usize>,
+) -> Result<()> {
+    for fetched in full_rx {
+        let (bi, block_hash, buf) = fetched?;
+        write_multiblock_block(ctx, bi, &block_hash, &buf, blockhashoffset, last_block_size)?;
+        let _ = free_tx.send(buf);
+    }
     Ok(())
 }
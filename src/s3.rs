@@ -0,0 +1,233 @@
+use crate::backend::{self, Backend, Stat};
+use crate::crypto::ReadSeek;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::Client;
+use eyre::{eyre, Context, Result};
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::runtime::Runtime;
+
+/// Bytes fetched per GET when reading front to back, each read of a block
+/// costs a request otherwise
+const READ_AHEAD: u64 = 256 * 1024;
+/// Reads this close to the end fetch everything up to it: the central
+/// directory of a zip is read backwards from its end record, one GET
+/// instead of one per record
+const TAIL_LEN: u64 = 1024 * 1024;
+
+/// A prefix of a bucket holding a backup, read with ranged GETs (--backend)
+///
+/// Credentials, region and endpoint come from the usual AWS environment
+/// variables and config files. A custom endpoint (AWS_ENDPOINT_URL, e.g. MinIO)
+/// is addressed path-style
+struct S3Backend {
+    bucket: Arc<Bucket>,
+    /// Empty or ending with '/'
+    prefix: String,
+    objects: BTreeMap<String, Stat>,
+}
+
+struct Bucket {
+    /// The SDK is async, every request blocks a restore thread on this
+    runtime: Runtime,
+    client: Client,
+    name: String,
+}
+
+/// Lists the backup at url, "s3://bucket/prefix", and mounts it under url
+/// as if it was a directory. Returns how many files there are
+pub fn mount_s3(url: &str) -> Result<usize> {
+    let (bucket, prefix) = url
+        .strip_prefix("s3://")
+        .map(|rest| rest.split_once('/').unwrap_or((rest, "")))
+        .filter(|(bucket, _)| !bucket.is_empty())
+        .ok_or_else(|| eyre!("--backend {:?} is not like s3://bucket/prefix", url))?;
+    let prefix = match prefix.trim_end_matches('/') {
+        "" => String::new(),
+        prefix => format!("{}/", prefix),
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()?;
+    let client = runtime.block_on(async {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        let s3_config = aws_sdk_s3::config::Builder::from(&config)
+            .force_path_style(config.endpoint_url().is_some())
+            .build();
+        Client::from_conf(s3_config)
+    });
+    let bucket = Arc::new(Bucket {
+        runtime,
+        client,
+        name: bucket.to_string(),
+    });
+    let objects = bucket
+        .list(&prefix)
+        .wrap_err_with(|| format!("list {:?}", url))?;
+    let count = objects.len();
+    let backend = S3Backend {
+        bucket,
+        prefix,
+        objects,
+    };
+    backend::mount(Path::new(url.trim_end_matches('/')), Arc::new(backend));
+    Ok(count)
+}
+
+impl Bucket {
+    /// Objects right under prefix by name, "directories" are left out
+    fn list(&self, prefix: &str) -> Result<BTreeMap<String, Stat>> {
+        self.runtime.block_on(async {
+            let mut objects = BTreeMap::new();
+            let mut pages = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.name)
+                .prefix(prefix)
+                .delimiter("/")
+                .into_paginator()
+                .send();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(|err| eyre!("{}", DisplayErrorContext(err)))?;
+                for object in page.contents() {
+                    let Some(name) = object.key().and_then(|key| key.strip_prefix(prefix)) else {
+                        continue;
+                    };
+                    let modified = object
+                        .last_modified()
+                        .and_then(|time| SystemTime::try_from(*time).ok())
+                        .unwrap_or(SystemTime::UNIX_EPOCH);
+                    let stat = Stat {
+                        len: object.size().unwrap_or(0).try_into()?,
+                        modified,
+                    };
+                    objects.insert(name.to_string(), stat);
+                }
+            }
+            Ok(objects)
+        })
+    }
+
+    /// Bytes start..end of the object at key
+    fn get_range(&self, key: &str, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        self.runtime.block_on(async {
+            let object = self
+                .client
+                .get_object()
+                .bucket(&self.name)
+                .key(key)
+                .range(format!("bytes={}-{}", start, end - 1))
+                .send()
+                .await
+                .map_err(|err| {
+                    io::Error::other(format!("GET {}: {}", key, DisplayErrorContext(err)))
+                })?;
+            let data = object.body.collect().await.map_err(|err| {
+                io::Error::other(format!("GET {}: {}", key, DisplayErrorContext(err)))
+            })?;
+            let data = data.to_vec();
+            if data.len() as u64 != end - start {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "GET {} bytes {}..{} returned {} bytes",
+                        key,
+                        start,
+                        end,
+                        data.len()
+                    ),
+                ));
+            }
+            Ok(data)
+        })
+    }
+}
+
+impl Backend for S3Backend {
+    fn names(&self) -> Vec<String> {
+        self.objects.keys().cloned().collect()
+    }
+
+    fn stat(&self, name: &str) -> Option<Stat> {
+        self.objects.get(name).copied()
+    }
+
+    fn open(&self, name: &str) -> io::Result<Box<dyn ReadSeek>> {
+        let stat = self
+            .stat(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such object"))?;
+        Ok(Box::new(ObjectReader {
+            bucket: self.bucket.clone(),
+            key: format!("{}{}", self.prefix, name),
+            len: stat.len,
+            pos: 0,
+            chunk_start: 0,
+            chunk: Vec::new(),
+        }))
+    }
+}
+
+/// Reads an object with ranged GETs, keeping the bytes of the last one
+struct ObjectReader {
+    bucket: Arc<Bucket>,
+    key: String,
+    len: u64,
+    pos: u64,
+    chunk_start: u64,
+    chunk: Vec<u8>,
+}
+
+impl ObjectReader {
+    fn in_chunk(&self) -> bool {
+        (self.chunk_start..self.chunk_start + self.chunk.len() as u64).contains(&self.pos)
+    }
+
+    fn fetch(&mut self, want: usize) -> io::Result<()> {
+        let tail_start = self.len.saturating_sub(TAIL_LEN);
+        let (start, end) = match self.pos >= tail_start {
+            true => (tail_start, self.len),
+            false => (
+                self.pos,
+                (self.pos + READ_AHEAD.max(want as u64)).min(self.len),
+            ),
+        };
+        self.chunk = self.bucket.get_range(&self.key, start, end)?;
+        self.chunk_start = start;
+        Ok(())
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.in_chunk() {
+            self.fetch(buf.len())?;
+        }
+        let from = (self.pos - self.chunk_start) as usize;
+        let n = buf.len().min(self.chunk.len() - from);
+        buf[..n].copy_from_slice(&self.chunk[from..from + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for ObjectReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = new_pos
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,159 @@
+use crate::blockarchive::{BlockArchive, BlockEntry};
+use crate::ziparchive::MyCloneFileReader;
+use eyre::{eyre, Context, Result};
+use lru::LruCache;
+use sevenz_rust::{Archive, BlockDecoder};
+use std::collections::HashMap;
+use std::io::{Cursor, Seek, SeekFrom};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Entries of one 7z folder (a solid block), in archive order
+type DecodedFolder = Vec<Vec<u8>>;
+type FolderSlot = Arc<Mutex<Option<Arc<DecodedFolder>>>>;
+
+/// Decoded folders of the 7z dblocks of a db, most recently used first
+///
+/// A folder can only be decompressed from its start, so reading one block means
+/// decoding everything before it. Keeping whole folders makes the neighbouring
+/// blocks free, restores read blocks in volume order
+pub struct SevenZFolderCache {
+    folders: Mutex<LruCache<(PathBuf, usize), FolderSlot>>,
+}
+
+impl SevenZFolderCache {
+    /// Keeps at most capacity folders, each up to a dblock in size
+    pub fn new(capacity: usize) -> Arc<Self> {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Arc::new(Self {
+            folders: Mutex::new(LruCache::new(capacity)),
+        })
+    }
+
+    /// Threads asking for a folder being decoded wait for it instead of decoding it again
+    fn get_or_decode(
+        &self,
+        key: (PathBuf, usize),
+        decode: impl FnOnce() -> Result<DecodedFolder>,
+    ) -> Result<Arc<DecodedFolder>> {
+        let slot = self
+            .folders
+            .lock()
+            .unwrap()
+            .get_or_insert(key, FolderSlot::default)
+            .clone();
+        let mut slot = slot.lock().unwrap();
+        if let Some(folder) = &*slot {
+            return Ok(folder.clone());
+        }
+        let folder = Arc::new(decode()?);
+        *slot = Some(folder.clone());
+        Ok(folder)
+    }
+}
+
+/// Header of a 7z dblock, shared by all handles to it
+struct SevenZIndex {
+    archive: Archive,
+    /// Entry name -> position in archive.files
+    by_name: HashMap<String, usize>,
+}
+
+/// A dblock written by Duplicati's 7z compression module
+///
+/// Blocks come out of the decoded folders in config.sevenz_folders
+pub struct SevenZArchive {
+    reader: MyCloneFileReader,
+    index: Arc<SevenZIndex>,
+}
+
+impl SevenZArchive {
+    /// Reads the header, the entries are decoded when first asked for
+    pub fn new(mut reader: MyCloneFileReader) -> Result<Self> {
+        let path = reader.config.path.clone();
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.rewind()?;
+        // Duplicati encrypts whole volumes with AES Crypt, not with a 7z password
+        let archive = Archive::read(&mut reader, len, &[])
+            .wrap_err_with(|| format!("{:?} is not a readable 7z", path))?;
+        let by_name = archive
+            .files
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| (entry.name.clone(), index))
+            .collect();
+        Ok(Self {
+            reader,
+            index: Arc::new(SevenZIndex { archive, by_name }),
+        })
+    }
+
+    fn decode_folder(&mut self, folder: usize) -> Result<DecodedFolder> {
+        let mut entries = Vec::new();
+        BlockDecoder::new(folder, &self.index.archive, &[], &mut self.reader).for_each_entries(
+            &mut |_entry, data| {
+                let mut content = Vec::new();
+                data.read_to_end(&mut content)
+                    .map_err(sevenz_rust::Error::io)?;
+                entries.push(content);
+                Ok(true)
+            },
+        )?;
+        Ok(entries)
+    }
+}
+
+impl BlockArchive for SevenZArchive {
+    fn get_file_index(&self, name: &str) -> Option<usize> {
+        self.index.by_name.get(name).copied()
+    }
+
+    fn file_names_ordered(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        Box::new(self.index.archive.files.iter().map(|entry| entry.name()))
+    }
+
+    fn by_name(&mut self, name: &str) -> Result<BlockEntry<'_>> {
+        let config = self.reader.config.clone();
+        let index = self
+            .get_file_index(name)
+            .ok_or_else(|| eyre!("no entry {:?} in {:?}", name, config.path))?;
+        let size = self.index.archive.files[index].size;
+        let stream_map = &self.index.archive.stream_map;
+        let Some(folder) = stream_map.file_folder_index[index] else {
+            // Empty entries have no data in any folder
+            return Ok(BlockEntry {
+                size,
+                reader: Box::new(std::io::empty()),
+            });
+        };
+        let position = index - stream_map.folder_first_file_index[folder];
+        let decoded = config
+            .sevenz_folders
+            .get_or_decode((config.path.clone(), folder), || {
+                self.decode_folder(folder)
+                    .wrap_err_with(|| format!("decode folder {} of {:?}", folder, config.path))
+            })?;
+        let content = decoded
+            .get(position)
+            .ok_or_else(|| {
+                eyre!(
+                    "entry {:?} missing from its folder in {:?}",
+                    name,
+                    config.path
+                )
+            })?
+            .clone();
+        Ok(BlockEntry {
+            size,
+            reader: Box::new(Cursor::new(content)),
+        })
+    }
+
+    fn clone_archive(&self) -> Box<dyn BlockArchive> {
+        Box::new(Self {
+            reader: self.reader.clone(),
+            index: self.index.clone(),
+        })
+    }
+}
@@ -0,0 +1,172 @@
+use crate::backend::{self, Backend, Stat};
+use crate::crypto::ReadSeek;
+use eyre::{eyre, Context, Result};
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+use std::{
+    collections::BTreeMap,
+    io,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// A directory on an SSH server holding a backup, read over SFTP (--backend)
+///
+/// One session serves every thread, its requests take turns. The host key has
+/// to be in ~/.ssh/known_hosts
+struct SftpBackend {
+    sftp: Sftp,
+    dir: PathBuf,
+    files: BTreeMap<String, Stat>,
+    /// Requests go through sftp, the session only has to stay open
+    _session: Session,
+}
+
+/// Where --backend sftp://user@host:port/path points, port 22 and the local
+/// user name if left out. A path starting with /~/ is in the user's home
+struct SftpUrl {
+    user: String,
+    host: String,
+    port: u16,
+    dir: PathBuf,
+}
+
+impl SftpUrl {
+    fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("sftp://")?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (user.to_string(), host_port),
+            None => (local_user()?, authority),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (host_port, 22),
+        };
+        if host.is_empty() || user.is_empty() {
+            return None;
+        }
+        let dir = match path.strip_prefix("~/").or((path == "~").then_some("")) {
+            Some("") => PathBuf::from("."),
+            Some(in_home) => PathBuf::from(in_home),
+            None => PathBuf::from(format!("/{}", path)),
+        };
+        Some(Self {
+            user,
+            host: host.to_string(),
+            port,
+            dir,
+        })
+    }
+}
+
+fn local_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+}
+
+/// Lists the backup at url, "sftp://user@host/path", and mounts it under url
+/// as if it was a directory. Returns how many files there are
+///
+/// Logs in with the private key at identity, or else through the ssh agent
+pub fn mount_sftp(url: &str, identity: Option<&Path>) -> Result<usize> {
+    let target = SftpUrl::parse(url)
+        .ok_or_else(|| eyre!("--backend {:?} is not like sftp://user@host/path", url))?;
+    let session = connect(&target).wrap_err_with(|| format!("connect to {}", target.host))?;
+    match identity {
+        Some(key) => session
+            .userauth_pubkey_file(&target.user, None, key, None)
+            .wrap_err_with(|| format!("log in as {} with --identity {:?}", target.user, key))?,
+        None => session.userauth_agent(&target.user).wrap_err_with(|| {
+            format!(
+                "log in as {} through the ssh agent, or pass --identity",
+                target.user
+            )
+        })?,
+    }
+    let sftp = session.sftp().wrap_err("start SFTP")?;
+    let files = list(&sftp, &target.dir).wrap_err_with(|| format!("list {:?}", target.dir))?;
+    let count = files.len();
+    let backend = SftpBackend {
+        sftp,
+        dir: target.dir,
+        files,
+        _session: session,
+    };
+    backend::mount(Path::new(url.trim_end_matches('/')), Arc::new(backend));
+    Ok(count)
+}
+
+/// A session with the server's host key checked against ~/.ssh/known_hosts
+fn connect(target: &SftpUrl) -> Result<Session> {
+    let tcp = TcpStream::connect((target.host.as_str(), target.port))?;
+    let mut session = Session::new()?;
+    session.set_tcp_stream(tcp);
+    session.handshake()?;
+
+    let known_hosts_path = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| Path::new(&home).join(".ssh").join("known_hosts"))
+        .ok_or_else(|| eyre!("no home directory to find .ssh/known_hosts in"))?;
+    let mut known_hosts = session.known_hosts()?;
+    known_hosts
+        .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+        .wrap_err_with(|| format!("read {:?}", known_hosts_path))?;
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| eyre!("the server sent no host key"))?;
+    match known_hosts.check_port(&target.host, target.port, key) {
+        CheckResult::Match => Ok(session),
+        CheckResult::Mismatch => Err(eyre!(
+            "host key of {} does not match the one in {:?}",
+            target.host,
+            known_hosts_path
+        )),
+        CheckResult::NotFound => Err(eyre!(
+            "host key of {} is not in {:?}, connect with ssh once to check and add it",
+            target.host,
+            known_hosts_path
+        )),
+        CheckResult::Failure => Err(eyre!("could not check the host key of {}", target.host)),
+    }
+}
+
+/// Regular files in dir by name
+fn list(sftp: &Sftp, dir: &Path) -> Result<BTreeMap<String, Stat>> {
+    let mut files = BTreeMap::new();
+    for (path, stat) in sftp.readdir(dir)? {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !stat.is_file() {
+            continue;
+        }
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(stat.mtime.unwrap_or(0));
+        files.insert(
+            name.to_string(),
+            Stat {
+                len: stat.size.unwrap_or(0),
+                modified,
+            },
+        );
+    }
+    Ok(files)
+}
+
+impl Backend for SftpBackend {
+    fn names(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    fn stat(&self, name: &str) -> Option<Stat> {
+        self.files.get(name).copied()
+    }
+
+    /// The file reads at its position, seeking sends nothing to the server
+    fn open(&self, name: &str) -> io::Result<Box<dyn ReadSeek>> {
+        let file = self.sftp.open(self.dir.join(name))?;
+        Ok(Box::new(file))
+    }
+}
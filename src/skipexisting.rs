@@ -0,0 +1,83 @@
+use crate::{
+    dfiletype::FileType,
+    flags::SkipExisting,
+    restoring::{calculate_path, RestoreParams},
+    FileEntries,
+};
+use eyre::{Context, Result};
+use rayon::prelude::*;
+use std::{fs::File, io::ErrorKind, path::Path};
+
+/// Leaves out files already in the restore dir, for --skip-existing
+///
+/// Restored files get their final name only once complete (they're written as
+/// <name>.partial), so a file of the right size is normally a finished one. Hash
+/// also re-reads it and compares with the dlist file hash. Other entries are kept
+pub fn skip_existing(
+    file_entries: FileEntries,
+    params: &RestoreParams<'_>,
+    mode: SkipExisting,
+) -> Result<FileEntries> {
+    let restored = file_entries
+        .entries
+        .par_iter()
+        .map(|entry| {
+            let FileType::File { hash, size, .. } = &entry.file_type else {
+                return Ok(false);
+            };
+            let Some((path, _)) = calculate_path(entry, params) else {
+                return Ok(false);
+            };
+            let restored = already_restored(&path, *size, &hash.hash, mode, params)
+                .wrap_err_with(|| format!("--skip-existing: check {:?}", path))?;
+            if let (true, Some(progress)) = (restored, &params.progress) {
+                progress.on_file_bytes(entry.bytes_size());
+                progress.on_file(entry);
+            }
+            Ok(restored)
+        })
+        .collect::<Result<Vec<bool>>>()?;
+
+    let skipped = restored.iter().filter(|&&restored| restored).count();
+    if skipped > 0 {
+        println!(
+            "{} files already restored, skipped (--skip-existing)",
+            skipped
+        );
+    }
+    let entries = file_entries
+        .entries
+        .into_iter()
+        .zip(restored)
+        .filter_map(|(entry, restored)| (!restored).then_some(entry))
+        .collect();
+    Ok(FileEntries { entries })
+}
+
+fn already_restored(
+    path: &Path,
+    size: i64,
+    hash: &[u8],
+    mode: SkipExisting,
+    params: &RestoreParams<'_>,
+) -> Result<bool> {
+    let len = match path.metadata() {
+        Ok(meta) if meta.is_file() => meta.len(),
+        Ok(_) => return Ok(false),
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+    if u64::try_from(size).ok() != Some(len) {
+        return Ok(false);
+    }
+    match mode {
+        SkipExisting::Size => Ok(true),
+        SkipExisting::Hash => {
+            let digest = params
+                .db
+                .file_hash_algo()
+                .digest_reader(File::open(path)?)?;
+            Ok(*digest == *hash)
+        }
+    }
+}
@@ -32,3 +32,29 @@ pub fn compare_fileentry(entry_a: &FileEntry, entry_b: &FileEntry, db: &DFileDat
 
     a.cmp(&b).then_with(|| entry_a.cmp(entry_b))
 }
+
+/// Splits sorted entries into runs whose first block is in the same dblock
+///
+/// Each volume's run is cut into workers_per_volume chunks, one worker restores a chunk
+/// in order, so the volume's file handle and page cache stay hot
+pub fn volume_runs<'a>(
+    sorted: &'a [FileEntry],
+    db: &DFileDatabase,
+    workers_per_volume: usize,
+) -> Vec<&'a [FileEntry]> {
+    let volumes: Vec<_> = sorted
+        .iter()
+        .map(|e| get_first_bytes_location(e, db).map(|loc| loc.ziplocation))
+        .collect();
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for end in 1..=sorted.len() {
+        if end == sorted.len() || volumes[end] != volumes[start] {
+            let run = &sorted[start..end];
+            let chunk = run.len().div_ceil(workers_per_volume.max(1));
+            runs.extend(run.chunks(chunk));
+            start = end;
+        }
+    }
+    runs
+}
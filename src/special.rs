@@ -0,0 +1,102 @@
+use crate::{
+    blockhash::BlockIdHash, database::DFileDatabase, dfileentry::FileEntry, dfiletype::FileType,
+    metadata::fetch_metadata,
+};
+use eyre::{Context, Result};
+use rayon::prelude::*;
+use std::path::Path;
+
+/// Turns entries whose metadata describes a FIFO or device node into Fifo / Device
+///
+/// The dlist has no type for these, they come as neither File nor Folder.
+/// Entries whose metadata can't be read or parsed are warned about and left
+/// as they are. Returns how many were found
+pub fn resolve_special_files(entries: &mut [FileEntry], db: &DFileDatabase) -> usize {
+    entries
+        .par_iter_mut()
+        .filter(|e| matches!(e.file_type, FileType::SymLink))
+        .map_init(Vec::new, |buf, entry| {
+            match special_file_type(entry, db, buf) {
+                Ok(Some(file_type)) => {
+                    entry.file_type = file_type;
+                    1
+                }
+                Ok(None) => 0,
+                Err(err) => {
+                    println!("warn: {:?} is not a special file: {:#}", entry.path, err);
+                    0
+                }
+            }
+        })
+        .sum()
+}
+
+fn special_file_type(
+    entry: &FileEntry,
+    db: &DFileDatabase,
+    buf: &mut Vec<u8>,
+) -> Result<Option<FileType>> {
+    let Some(metahash) = BlockIdHash::from_base64(&entry.metahash) else {
+        return Ok(None);
+    };
+    let Some(metadata) = fetch_metadata(db, &metahash, buf)? else {
+        return Ok(None);
+    };
+    metadata
+        .special_file_type()
+        .wrap_err_with(|| format!("metadata of {:?}", entry.path))
+}
+
+/// Creates a FIFO or device node, warns and skips if we lack the privileges
+pub fn restore_special_file(path: &Path, file_type: &FileType) -> Result<()> {
+    let result = make_special(path, file_type);
+    if let Err(err) = &result {
+        if err.kind() == std::io::ErrorKind::PermissionDenied {
+            println!("warn: no permission to create {:?}, skipping", path);
+            return Ok(());
+        }
+    }
+    result.wrap_err_with(|| format!("create special file {:?}", path))
+}
+
+#[cfg(unix)]
+fn make_special(path: &Path, file_type: &FileType) -> std::io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let cpath = CString::new(path.as_os_str().as_bytes())?;
+    // Permissions are set from metadata later, if at all
+    let ret = match *file_type {
+        FileType::Fifo => unsafe { libc::mkfifo(cpath.as_ptr(), 0o644) },
+        FileType::Device {
+            block,
+            major,
+            minor,
+        } => {
+            let kind = if block { libc::S_IFBLK } else { libc::S_IFCHR };
+            let dev = libc::makedev(major as _, minor as _);
+            unsafe { libc::mknod(cpath.as_ptr(), kind | 0o600, dev) }
+        }
+        _ => return Ok(()),
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_special(_path: &Path, _file_type: &FileType) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "special files can only be restored on unix",
+    ))
+}
+
+pub fn special_kind(file_type: &FileType) -> &'static str {
+    match file_type {
+        FileType::Fifo => "fifo",
+        FileType::Device { block: true, .. } => "block device",
+        FileType::Device { block: false, .. } => "character device",
+        _ => "special file",
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{
+    blockhash::BlockIdHash,
+    dfileentry::FileEntry,
+    dfiletype::FileType,
+    metadata::fetch_metadata,
+    restoring::{calculate_path, RestoreParams},
+};
+use eyre::{eyre, Context, Result};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Metadata key with the link target, as the backed up system stored it
+pub const KEY_SYMLINK_TARGET: &str = "CoreSymlinkTarget";
+
+/// A backed up link and its target
+struct Link<'a> {
+    entry: &'a FileEntry,
+    target: String,
+    /// Directory reparse point of a Windows backup
+    junction: bool,
+}
+
+/// Creates symlinks one by one, in path order, after folders and files exist
+///
+/// Links that come back to themselves through other backed up links are skipped with a warning.
+/// Returns how many were created
+pub fn restore_symlinks(entries: &[FileEntry], params: &RestoreParams<'_>) -> Result<usize> {
+    let mut links = read_symlink_targets(entries, params)?;
+    links.sort_by(|a, b| a.entry.path.cmp(&b.entry.path));
+
+    let separator = if params.path_style.source_windows {
+        '\\'
+    } else {
+        '/'
+    };
+    let resolved: HashMap<String, String> = links
+        .iter()
+        .map(|link| {
+            (
+                normalize(&link.entry.path, separator),
+                resolve_target(&link.entry.path, &link.target, separator),
+            )
+        })
+        .collect();
+
+    let mut created = 0;
+    for Link {
+        entry,
+        target,
+        junction,
+    } in &links
+    {
+        if is_loop(&normalize(&entry.path, separator), &resolved) {
+            println!(
+                "warn: skipping symlink {:?} -> {:?}, it loops back to itself",
+                entry.path, target
+            );
+            continue;
+        }
+        let Some((path, _)) = calculate_path(entry, params) else {
+            continue;
+        };
+        if *junction {
+            if restore_junction(entry, target, &path, params.restore_reparse)? {
+                created += 1;
+            }
+            continue;
+        }
+        let target = if params.path_style.source_windows && !cfg!(windows) {
+            target.replace('\\', "/")
+        } else {
+            target.clone()
+        };
+        create_symlink(&target, &path)
+            .wrap_err_with(|| format!("restoring symlink {:?}", entry.path))?;
+        created += 1;
+    }
+    Ok(created)
+}
+
+fn read_symlink_targets<'a>(
+    entries: &'a [FileEntry],
+    params: &RestoreParams<'_>,
+) -> Result<Vec<Link<'a>>> {
+    let mut buf = Vec::new();
+    let mut links = Vec::new();
+    for entry in entries
+        .iter()
+        .filter(|e| matches!(e.file_type, FileType::SymLink))
+    {
+        let metadata = match BlockIdHash::from_base64(&entry.metahash) {
+            Some(hash) => fetch_metadata(&params.db, &hash, &mut buf)
+                .wrap_err_with(|| format!("metadata of {:?}", entry.path))?,
+            None => None,
+        };
+        let Some((metadata, target)) = metadata
+            .as_ref()
+            .and_then(|m| Some((m, m.get(KEY_SYMLINK_TARGET)?)))
+        else {
+            println!("warn: skipping symlink {:?}, target unknown", entry.path);
+            continue;
+        };
+        links.push(Link {
+            entry,
+            target: target.to_string(),
+            junction: params.path_style.source_windows && metadata.is_directory_reparse_point(),
+        });
+    }
+    Ok(links)
+}
+
+/// Lexically removes empty, "." and ".." components
+fn normalize(path: &str, separator: char) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split(separator) {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join(&separator.to_string())
+}
+
+/// Source path the link points to, relative targets start at the link's folder
+fn resolve_target(link_path: &str, target: &str, separator: char) -> String {
+    let absolute = target.starts_with(separator) || target.get(1..2) == Some(":");
+    if absolute {
+        return normalize(target, separator);
+    }
+    let parent = link_path
+        .trim_end_matches(separator)
+        .rsplit_once(separator)
+        .map(|(parent, _)| parent)
+        .unwrap_or("");
+    normalize(&format!("{parent}{separator}{target}"), separator)
+}
+
+/// Follows backed up links from start, true if it comes back
+fn is_loop(start: &str, resolved: &HashMap<String, String>) -> bool {
+    let mut current = start;
+    for _ in 0..=resolved.len() {
+        match resolved.get(current) {
+            Some(next) if next == start => return true,
+            Some(next) => current = next,
+            None => return false,
+        }
+    }
+    // Ran into a cycle that doesn't include start, the link can't be followed either
+    true
+}
+
+/// Creates a junction with --restore-reparse on Windows, a plain folder otherwise.
+/// Returns true for a junction
+fn restore_junction(
+    entry: &FileEntry,
+    target: &str,
+    path: &Path,
+    restore_reparse: bool,
+) -> Result<bool> {
+    if restore_reparse && cfg!(windows) {
+        create_junction(target, path)
+            .wrap_err_with(|| format!("restoring junction {:?}", entry.path))?;
+        return Ok(true);
+    }
+    println!(
+        "warn: junction {:?} -> {:?} restored as a plain folder, needs --restore-reparse on Windows",
+        entry.path, target
+    );
+    fs::create_dir_all(path)?;
+    Ok(false)
+}
+
+/// mklink /J, junctions need no admin rights unlike symlinks
+#[cfg(windows)]
+fn create_junction(target: &str, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // An empty folder, e.g. from an earlier run without --restore-reparse, is replaced
+    if path.is_dir() {
+        let _ = fs::remove_dir(path);
+    }
+    let status = std::process::Command::new("cmd")
+        .arg("/C")
+        .arg("mklink")
+        .arg("/J")
+        .arg(path)
+        .arg(target)
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(eyre!("mklink /J failed with {}", status));
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn create_junction(_target: &str, _path: &Path) -> Result<()> {
+    Err(eyre!("junctions can only be created on Windows"))
+}
+
+fn create_symlink(target: &str, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A link from an earlier run is replaced, anything else is an error below
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if meta.file_type().is_symlink() {
+            fs::remove_file(path)?;
+        }
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, path)?;
+    #[cfg(windows)]
+    if let Err(err) = std::os::windows::fs::symlink_file(target, path) {
+        // Needs developer mode or admin rights
+        println!("warn: can't create symlink {:?}: {}", path, err);
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use crate::{
+    blockhash::BlockIdHash, database::DFileDatabase, restoring::check_block_hash,
+    ziparchive::ZipArchiveWrapper,
+};
+use base64::{engine::general_purpose, Engine};
+use eyre::{eyre, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use smallvec::SmallVec;
+use std::path::PathBuf;
+
+/// A dblock entry that failed to read or doesn't hash to its name
+pub struct BadBlock {
+    pub volume: PathBuf,
+    pub name: String,
+    pub error: eyre::Report,
+}
+
+#[derive(Default)]
+pub struct VerifyBlocksOutcome {
+    pub volumes: usize,
+    /// Entries named like a block, read and hashed
+    pub blocks: usize,
+    /// Entries of other names, not checked (see the index diagnostics)
+    pub skipped_names: usize,
+    pub bad: Vec<BadBlock>,
+}
+
+/// Reads every entry of every indexed dblock and checks it against the block
+/// hash its name decodes to, with the manifest's BlockHash algorithm
+///
+/// Volumes are checked in parallel, each front to back. Every copy of a block
+/// is checked, not only the one a restore would read
+pub fn verify_blocks(db: &DFileDatabase) -> Result<VerifyBlocksOutcome> {
+    let volumes = db.volumes();
+    let pb = ProgressBar::new(volumes.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "[{elapsed_precise}] {wide_bar:40.cyan/blue} {pos:>7}/{len:7} {msg} [{eta_precise}]",
+            )?
+            .progress_chars("##-"),
+    );
+    let outcome = volumes
+        .par_iter()
+        .map(|volume| {
+            let outcome = verify_volume(db, volume);
+            pb.inc(1);
+            outcome
+        })
+        .reduce(VerifyBlocksOutcome::default, |mut a, b| {
+            a.volumes += b.volumes;
+            a.blocks += b.blocks;
+            a.skipped_names += b.skipped_names;
+            a.bad.extend(b.bad);
+            a
+        });
+    pb.finish_and_clear();
+    Ok(outcome)
+}
+
+fn verify_volume(db: &DFileDatabase, volume: &ZipArchiveWrapper) -> VerifyBlocksOutcome {
+    let path = &volume.ziplocation.path;
+    let mut outcome = VerifyBlocksOutcome {
+        volumes: 1,
+        ..Default::default()
+    };
+    let mut archive = match volume.archive() {
+        Ok(archive) => archive,
+        Err(error) => {
+            // Nothing of it can be read, reported as one entry
+            outcome.bad.push(BadBlock {
+                volume: path.clone(),
+                name: String::new(),
+                error,
+            });
+            return outcome;
+        }
+    };
+    let names: Vec<String> = archive
+        .file_names_ordered()
+        .filter(|name| *name != "manifest")
+        .map(String::from)
+        .collect();
+    let mut buf = Vec::with_capacity(db.block_size());
+    for name in names {
+        let hash = match general_purpose::URL_SAFE.decode(&name) {
+            Ok(hash) if hash.len() == db.hash_size() => BlockIdHash {
+                hash: SmallVec::from_vec(hash),
+            },
+            _ => {
+                outcome.skipped_names += 1;
+                continue;
+            }
+        };
+        outcome.blocks += 1;
+        buf.clear();
+        let checked = db
+            .read_block_entry(archive.as_mut(), &hash, &mut buf)
+            .and_then(|_| check_block_hash(db.block_hash_algo(), &hash, &buf));
+        if let Err(error) = checked {
+            outcome.bad.push(BadBlock {
+                volume: path.clone(),
+                name,
+                error,
+            });
+        }
+    }
+    outcome
+}
+
+/// Prints the bad blocks, errors if there are any
+pub fn report_verify_blocks(outcome: &VerifyBlocksOutcome) -> Result<()> {
+    for bad in &outcome.bad {
+        if bad.name.is_empty() {
+            println!("unreadable dblock {:?}: {:#}", bad.volume, bad.error);
+        } else {
+            println!(
+                "bad block {} in {:?}: {:#}",
+                bad.name, bad.volume, bad.error
+            );
+        }
+    }
+    if outcome.skipped_names > 0 {
+        println!(
+            "{} entries are not named like a block and were not checked",
+            outcome.skipped_names
+        );
+    }
+    if outcome.bad.is_empty() {
+        println!(
+            "All {} blocks in {} dblocks match their hash",
+            outcome.blocks, outcome.volumes
+        );
+        return Ok(());
+    }
+    Err(eyre!(
+        "{} damaged or unreadable, out of {} blocks in {} dblocks",
+        outcome.bad.len(),
+        outcome.blocks,
+        outcome.volumes
+    ))
+}
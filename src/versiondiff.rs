@@ -0,0 +1,35 @@
+use crate::{dfileentry::FileEntry, dfiletype::FileType, FileEntries};
+use std::collections::HashMap;
+
+/// Files of newer that were added or whose content changed since older
+///
+/// Compares the file hash, so a file touched without changes is left out.
+/// Folders and symlinks are dropped, parents get created as files need them
+pub fn changed_files_since(newer: FileEntries, older: &FileEntries) -> FileEntries {
+    let older_hashes: HashMap<&str, &FileType> = older
+        .entries
+        .iter()
+        .filter(|e| e.is_file())
+        .map(|e| (e.path.as_str(), &e.file_type))
+        .collect();
+
+    let entries: Vec<FileEntry> = newer
+        .entries
+        .into_iter()
+        .filter(|e| e.is_file())
+        .filter(
+            |e| match (older_hashes.get(e.path.as_str()), &e.file_type) {
+                (
+                    Some(FileType::File {
+                        hash: old_hash,
+                        size: old_size,
+                        ..
+                    }),
+                    FileType::File { hash, size, .. },
+                ) => old_hash != hash || old_size != size,
+                _ => true,
+            },
+        )
+        .collect();
+    FileEntries { entries }
+}
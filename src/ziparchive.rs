@@ -1,11 +1,16 @@
-use eyre::Result;
+use crate::backend;
+use crate::blockarchive::{open_block_archive, BlockArchive};
+use crate::crypto::{decrypt_maybe, AesCryptKeys, ReadSeek, AESCRYPT_MAGIC};
+use crate::flags::ZipReader;
+use crate::sevenzarchive::SevenZFolderCache;
+use eyre::{eyre, Context, Result};
+use memmap2::Mmap;
 use std::{
     fs::File,
-    io::{BufRead, BufReader, IoSliceMut, Read, Seek, SeekFrom},
-    path::PathBuf,
-    sync::{atomic::AtomicU32, Arc},
+    io::{BufRead, BufReader, Cursor, IoSliceMut, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU32, Arc, Condvar, Mutex, OnceLock},
 };
-use zip::ZipArchive;
 
 #[derive(Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 /// Path to dblock.zip
@@ -41,12 +46,48 @@ impl PartialOrd for BlockLocation {
 
 pub struct ZipArchiveWrapper {
     pub ziplocation: Arc<ZipLocation>,
-    pub archive: ZipArchive<MyCloneFileReader>,
+    config: Arc<MyCloneFileConfig>,
+    /// Unset for volumes loaded from the index cache, until a block is read
+    archive: OnceLock<Box<dyn BlockArchive>>,
 }
 
 impl ZipArchiveWrapper {
+    pub fn new(
+        ziplocation: Arc<ZipLocation>,
+        config: Arc<MyCloneFileConfig>,
+        archive: Box<dyn BlockArchive>,
+    ) -> Self {
+        Self {
+            ziplocation,
+            config,
+            archive: OnceLock::from(archive),
+        }
+    }
+
+    /// Reads the central directory (or 7z header) on first use
+    pub fn unopened(ziplocation: Arc<ZipLocation>, config: Arc<MyCloneFileConfig>) -> Self {
+        Self {
+            ziplocation,
+            config,
+            archive: OnceLock::new(),
+        }
+    }
+
+    pub fn archive(&self) -> Result<Box<dyn BlockArchive>> {
+        if let Some(archive) = self.archive.get() {
+            return Ok(archive.clone_archive());
+        }
+        let archive = open_block_archive(self.config.clone())
+            .wrap_err_with(|| format!("open {:?}", self.ziplocation.path))?;
+        // Keep a clone, it holds no file descriptor until read
+        self.archive.get_or_init(|| archive.clone_archive());
+        Ok(archive)
+    }
+
+    /// Unopened archives are only found through hash2path
     pub fn get_block_location(&self, block_base64: &str) -> Option<BlockLocation> {
         self.archive
+            .get()?
             .get_file_index(block_base64)
             .map(|index| BlockLocation {
                 file_index: index as u32,
@@ -55,7 +96,134 @@ impl ZipArchiveWrapper {
     }
 
     pub fn contains_file_name(&self, block_base64: &str) -> bool {
-        self.archive.contains_file_name(block_base64)
+        self.archive
+            .get()
+            .is_some_and(|archive| archive.contains_file_name(block_base64))
+    }
+}
+
+/// Errors on an AESCrypt file that wasn't decrypted instead of failing later as a broken zip
+///
+/// Checked per file, a backup set may mix encrypted and plain volumes
+pub fn check_not_encrypted(reader: &mut impl BufRead, path: &Path) -> Result<()> {
+    if reader.fill_buf()?.starts_with(AESCRYPT_MAGIC) {
+        return Err(eyre!(
+            "{:?} is AES-encrypted, pass the backup passphrase with --passphrase",
+            path
+        ));
+    }
+    Ok(())
+}
+
+/// End of central directory signature, the last record of every zip
+const EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+/// EOCD record plus the longest zip comment
+const EOCD_SEARCH_LEN: u64 = 22 + u16::MAX as u64;
+
+/// Explains why a zip couldn't be opened
+///
+/// An empty file or one without an end of central directory is an incomplete
+/// download, e.g. an interrupted upload. Anything else is a corrupt zip
+pub fn diagnose_unreadable_zip(path: &Path, err: zip::result::ZipError) -> eyre::Report {
+    match incomplete_download(path) {
+        Ok(Some(what)) => eyre!(
+            "{:?} {}, an incomplete download, download it again",
+            path,
+            what
+        ),
+        _ => eyre::Report::new(err).wrap_err(format!("{:?} is a corrupt zip", path)),
+    }
+}
+
+/// What's missing if path looks cut off, None if it has a zip end record
+fn incomplete_download(path: &Path) -> std::io::Result<Option<&'static str>> {
+    let mut file = backend::open(path)?;
+    let len = file.seek(SeekFrom::End(0))?;
+    file.rewind()?;
+    if len == 0 {
+        return Ok(Some("is empty"));
+    }
+    let mut magic = [0u8; 3];
+    let n = file.read(&mut magic)?;
+    if magic[..n] == *AESCRYPT_MAGIC {
+        // The end record is encrypted, a cut off file fails to decrypt instead
+        return Ok(None);
+    }
+    let tail_len = len.min(EOCD_SEARCH_LEN);
+    file.seek(SeekFrom::End(-(tail_len as i64)))?;
+    let mut tail = vec![0; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    if tail
+        .windows(EOCD_SIGNATURE.len())
+        .any(|w| w == EOCD_SIGNATURE)
+    {
+        Ok(None)
+    } else {
+        Ok(Some("is truncated, it has no zip end record"))
+    }
+}
+
+/// Counting semaphore for dblock file descriptors, shared by all readers of a db
+pub struct OpenArchiveLimit {
+    max: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl OpenArchiveLimit {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max: max.max(1),
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        })
+    }
+
+    /// Waits until a file may be opened
+    fn acquire(self: &Arc<Self>) -> OpenArchivePermit {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.max {
+            open = self.closed.wait(open).unwrap();
+        }
+        *open += 1;
+        OpenArchivePermit {
+            limit: self.clone(),
+        }
+    }
+}
+
+/// Released when the file it was taken for is closed
+struct OpenArchivePermit {
+    limit: Arc<OpenArchiveLimit>,
+}
+
+impl Drop for OpenArchivePermit {
+    fn drop(&mut self) {
+        *self.limit.open.lock().unwrap() -= 1;
+        self.limit.closed.notify_one();
+    }
+}
+
+/// Default for --max-open-archives: 3/4 of the soft fd limit, the rest is
+/// left for restored files, dlists and stdio. None if there is no limit
+pub fn default_max_open_archives() -> Option<usize> {
+    #[cfg(unix)]
+    {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+            || limit.rlim_cur == libc::RLIM_INFINITY
+        {
+            return None;
+        }
+        let soft = usize::try_from(limit.rlim_cur).unwrap_or(usize::MAX);
+        Some((soft - soft / 4).max(1))
+    }
+    #[cfg(not(unix))]
+    {
+        None
     }
 }
 
@@ -65,77 +233,173 @@ pub struct MyCloneFileConfig {
     /// Bigger buf helps with large file reads.
     /// Smaller buf does less redundant byte reads from disk when indexing.
     pub buf_capacity: AtomicU32,
+    /// None to open as many files as needed
+    pub open_limit: Option<Arc<OpenArchiveLimit>>,
+    /// Decrypts the file if it is AES-encrypted
+    pub passphrase: Option<String>,
+    /// Filled on the first open of an encrypted file
+    pub aes_keys: OnceLock<Arc<AesCryptKeys>>,
+    pub reader: ZipReader,
+    /// Filled on the first open with ZipReader::Mmap, None if the file is encrypted
+    pub mmap: OnceLock<Option<SharedMmap>>,
+    /// Shared by all volumes of a db, only 7z volumes use it
+    pub sevenz_folders: Arc<SevenZFolderCache>,
+}
+
+impl MyCloneFileConfig {
+    /// The shared map of the file, None if it has to be decrypted while reading
+    fn mmap(&self) -> std::io::Result<Option<SharedMmap>> {
+        if let Some(mmap) = self.mmap.get() {
+            return Ok(mmap.clone());
+        }
+        if !backend::is_on_disk(&self.path) {
+            // Members of a tar aren't page aligned, objects aren't files
+            return Ok(None);
+        }
+        let file = File::open(&self.path)?;
+        // Safety: dblocks aren't written while restoring. A file truncated by another
+        // process would fault on read, the same as a failing disk
+        let mmap = unsafe { Mmap::map(&file)? };
+        let mmap = match self.passphrase.is_some() && mmap.starts_with(AESCRYPT_MAGIC) {
+            true => None,
+            false => Some(SharedMmap(Arc::new(mmap))),
+        };
+        Ok(self.mmap.get_or_init(|| mmap).clone())
+    }
+}
+
+/// A memory map readable through a Cursor by many clones at once
+#[derive(Clone)]
+pub struct SharedMmap(Arc<Mmap>);
+
+impl AsRef<[u8]> for SharedMmap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+enum OpenFile {
+    Buffered {
+        buf_reader: BufReader<Box<dyn ReadSeek>>,
+        _permit: Option<OpenArchivePermit>,
+    },
+    /// Holds no file descriptor, the map outlives it
+    Mapped(Cursor<SharedMmap>),
 }
 
-/// Used to share ZipArchive across many threads
+impl OpenFile {
+    fn open(config: &MyCloneFileConfig) -> std::io::Result<Self> {
+        if config.reader == ZipReader::Mmap {
+            if let Some(mmap) = config.mmap()? {
+                return Ok(Self::Mapped(Cursor::new(mmap)));
+            }
+        }
+        let permit = config.open_limit.as_ref().map(|limit| limit.acquire());
+        let target_file = decrypt_maybe(
+            backend::open(&config.path)?,
+            &config.path,
+            config.passphrase.as_deref(),
+            &config.aes_keys,
+        )
+        .map_err(|err| std::io::Error::other(format!("{:#}", err)))?;
+        let cap = config
+            .buf_capacity
+            .load(std::sync::atomic::Ordering::Relaxed);
+        Ok(Self::Buffered {
+            buf_reader: BufReader::with_capacity(cap as usize, target_file),
+            _permit: permit,
+        })
+    }
+
+    fn reader(&mut self) -> &mut dyn BufReadSeek {
+        match self {
+            Self::Buffered { buf_reader, .. } => buf_reader,
+            Self::Mapped(cursor) => cursor,
+        }
+    }
+}
+
+trait BufReadSeek: BufRead + Seek {}
+impl<T: BufRead + Seek> BufReadSeek for T {}
+
+/// Used to share ZipArchive across many threads
 ///
 /// Multiple ZipArchive structs would allocate too much Vec<Files> in rayon threads
 ///
-/// Therefore we open file again on every .clone()
+/// Therefore we open file again after every .clone(), on first use.
+/// A clone that is never read (like the one cached in the db) holds no file descriptor
 pub struct MyCloneFileReader {
     pub config: Arc<MyCloneFileConfig>,
-    buf_reader: BufReader<File>,
+    file: Option<OpenFile>,
 }
 
 impl Clone for MyCloneFileReader {
     fn clone(&self) -> Self {
-        Self::new(self.config.clone()).unwrap()
+        Self {
+            config: self.config.clone(),
+            file: None,
+        }
     }
 }
 
 impl MyCloneFileReader {
+    /// Blocks while config.open_limit files are already open
     pub fn new(config: Arc<MyCloneFileConfig>) -> Result<Self> {
-        let target_file = File::open(&config.path)?;
-        let cap = config
-            .buf_capacity
-            .load(std::sync::atomic::Ordering::Relaxed);
-        let filebuf = BufReader::with_capacity(cap as usize, target_file);
-
+        let file = OpenFile::open(&config)?;
         Ok(Self {
             config,
-            buf_reader: filebuf,
+            file: Some(file),
         })
     }
+
+    fn buf_reader(&mut self) -> std::io::Result<&mut dyn BufReadSeek> {
+        if self.file.is_none() {
+            self.file = Some(OpenFile::open(&self.config)?);
+        }
+        Ok(self.file.as_mut().unwrap().reader())
+    }
 }
 
 impl Read for MyCloneFileReader {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.buf_reader.read(buf)
+        self.buf_reader()?.read(buf)
     }
 
     fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
-        self.buf_reader.read_exact(buf)
+        self.buf_reader()?.read_exact(buf)
     }
 
     fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> std::io::Result<usize> {
-        self.buf_reader.read_vectored(bufs)
+        self.buf_reader()?.read_vectored(bufs)
     }
 
     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> std::io::Result<usize> {
-        self.buf_reader.read_to_end(buf)
+        self.buf_reader()?.read_to_end(buf)
     }
 
     fn read_to_string(&mut self, buf: &mut String) -> std::io::Result<usize> {
-        self.buf_reader.read_to_string(buf)
+        self.buf_reader()?.read_to_string(buf)
     }
 }
 
 impl Seek for MyCloneFileReader {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-        self.buf_reader.seek(pos)
+        self.buf_reader()?.seek(pos)
     }
 
     fn stream_position(&mut self) -> std::io::Result<u64> {
-        self.buf_reader.stream_position()
+        self.buf_reader()?.stream_position()
     }
 }
 
 impl BufRead for MyCloneFileReader {
     fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
-        self.buf_reader.fill_buf()
+        self.buf_reader()?.fill_buf()
     }
 
     fn consume(&mut self, amt: usize) {
-        self.buf_reader.consume(amt)
+        if let Some(file) = &mut self.file {
+            file.reader().consume(amt)
+        }
     }
 }
@@ -0,0 +1,136 @@
+//! Volumes encrypted in the AES Crypt format, found as `.aes` files in the backup dir
+
+mod common;
+
+use aes::cipher::{block_padding::NoPadding, BlockEncryptMut, KeyIvInit};
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use hmac::{Hmac, Mac};
+use rust_duplicati_restore::crypto::{AesCryptKeys, AesCryptReader};
+use rust_duplicati_restore::{restore, RestoreConfig};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+/// Encrypts plain as an AES Crypt v2 file, like SharpAESCrypt does
+fn aes_crypt(plain: &[u8], passphrase: &str) -> Vec<u8> {
+    let iv1 = [0x11u8; 16];
+    let iv2 = [0x22u8; 16];
+    let key2 = [0x33u8; 32];
+
+    let mut out = b"AES\x02\x00".to_vec();
+    let created_by = b"CREATED_BY\0rust-duplicati-restore tests";
+    out.extend_from_slice(&(created_by.len() as u16).to_be_bytes());
+    out.extend_from_slice(created_by);
+    out.extend_from_slice(&[0, 0]);
+
+    // SHA-256 of the IV and the UTF-16LE passphrase, 8192 times
+    let passphrase: Vec<u8> = passphrase
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let mut key1 = [0u8; 32];
+    key1[..16].copy_from_slice(&iv1);
+    for _ in 0..8192 {
+        key1 = Sha256::new()
+            .chain_update(key1)
+            .chain_update(&passphrase)
+            .finalize()
+            .into();
+    }
+    let mut wrapped = [iv2.as_slice(), key2.as_slice()].concat();
+    Aes256CbcEnc::new(&key1.into(), &iv1.into())
+        .encrypt_padded_mut::<NoPadding>(&mut wrapped, 48)
+        .unwrap();
+    out.extend_from_slice(&iv1);
+    out.extend_from_slice(&wrapped);
+    out.extend_from_slice(&hmac(&key1, &wrapped));
+
+    let mut cipher = plain.to_vec();
+    cipher.resize(plain.len().div_ceil(16) * 16, 0);
+    let len = cipher.len();
+    Aes256CbcEnc::new(&key2.into(), &iv2.into())
+        .encrypt_padded_mut::<NoPadding>(&mut cipher, len)
+        .unwrap();
+    out.extend_from_slice(&cipher);
+    out.push((plain.len() % 16) as u8);
+    out.extend_from_slice(&hmac(&key2, &cipher));
+    out
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[test]
+fn encrypted_backup_restores() {
+    let dir = test_dir("aes-crypt");
+    // Over one 64 KiB decrypt chunk, so reads cross into the next one
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i % 239) as u8).collect();
+    let plain_dir = BackupBuilder::new(4096)
+        .folder("C:\\d\\")
+        .file("C:\\d\\big.bin", &content, METHOD_STORED)
+        .file("C:\\d\\small.txt", b"hello", METHOD_STORED)
+        .write(&dir.join("plain"));
+    let backup_dir = dir.join("backup");
+    fs::create_dir_all(&backup_dir).unwrap();
+    for volume in fs::read_dir(&plain_dir).unwrap() {
+        let path = volume.unwrap().path();
+        let name = format!("{}.aes", path.file_name().unwrap().to_str().unwrap());
+        let encrypted = aes_crypt(&fs::read(&path).unwrap(), "secret");
+        fs::write(backup_dir.join(name), encrypted).unwrap();
+    }
+
+    let restore_dir = dir.join("restore");
+    let config =
+        RestoreConfig::new(&backup_dir, Some(restore_dir.clone())).with_passphrase("secret");
+    restore(config).unwrap();
+    let root = restore_dir.join("C").join("d");
+    assert_eq!(fs::read(root.join("big.bin")).unwrap(), content);
+    assert_eq!(fs::read(root.join("small.txt")).unwrap(), b"hello");
+
+    let config = RestoreConfig::new(&backup_dir, Some(dir.join("wrong"))).with_passphrase("nope");
+    let err = restore(config).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("wrong passphrase"),
+        "{:#}",
+        err
+    );
+    assert!(!dir.join("wrong").join("C").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reads_after_unaligned_seeks() {
+    let plain: Vec<u8> = (0..70_001u32).map(|i| (i % 251) as u8).collect();
+    let mut encrypted = Cursor::new(aes_crypt(&plain, "secret"));
+    let keys = Arc::new(AesCryptKeys::read(&mut encrypted, "secret").unwrap());
+    let mut reader = AesCryptReader::new(encrypted, keys);
+
+    let mut read_at = |pos: SeekFrom, len: usize| {
+        let mut buf = vec![0u8; len];
+        reader.seek(pos).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        buf
+    };
+    assert_eq!(read_at(SeekFrom::Start(21), 40), &plain[21..61]);
+    // Across the end of the first decrypted chunk
+    assert_eq!(read_at(SeekFrom::Start(65_530), 13), &plain[65_530..65_543]);
+    assert_eq!(read_at(SeekFrom::Start(3), 5), &plain[3..8]);
+    // The last block is partly padding
+    assert_eq!(read_at(SeekFrom::End(-7), 7), &plain[plain.len() - 7..]);
+
+    let mut rest = Vec::new();
+    reader.seek(SeekFrom::Start(69_999)).unwrap();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, &plain[69_999..]);
+
+    let mut encrypted = Cursor::new(aes_crypt(&plain, "secret"));
+    let err = AesCryptKeys::read(&mut encrypted, "Secret").err().unwrap();
+    assert!(err.to_string().contains("wrong passphrase"), "{}", err);
+}
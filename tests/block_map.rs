@@ -0,0 +1,46 @@
+//! block_map lists where each byte range of a file is stored, without reading its content
+
+mod common;
+
+use common::{sha256, test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::blockmap::block_map;
+use rust_duplicati_restore::{parse_dlist_file, read_manifest, DFileDatabase};
+
+#[test]
+fn multiblock_file_map() {
+    let dir = test_dir("block-map");
+    let content: Vec<u8> = (0..2500u32).map(|i| (i % 253) as u8).collect();
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\big.bin", &content, METHOD_STORED)
+        .file("C:\\d\\small.txt", b"small", METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let dblock = backup_dir.join("duplicati-b0001.dblock.zip");
+    let db = DFileDatabase::new(&read_manifest(&dlist, None).unwrap(), false).unwrap();
+    db.create_block_id_to_filenames(std::slice::from_ref(&dblock))
+        .unwrap();
+    let entries = parse_dlist_file(&dlist, None).unwrap();
+
+    let map = block_map(&entries.entries[1], &db).unwrap();
+    let ranges: Vec<(u64, u64)> = map.iter().map(|b| (b.offset, b.len)).collect();
+    assert_eq!(ranges, [(0, 1024), (1024, 1024), (2048, 452)]);
+    for (block, chunk) in map.iter().zip(content.chunks(1024)) {
+        assert_eq!(block.hash.hash.as_slice(), sha256(chunk));
+        assert_eq!(block.volume, dblock);
+    }
+    // The metadata block is first in the dblock, then the content blocks in order
+    let indexes: Vec<u32> = map.iter().map(|b| b.file_index).collect();
+    assert_eq!(indexes, [1, 2, 3]);
+
+    // A single block file is one range named by the file hash
+    let map = block_map(&entries.entries[2], &db).unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!((map[0].offset, map[0].len), (0, 5));
+    assert_eq!(map[0].hash.hash.as_slice(), sha256(b"small"));
+
+    assert!(block_map(&entries.entries[0], &db).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
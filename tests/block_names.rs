@@ -0,0 +1,54 @@
+//! Block hashes convert between bytes, the hex shown in errors and the base64 names in dblocks
+
+use rust_duplicati_restore::blockhash::BlockIdHash;
+
+/// 0xfb and 0xff encode to '+' and '/' in base64, '-' and '_' url-safe
+fn edge_bytes() -> Vec<u8> {
+    let mut bytes = vec![0xfb, 0xff, 0xbf, 0x3e, 0xef, 0xff];
+    bytes.extend((0u8..26).map(|i| i.wrapping_mul(37)));
+    bytes
+}
+
+#[test]
+fn hex_base64_bytes_round_trip() {
+    let bytes = edge_bytes();
+    let hash = BlockIdHash::from_bytes(&bytes).unwrap();
+    let hex = hash.to_string();
+    assert_eq!(hex.len(), 64);
+    assert!(hex.starts_with("fbffbf3eefff"));
+    assert_eq!(BlockIdHash::from_hex(&hex).unwrap(), hash);
+    assert_eq!(BlockIdHash::from_hex(&hex.to_uppercase()).unwrap(), hash);
+
+    let mut buf = [0u8; 64];
+    let urlsafe = hash.as_base64_urlsafe(&mut buf).to_string();
+    assert!(urlsafe.starts_with("-_-_Pu__"), "{}", urlsafe);
+    let standard = hash.as_base64(&mut buf).to_string();
+    assert!(standard.starts_with("+/+/Pu//"), "{}", standard);
+
+    let from_urlsafe = BlockIdHash::from_base64_urlsafe(&urlsafe).unwrap();
+    assert_eq!(from_urlsafe.hash.as_slice(), &bytes[..]);
+    assert_eq!(from_urlsafe.to_string(), hex);
+    assert_eq!(BlockIdHash::from_base64(&standard).unwrap(), hash);
+    // The alphabets don't mix
+    assert!(BlockIdHash::from_base64(&urlsafe).is_none());
+    assert!(BlockIdHash::from_base64_urlsafe(&standard).is_none());
+}
+
+#[test]
+fn from_hex_rejects_other_lengths() {
+    let hex = BlockIdHash::from_bytes(&edge_bytes()).unwrap().to_string();
+    assert!(BlockIdHash::from_hex(&hex[..62]).is_none());
+    assert!(BlockIdHash::from_hex(&format!("{}00", hex)).is_none());
+    assert!(BlockIdHash::from_hex(&hex[..63]).is_none());
+    assert!(BlockIdHash::from_hex("").is_none());
+    // SHA1 and MD5 lengths
+    assert_eq!(BlockIdHash::from_hex(&hex[..40]).unwrap().hash.len(), 20);
+    assert_eq!(BlockIdHash::from_hex(&hex[..32]).unwrap().hash.len(), 16);
+    assert!(BlockIdHash::from_hex(&hex.replace('f', "g")).is_none());
+    assert_eq!(
+        BlockIdHash::from_hex(&format!(" {}\n", hex))
+            .unwrap()
+            .to_string(),
+        hex
+    );
+}
@@ -0,0 +1,67 @@
+//! A DFileDatabase with a HashMapBlockProvider restores without reading any dblock
+
+mod common;
+
+use common::{sha256, test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::blockhash::BlockIdHash;
+use rust_duplicati_restore::blockprovider::HashMapBlockProvider;
+use rust_duplicati_restore::restoring::RestoreContext;
+use rust_duplicati_restore::{parse_dlist_file, read_manifest, restore_entry};
+use rust_duplicati_restore::{BlockProvider, DFileDatabase, RestoreParams};
+use std::fs;
+use std::sync::Arc;
+
+#[test]
+fn restore_from_hashmap_provider() {
+    let dir = test_dir("block-provider");
+    let big: Vec<u8> = (0..2500u32).map(|i| (i % 241) as u8).collect();
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\big.bin", &big, METHOD_STORED)
+        .file("C:\\d\\small.txt", b"small", METHOD_STORED)
+        .write(&dir.join("backup"));
+    // Only the dlist is used, the blocks come from the provider
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    fs::remove_file(backup_dir.join("duplicati-b0001.dblock.zip")).unwrap();
+
+    let mut provider = HashMapBlockProvider::default();
+    let mut insert = |block: &[u8]| {
+        let hash = sha256(block);
+        provider.insert(&BlockIdHash::from_bytes(&hash).unwrap(), block.to_vec());
+        hash
+    };
+    let blocklist: Vec<u8> = big.chunks(1024).flat_map(&mut insert).collect();
+    insert(&blocklist);
+    insert(b"small");
+
+    let missing = BlockIdHash::from_bytes(&sha256(b"other")).unwrap();
+    assert!(provider
+        .get_block(&missing, &mut Vec::new())
+        .unwrap()
+        .is_none());
+    let mut buf = b"kept".to_vec();
+    let small = BlockIdHash::from_bytes(&sha256(b"small")).unwrap();
+    assert_eq!(provider.get_block(&small, &mut buf).unwrap(), Some(5));
+    assert_eq!(buf, b"keptsmall");
+
+    let db = DFileDatabase::new(&read_manifest(&dlist, None).unwrap(), false)
+        .unwrap()
+        .with_block_provider(Box::new(provider));
+    let entries = parse_dlist_file(&dlist, None).unwrap();
+    let restore_dir = dir.join("restore");
+    let params = RestoreParams::builder()
+        .with_db(Arc::new(db))
+        .with_restore_path(restore_dir.to_str().unwrap())
+        .build(&entries)
+        .unwrap();
+    let context = RestoreContext::new();
+    for entry in &entries.entries {
+        restore_entry(entry, &params, &context).unwrap();
+    }
+
+    let root = restore_dir.join("C").join("d");
+    assert_eq!(fs::read(root.join("big.bin")).unwrap(), big);
+    assert_eq!(fs::read(root.join("small.txt")).unwrap(), b"small");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,252 @@
+//! Writes small Duplicati backups for the integration tests
+
+#![allow(dead_code)] // Each test crate uses a different part
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const METHOD_STORED: u16 = 0;
+pub const METHOD_ZSTD: u16 = 93;
+
+pub struct ZipEntry {
+    name: String,
+    method: u16,
+    data: Vec<u8>,
+    /// Of the uncompressed content
+    crc: u32,
+    uncompressed_len: u32,
+}
+
+impl ZipEntry {
+    pub fn new(name: &str, content: &[u8], method: u16) -> Self {
+        let data = match method {
+            METHOD_ZSTD => zstd::bulk::compress(content, 3).unwrap(),
+            _ => content.to_vec(),
+        };
+        Self {
+            name: name.to_string(),
+            method,
+            data,
+            crc: crc32fast::hash(content),
+            uncompressed_len: content.len() as u32,
+        }
+    }
+}
+
+/// A zip with the entries as given, the zip crate can't write zstd without its feature
+pub fn write_zip(path: &Path, entries: &[ZipEntry]) {
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for entry in entries {
+        let offset = out.len() as u32;
+        let common = |buf: &mut Vec<u8>| {
+            buf.extend_from_slice(&63u16.to_le_bytes()); // version needed
+            buf.extend_from_slice(&0u16.to_le_bytes()); // flags
+            buf.extend_from_slice(&entry.method.to_le_bytes());
+            buf.extend_from_slice(&0u16.to_le_bytes()); // time
+            buf.extend_from_slice(&0x21u16.to_le_bytes()); // date, 1980-01-01
+            buf.extend_from_slice(&entry.crc.to_le_bytes());
+            buf.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&entry.uncompressed_len.to_le_bytes());
+            buf.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            buf.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        };
+        out.extend_from_slice(b"PK\x03\x04");
+        common(&mut out);
+        out.extend_from_slice(entry.name.as_bytes());
+        out.extend_from_slice(&entry.data);
+
+        central.extend_from_slice(b"PK\x01\x02");
+        central.extend_from_slice(&63u16.to_le_bytes()); // version made by
+        common(&mut central);
+        central.extend_from_slice(&[0; 6]); // comment length, disk, internal attributes
+        central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(entry.name.as_bytes());
+    }
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(b"PK\x05\x06");
+    out.extend_from_slice(&[0; 4]); // disk numbers
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    fs::write(path, out).unwrap();
+}
+
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+pub fn sha1(data: &[u8]) -> Vec<u8> {
+    sha1::Sha1::digest(data).to_vec()
+}
+
+fn base64(hash: &[u8]) -> String {
+    general_purpose::STANDARD.encode(hash)
+}
+
+/// One dlist and one dblock, SHA256 hashes unless with_sha1, every entry with the same metadata
+pub struct BackupBuilder {
+    block_size: usize,
+    /// BlockHash and FileHash of the manifest
+    hash_name: &'static str,
+    hash: fn(&[u8]) -> Vec<u8>,
+    dblock: Vec<ZipEntry>,
+    filelist: Vec<serde_json::Value>,
+    meta_hash: Vec<u8>,
+    meta_len: usize,
+}
+
+impl BackupBuilder {
+    pub fn new(block_size: usize) -> Self {
+        Self::with_hash(block_size, "SHA256", sha256)
+    }
+
+    /// SHA1 block and file hashes, 20 bytes each in the blocklists
+    pub fn with_sha1(block_size: usize) -> Self {
+        Self::with_hash(block_size, "SHA1", sha1)
+    }
+
+    fn with_hash(block_size: usize, hash_name: &'static str, hash: fn(&[u8]) -> Vec<u8>) -> Self {
+        let mut builder = Self {
+            block_size,
+            hash_name,
+            hash,
+            dblock: Vec::new(),
+            filelist: Vec::new(),
+            meta_hash: Vec::new(),
+            meta_len: 0,
+        };
+        let meta = br#"{"CoreLastWritetime": "638398000000000000"}"#;
+        builder.meta_hash = builder.add_block(meta, METHOD_STORED);
+        builder.meta_len = meta.len();
+        builder
+    }
+
+    fn add_block(&mut self, content: &[u8], method: u16) -> Vec<u8> {
+        let hash = (self.hash)(content);
+        let name = general_purpose::URL_SAFE.encode(&hash);
+        self.dblock.push(ZipEntry::new(&name, content, method));
+        hash
+    }
+
+    /// path as Duplicati stores it, e.g. "C:\\d\\sub\\"
+    pub fn folder(&mut self, path: &str) -> &mut Self {
+        self.filelist.push(serde_json::json!({
+            "type": "Folder",
+            "path": path,
+            "metahash": base64(&self.meta_hash),
+            "metasize": self.meta_len,
+            "metablockhash": base64(&self.meta_hash),
+        }));
+        self
+    }
+
+    /// A folder whose metablockhash is empty, as in some dlists
+    pub fn folder_without_metablock(&mut self, path: &str) -> &mut Self {
+        self.folder(path);
+        let folder = self.filelist.last_mut().unwrap();
+        folder["metablockhash"] = serde_json::json!("");
+        self
+    }
+
+    /// Content blocks are stored with method, blocklists uncompressed
+    pub fn file(&mut self, path: &str, content: &[u8], method: u16) -> &mut Self {
+        let mut entry = serde_json::json!({
+            "type": "File",
+            "path": path,
+            "hash": base64(&(self.hash)(content)),
+            "size": content.len(),
+            "time": "20231231T235959Z",
+            "metahash": base64(&self.meta_hash),
+            "metasize": self.meta_len,
+        });
+        if content.len() <= self.block_size {
+            // A single block is named by the file hash, there is no blocklist
+            self.add_block(content, method);
+        } else {
+            let mut blocklist = Vec::new();
+            for chunk in content.chunks(self.block_size) {
+                blocklist.extend_from_slice(&self.add_block(chunk, method));
+            }
+            let blocklist_hash = self.add_block(&blocklist, METHOD_STORED);
+            entry["blocklists"] = serde_json::json!([base64(&blocklist_hash)]);
+        }
+        self.filelist.push(entry);
+        self
+    }
+
+    /// Like file, but the content blocks are left out of the dblock
+    pub fn file_with_missing_blocks(&mut self, path: &str, content: &[u8]) -> &mut Self {
+        let stored = self.dblock.len();
+        self.file(path, content, METHOD_STORED);
+        let blocklist = (content.len() > self.block_size).then(|| self.dblock.pop().unwrap());
+        self.dblock.truncate(stored);
+        self.dblock.extend(blocklist);
+        self
+    }
+
+    /// Sets the size the dlist declares for the file added last
+    pub fn declared_size(&mut self, size: usize) -> &mut Self {
+        let file = self.filelist.last_mut().unwrap();
+        file["size"] = serde_json::json!(size);
+        self
+    }
+
+    /// Like file, but the first content block is stored with its first byte changed
+    pub fn file_with_corrupt_block(&mut self, path: &str, content: &[u8]) -> &mut Self {
+        let stored = self.dblock.len();
+        self.file(path, content, METHOD_STORED);
+        let block = &mut self.dblock[stored];
+        let mut corrupt = content[..content.len().min(self.block_size)].to_vec();
+        corrupt[0] ^= 0xff;
+        *block = ZipEntry::new(&block.name, &corrupt, METHOD_STORED);
+        self
+    }
+
+    /// Writes the backup to a new directory, returns it
+    pub fn write(&self, dir: &Path) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        write_zip(&dir.join("duplicati-b0001.dblock.zip"), &self.dblock);
+        let manifest = serde_json::json!({
+            "Version": 2,
+            "Created": "20240101T000000Z",
+            "Encoding": "utf8",
+            "Blocksize": self.block_size,
+            "BlockHash": self.hash_name,
+            "FileHash": self.hash_name,
+            "AppVersion": "2.1.0.2",
+        });
+        let manifest = manifest.to_string().into_bytes();
+        let filelist = self.filelist_json();
+        write_zip(
+            &dir.join("duplicati-20240101T000000Z.dlist.zip"),
+            &[
+                ZipEntry::new("manifest", &manifest, METHOD_STORED),
+                ZipEntry::new("filelist.json", &filelist, METHOD_STORED),
+            ],
+        );
+        dir.to_path_buf()
+    }
+
+    /// The filelist.json that write stores in the dlist
+    pub fn filelist_json(&self) -> Vec<u8> {
+        serde_json::Value::from(self.filelist.clone())
+            .to_string()
+            .into_bytes()
+    }
+}
+
+/// An empty directory under the system temp dir, unique to the test and process
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("duplicati-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,80 @@
+//! conflict_renames gives every file of the backup a target path of its own
+
+mod common;
+
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::flags::PathOs;
+use rust_duplicati_restore::pathcheck::{conflict_renames, ConflictSuffix};
+use rust_duplicati_restore::pathstyle::PathStyle;
+use rust_duplicati_restore::{parse_dlist_file, FileEntries};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+fn entries(name: &str, files: &[&str]) -> FileEntries {
+    let dir = test_dir(name);
+    let mut builder = BackupBuilder::new(1024);
+    builder.folder("C:\\d\\");
+    for file in files {
+        builder.file(&format!("C:\\d\\{}", file), file.as_bytes(), METHOD_STORED);
+    }
+    let backup_dir = builder.write(&dir);
+    let entries = parse_dlist_file(
+        backup_dir.join("duplicati-20240101T000000Z.dlist.zip"),
+        None,
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+    entries.unwrap()
+}
+
+/// dlist file name -> renamed target path, with forward slashes
+fn renames(entries: &FileEntries, target: PathOs, suffix: &str) -> HashMap<String, String> {
+    let style = PathStyle::new(PathOs::Windows, target, &entries.entries);
+    let mut renames = HashMap::new();
+    let suffix = ConflictSuffix::parse(suffix).unwrap();
+    let count = conflict_renames(&entries.entries, style, &mut renames, &suffix);
+    assert_eq!(count, renames.len());
+    renames
+        .into_iter()
+        .map(|(path, target): (String, PathBuf)| {
+            let name = path.rsplit('\\').next().unwrap().to_string();
+            let target: Vec<_> = target.iter().map(|c| c.to_string_lossy()).collect();
+            (name, target.join("/"))
+        })
+        .collect()
+}
+
+#[test]
+fn case_folds_only_on_windows_targets() {
+    let entries = entries("conflict-case", &["a.txt", "A.txt", "b.txt"]);
+    assert!(renames(&entries, PathOs::Unix, "{name} ({n}){ext}").is_empty());
+    // Entries are taken in dlist path order, "A.txt" claims its name first
+    let renamed = renames(&entries, PathOs::Windows, "{name} ({n}){ext}");
+    assert_eq!(
+        renamed,
+        HashMap::from([("a.txt".to_string(), "C/d/a (1).txt".to_string())])
+    );
+}
+
+#[test]
+fn template_without_ext() {
+    let entries = entries("conflict-template", &["a.txt", "A.txt", "noext", "NOEXT"]);
+    let renamed = renames(&entries, PathOs::Windows, "{name}-{n}");
+    assert_eq!(
+        renamed,
+        HashMap::from([
+            ("a.txt".to_string(), "C/d/a-1".to_string()),
+            ("noext".to_string(), "C/d/noext-1".to_string()),
+        ])
+    );
+    assert!(ConflictSuffix::parse("{name}{ext}").is_err());
+}
+
+#[test]
+fn generated_name_taken_by_a_real_entry() {
+    let entries = entries("conflict-generated", &["a.txt", "A.txt", "a (1).txt"]);
+    let renamed = renames(&entries, PathOs::Windows, "{name} ({n}){ext}");
+    assert_eq!(
+        renamed,
+        HashMap::from([("a.txt".to_string(), "C/d/a (2).txt".to_string())])
+    );
+}
@@ -0,0 +1,56 @@
+//! A file whose declared size disagrees with its blocks fails on the size, before its hash is checked
+
+mod common;
+
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::restoring::{HashMismatch, RestoreContext};
+use rust_duplicati_restore::{parse_dlist_file, read_manifest, restore_entry};
+use rust_duplicati_restore::{DFileDatabase, RestoreParams};
+use std::sync::Arc;
+
+#[test]
+fn mismatched_declared_size_fails() {
+    let dir = test_dir("declared-size");
+    let content: Vec<u8> = (0..2500u32).map(|i| (i % 227) as u8).collect();
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        // Small enough for a single block, but the file has a blocklist
+        .file("C:\\d\\fewer_blocklists.bin", &content, METHOD_STORED)
+        .declared_size(5)
+        // Right number of blocklists, but the blocks add up to 2500 bytes
+        .file("C:\\d\\shorter.bin", &content, METHOD_STORED)
+        .declared_size(2000)
+        .file("C:\\d\\right.bin", &content, METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let db = DFileDatabase::new(&read_manifest(&dlist, None).unwrap(), false).unwrap();
+    db.create_block_id_to_filenames(&[backup_dir.join("duplicati-b0001.dblock.zip")])
+        .unwrap();
+    let entries = parse_dlist_file(&dlist, None).unwrap();
+    let restore_dir = dir.join("restore");
+    let params = RestoreParams::builder()
+        .with_db(Arc::new(db))
+        .with_restore_path(restore_dir.to_str().unwrap())
+        .build(&entries)
+        .unwrap();
+    let context = RestoreContext::new();
+    restore_entry(&entries.entries[0], &params, &context).unwrap();
+
+    let size_error = |index: usize, expected: &str| {
+        let err = restore_entry(&entries.entries[index], &params, &context).unwrap_err();
+        assert!(format!("{:#}", err).contains(expected), "{:#}", err);
+        let hash_checked = err.chain().any(|cause| cause.is::<HashMismatch>());
+        assert!(!hash_checked, "{:#}", err);
+    };
+    size_error(1, "declared size 5 needs 0 blocklists, but dlist has 1");
+    size_error(2, "reassembled to 2500 bytes, but declared size is 2000");
+    restore_entry(&entries.entries[3], &params, &context).unwrap();
+
+    let root = restore_dir.join("C").join("d");
+    assert!(!root.join("fewer_blocklists.bin").exists());
+    assert!(!root.join("shorter.bin").exists());
+    assert_eq!(std::fs::read(root.join("right.bin")).unwrap(), content);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
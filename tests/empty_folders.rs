@@ -0,0 +1,88 @@
+//! Folders are created from their entries, whether or not files are restored into them,
+//! and empty files and folders without a metadata block need no block
+
+mod common;
+
+use clap::Parser;
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::cli::run;
+use rust_duplicati_restore::flags::RestoreFlags;
+use rust_duplicati_restore::{restore, RestoreConfig};
+use std::fs;
+
+#[test]
+fn folders_without_files_and_files_without_folders() {
+    let dir = test_dir("empty-folders");
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .folder("C:\\d\\empty\\")
+        .folder("C:\\d\\a\\")
+        .folder("C:\\d\\a\\b\\")
+        .folder("C:\\d\\a\\b\\c\\")
+        .file("C:\\d\\a\\b\\c\\deep.txt", b"deep", METHOD_STORED)
+        // No folder entry for implicit, only the path of the file in it
+        .file("C:\\d\\implicit\\f.txt", b"implicit", METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    let restore_dir = dir.join("restore");
+    let summary = restore(RestoreConfig::new(&backup_dir, Some(restore_dir.clone()))).unwrap();
+    assert_eq!(summary.file_count, 2);
+
+    let root = restore_dir.join("C").join("d");
+    let empty = root.join("empty");
+    assert!(empty.is_dir());
+    assert_eq!(fs::read_dir(&empty).unwrap().count(), 0);
+    assert_eq!(fs::read(root.join("a/b/c/deep.txt")).unwrap(), b"deep");
+    assert_eq!(fs::read(root.join("implicit/f.txt")).unwrap(), b"implicit");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn empty_file_needs_no_block() {
+    let dir = test_dir("empty-file");
+    // The empty block is in no dblock, an empty file doesn't read it
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file_with_missing_blocks("C:\\d\\empty.txt", b"")
+        .file("C:\\d\\a.txt", b"a", METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    let restore_dir = dir.join("restore");
+    let summary = restore(RestoreConfig::new(&backup_dir, Some(restore_dir.clone()))).unwrap();
+    assert_eq!(summary.file_count, 2);
+    let root = restore_dir.join("C").join("d");
+    assert_eq!(fs::read(root.join("empty.txt")).unwrap(), b"");
+    assert_eq!(fs::read(root.join("a.txt")).unwrap(), b"a");
+    restore(RestoreConfig::new(&backup_dir, None)).unwrap();
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn folder_with_empty_metablockhash() {
+    let dir = test_dir("empty-metablockhash");
+    let backup_dir = BackupBuilder::new(1024)
+        .folder_without_metablock("C:\\d\\")
+        .folder_without_metablock("C:\\d\\empty\\")
+        .file("C:\\d\\a.txt", b"a", METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    let restore_dir = dir.join("restore");
+    let args = RestoreFlags::try_parse_from([
+        "rust-duplicati-restore",
+        "--backup-dir",
+        backup_dir.to_str().unwrap(),
+        "--restore-dir",
+        restore_dir.to_str().unwrap(),
+        "--restore-mtime",
+        "--restore-permissions",
+    ])
+    .unwrap();
+    run(args).unwrap();
+    let root = restore_dir.join("C").join("d");
+    assert!(root.join("empty").is_dir());
+    assert_eq!(fs::read(root.join("a.txt")).unwrap(), b"a");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,41 @@
+//! A dlist may store filelist.json gzip-compressed, with a UTF-8 BOM inside
+
+mod common;
+
+use common::{test_dir, write_zip, BackupBuilder, ZipEntry, METHOD_STORED};
+use flate2::{write::GzEncoder, Compression};
+use rust_duplicati_restore::parse_dlist_file;
+use std::io::Write;
+
+#[test]
+fn gzip_filelist_with_bom_parses_like_plain() {
+    let dir = test_dir("gzip-filelist");
+    let content: Vec<u8> = (0..2500u32).map(|i| (i % 229) as u8).collect();
+    let mut builder = BackupBuilder::new(1024);
+    builder
+        .folder("C:\\d\\")
+        .file("C:\\d\\small.txt", b"hello", METHOD_STORED)
+        .file("C:\\d\\big.bin", &content, METHOD_STORED);
+    let backup_dir = builder.write(&dir.join("backup"));
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"\xEF\xBB\xBF").unwrap();
+    encoder.write_all(&builder.filelist_json()).unwrap();
+    let gzipped = encoder.finish().unwrap();
+    let gzip_dlist = dir.join("gzip.dlist.zip");
+    write_zip(
+        &gzip_dlist,
+        &[ZipEntry::new("filelist.json", &gzipped, METHOD_STORED)],
+    );
+
+    let plain = parse_dlist_file(
+        backup_dir.join("duplicati-20240101T000000Z.dlist.zip"),
+        None,
+    )
+    .unwrap();
+    let gzip = parse_dlist_file(&gzip_dlist, None).unwrap();
+    assert_eq!(plain.entries.len(), 3);
+    assert_eq!(gzip.entries, plain.entries);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,57 @@
+//! A 300 byte name fails before restoring, or is cut to 255 bytes with --truncate-long-names
+
+mod common;
+
+use clap::Parser;
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::cli::run;
+use rust_duplicati_restore::flags::RestoreFlags;
+use rust_duplicati_restore::{restore, RestoreConfig};
+use std::fs;
+
+#[test]
+fn long_name_fails_or_is_truncated() {
+    let dir = test_dir("long-names");
+    let name = format!("{}.txt", "n".repeat(296));
+    assert_eq!(name.len(), 300);
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file(&format!("C:\\d\\{}", name), b"long", METHOD_STORED)
+        .file("C:\\d\\short.txt", b"short", METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    let restore_dir = dir.join("restore");
+    let err = restore(RestoreConfig::new(&backup_dir, Some(restore_dir.clone()))).unwrap_err();
+    assert!(
+        err.to_string().contains("--truncate-long-names"),
+        "{:?}",
+        err
+    );
+    assert!(!restore_dir.join("C").exists());
+
+    let args = RestoreFlags::try_parse_from([
+        "rust-duplicati-restore",
+        "--backup-dir",
+        backup_dir.to_str().unwrap(),
+        "--restore-dir",
+        restore_dir.to_str().unwrap(),
+        "--truncate-long-names",
+    ])
+    .unwrap();
+    run(args).unwrap();
+
+    let root = restore_dir.join("C").join("d");
+    assert_eq!(fs::read(root.join("short.txt")).unwrap(), b"short");
+    let truncated: Vec<String> = fs::read_dir(&root)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|n| n != "short.txt")
+        .collect();
+    assert_eq!(truncated.len(), 1);
+    let truncated = &truncated[0];
+    assert_eq!(truncated.len(), 255);
+    assert!(truncated.starts_with("nnnn") && truncated.ends_with(".txt"));
+    assert_eq!(fs::read(root.join(truncated)).unwrap(), b"long");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
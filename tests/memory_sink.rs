@@ -0,0 +1,57 @@
+//! Files restored into a MemoryBlockSink, nothing is written to disk
+
+mod common;
+
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::restoring::RestoreContext;
+use rust_duplicati_restore::{parse_dlist_file, read_manifest, restore_entry};
+use rust_duplicati_restore::{DFileDatabase, MemoryBlockSink, RestoreParams};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn restore_into(sink: Arc<MemoryBlockSink>, backup_dir: &Path) -> eyre::Result<()> {
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let db = DFileDatabase::new(&read_manifest(&dlist, None)?, false)?;
+    db.create_block_id_to_filenames(&[backup_dir.join("duplicati-b0001.dblock.zip")])?;
+    let entries = parse_dlist_file(&dlist, None)?;
+    let params = RestoreParams::builder()
+        .with_db(Arc::new(db))
+        .with_sink(Box::new(sink))
+        .build(&entries)?;
+    let context = RestoreContext::new();
+    for entry in &entries.entries {
+        restore_entry(entry, &params, &context)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn files_restored_in_memory() {
+    let dir = test_dir("memory-sink");
+    let big: Vec<u8> = (0..2500u32).map(|i| (i % 239) as u8).collect();
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\big.bin", &big, METHOD_STORED)
+        .file("C:\\d\\sub\\small.txt", b"small", METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    let sink = Arc::new(MemoryBlockSink::new(1 << 20));
+    restore_into(sink.clone(), &backup_dir).unwrap();
+    let files = Arc::into_inner(sink).unwrap().into_files();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[&PathBuf::from("C/d/big.bin")], big);
+    assert_eq!(files[&PathBuf::from("C/d/sub/small.txt")], b"small");
+    // Only the backup is on disk
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    // 2500 + 5 bytes don't fit in 2000
+    let sink = Arc::new(MemoryBlockSink::new(2000));
+    let err = restore_into(sink, &backup_dir).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("cap of 2000 bytes"),
+        "{:#}",
+        err
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,66 @@
+//! A file whose blocks are in no dblock fails, naming the block, and so does the run
+
+mod common;
+
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::restoring::RestoreContext;
+use rust_duplicati_restore::{parse_dlist_file, read_manifest, restore_entry};
+use rust_duplicati_restore::{restore, RestoreConfig};
+use rust_duplicati_restore::{DFileDatabase, RestoreParams};
+use std::sync::Arc;
+
+#[test]
+fn missing_single_block_fails() {
+    let dir = test_dir("missing-block");
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\a.txt", b"present", METHOD_STORED)
+        .file_with_missing_blocks("C:\\d\\b.txt", b"missing")
+        .write(&dir.join("backup"));
+
+    // The command line skips files with missing blocks before restoring, the library doesn't
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let db = DFileDatabase::new(&read_manifest(&dlist, None).unwrap(), false).unwrap();
+    db.create_block_id_to_filenames(&[backup_dir.join("duplicati-b0001.dblock.zip")])
+        .unwrap();
+    let entries = parse_dlist_file(&dlist, None).unwrap();
+    let restore_dir = dir.join("restore");
+    let params = RestoreParams::builder()
+        .with_db(Arc::new(db))
+        .with_restore_path(restore_dir.to_str().unwrap())
+        .build(&entries)
+        .unwrap();
+
+    let context = RestoreContext::new();
+    let (present, missing) = (&entries.entries[1], &entries.entries[2]);
+    restore_entry(&entries.entries[0], &params, &context).unwrap();
+    restore_entry(present, &params, &context).unwrap();
+    let err = restore_entry(missing, &params, &context).unwrap_err();
+    assert!(format!("{:?}", err).contains("Missing block"), "{:?}", err);
+    assert!(!restore_dir.join("C").join("d").join("b.txt").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn skipped_files_fail_the_run() {
+    let dir = test_dir("missing-block-run");
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\a.txt", b"present", METHOD_STORED)
+        .file_with_missing_blocks("C:\\d\\b.txt", b"missing")
+        .write(&dir.join("backup"));
+
+    // The other files are restored, but the run doesn't end as if nothing was wrong
+    let restore_dir = dir.join("restore");
+    let err = restore(RestoreConfig::new(&backup_dir, Some(restore_dir.clone()))).unwrap_err();
+    assert_eq!(err.to_string(), "1 files failed");
+    let root = restore_dir.join("C").join("d");
+    assert_eq!(std::fs::read(root.join("a.txt")).unwrap(), b"present");
+    assert!(!root.join("b.txt").exists());
+
+    let err = restore(RestoreConfig::new(&backup_dir, None)).unwrap_err();
+    assert_eq!(err.to_string(), "1 files failed");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
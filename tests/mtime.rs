@@ -0,0 +1,52 @@
+//! Backup times keep their fractional seconds from the dlist to the restored file
+
+mod common;
+
+use common::test_dir;
+use filetime::FileTime;
+use rust_duplicati_restore::mtime::{parse_backup_time, set_file_mtime_from_backup};
+use std::fs;
+
+#[test]
+fn parse_keeps_fractional_seconds() {
+    let nanos = |time: &str| parse_backup_time(time).unwrap().timestamp_subsec_nanos();
+    assert_eq!(nanos("2023-01-02T03:04:05.123456789Z"), 123_456_789);
+    assert_eq!(nanos("2023-01-02T03:04:05.5Z"), 500_000_000);
+    assert_eq!(nanos("20230102T030405.250Z"), 250_000_000);
+    assert_eq!(nanos("20230102T030405Z"), 0);
+
+    let compact = parse_backup_time("20230102T030405.1234567Z").unwrap();
+    let rfc3339 = parse_backup_time("2023-01-02T05:04:05.1234567+02:00").unwrap();
+    assert_eq!(compact, rfc3339);
+    assert_eq!(compact.timestamp(), 1_672_628_645);
+
+    assert!(parse_backup_time("2023-01-02 03:04:05").is_err());
+    assert!(parse_backup_time("").is_err());
+}
+
+#[test]
+fn restored_mtime_round_trips() {
+    let dir = test_dir("mtime");
+    let path = dir.join("f.txt");
+    fs::write(&path, b"f").unwrap();
+
+    for (time, nanos) in [
+        ("2023-01-02T03:04:05.123456789Z", 123_456_789),
+        ("20230102T030405.5Z", 500_000_000),
+        ("20230102T030405Z", 0),
+    ] {
+        set_file_mtime_from_backup(&path, time).unwrap();
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&path).unwrap());
+        assert_eq!(mtime.unix_seconds(), 1_672_628_645, "{}", time);
+        // Filesystems without nanoseconds round down to what they keep
+        let kept = [nanos, nanos / 100 * 100, nanos / 1000 * 1000, 0];
+        assert!(
+            kept.contains(&mtime.nanoseconds()),
+            "{}: {} ns",
+            time,
+            mtime.nanoseconds()
+        );
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,56 @@
+//! Files are written as .partial and renamed once complete, so resuming with
+//! --skip-existing size never takes an interrupted write for a finished file
+
+mod common;
+
+use clap::Parser;
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::cli::{run, RunOutcome};
+use rust_duplicati_restore::flags::RestoreFlags;
+use std::fs;
+
+#[test]
+fn interrupted_multiblock_write_is_restored_on_resume() {
+    let dir = test_dir("partial-resume");
+    let big: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    // 254 bytes, its name plus ".partial" is over the 255 byte limit
+    let long_name = format!("{}.bin", "l".repeat(250));
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\big.bin", &big, METHOD_STORED)
+        .file(&format!("C:\\d\\{}", long_name), &big, METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    // What an interrupted write leaves: preallocated to the full size, only the
+    // first block written
+    let restore_dir = dir.join("restore");
+    let root = restore_dir.join("C").join("d");
+    fs::create_dir_all(&root).unwrap();
+    let mut partial = big[..1024].to_vec();
+    partial.resize(big.len(), 0);
+    fs::write(root.join("big.bin.partial"), &partial).unwrap();
+
+    let args = RestoreFlags::try_parse_from([
+        "rust-duplicati-restore",
+        "--backup-dir",
+        backup_dir.to_str().unwrap(),
+        "--restore-dir",
+        restore_dir.to_str().unwrap(),
+        "--skip-existing",
+        "size",
+    ])
+    .unwrap();
+    let outcome = run(args).unwrap();
+    assert!(matches!(outcome, RunOutcome::Restored(_)));
+
+    assert_eq!(fs::read(root.join("big.bin")).unwrap(), big);
+    assert_eq!(fs::read(root.join(&long_name)).unwrap(), big);
+    let mut left: Vec<String> = fs::read_dir(&root)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    left.sort();
+    assert_eq!(left, ["big.bin", long_name.as_str()]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,76 @@
+//! dlist paths map under the restore dir the same way for every source and target OS
+
+mod common;
+
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::flags::PathOs;
+use rust_duplicati_restore::parse_dlist_file;
+use rust_duplicati_restore::pathstyle::PathStyle;
+use std::path::PathBuf;
+
+fn relative(source: PathOs, target: PathOs, path: &str) -> PathBuf {
+    PathStyle::new(source, target, &[]).relative_target_path(path)
+}
+
+fn components(parts: &[&str]) -> PathBuf {
+    parts.iter().collect()
+}
+
+#[test]
+fn windows_source() {
+    for target in [PathOs::Windows, PathOs::Unix] {
+        let rel = |path| relative(PathOs::Windows, target, path);
+        assert_eq!(rel("C:\\dir\\f.txt"), components(&["C", "dir", "f.txt"]));
+        assert_eq!(rel("C:\\"), components(&["C"]));
+        assert_eq!(rel("d:\\x"), components(&["d", "x"]));
+        assert_eq!(
+            rel("\\\\server\\share\\f"),
+            components(&["server", "share", "f"])
+        );
+        // ".." and "." are dropped, never resolved above the restore dir
+        assert_eq!(
+            rel("C:\\a\\..\\..\\b\\.\\f"),
+            components(&["C", "a", "b", "f"])
+        );
+        // Only a leading drive is one
+        assert_eq!(rel("C:\\a\\D:\\f"), components(&["C", "a", "D:", "f"]));
+    }
+}
+
+#[test]
+fn unix_source() {
+    let unix = |path| relative(PathOs::Unix, PathOs::Unix, path);
+    let windows = |path| relative(PathOs::Unix, PathOs::Windows, path);
+    for rel in [unix, windows] {
+        assert_eq!(rel("/home/me/f.txt"), components(&["home", "me", "f.txt"]));
+        assert_eq!(rel("/"), components(&[]));
+        assert_eq!(
+            rel("/home/../../etc/./passwd"),
+            components(&["home", "etc", "passwd"])
+        );
+        // No drive letters in unix paths
+        assert_eq!(rel("/C:/x"), components(&["C:", "x"]));
+    }
+    // A '\' in a unix name would split it on Windows
+    assert_eq!(unix("/home/a\\b"), components(&["home", "a\\b"]));
+    assert_eq!(windows("/home/a\\b"), components(&["home", "a_b"]));
+}
+
+#[test]
+fn auto_source_looks_at_the_paths() {
+    let dir = test_dir("path-style");
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\f.txt", b"f", METHOD_STORED)
+        .write(&dir);
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let entries = parse_dlist_file(dlist, None).unwrap().entries;
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let style = PathStyle::new(PathOs::Auto, PathOs::Unix, &entries);
+    assert!(style.source_windows && !style.target_windows);
+    let style = PathStyle::new(PathOs::Auto, PathOs::Windows, &[]);
+    assert!(!style.source_windows && style.target_windows);
+    let style = PathStyle::new(PathOs::Auto, PathOs::Auto, &[]);
+    assert_eq!(style.target_windows, cfg!(windows));
+}
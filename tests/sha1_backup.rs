@@ -0,0 +1,44 @@
+//! Backups with SHA1 block and file hashes, 20 byte hashes in the blocklists
+
+mod common;
+
+use common::{sha1, test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::blockhash::BlockIdHash;
+use rust_duplicati_restore::blockmap::block_map;
+use rust_duplicati_restore::{parse_dlist_file, read_manifest, DFileDatabase};
+use rust_duplicati_restore::{restore, RestoreConfig};
+use std::fs;
+
+#[test]
+fn multiblock_sha1_restore() {
+    let dir = test_dir("sha1-backup");
+    let big: Vec<u8> = (0..5000u32).map(|i| (i % 233) as u8).collect();
+    let backup_dir = BackupBuilder::with_sha1(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\big.bin", &big, METHOD_STORED)
+        .file("C:\\d\\small.txt", b"small", METHOD_STORED)
+        .file_with_missing_blocks("C:\\d\\empty.txt", b"")
+        .write(&dir.join("backup"));
+
+    let restore_dir = dir.join("restore");
+    let summary = restore(RestoreConfig::new(&backup_dir, Some(restore_dir.clone()))).unwrap();
+    assert_eq!(summary.file_count, 3);
+    let root = restore_dir.join("C").join("d");
+    assert_eq!(fs::read(root.join("big.bin")).unwrap(), big);
+    assert_eq!(fs::read(root.join("small.txt")).unwrap(), b"small");
+    assert_eq!(fs::read(root.join("empty.txt")).unwrap(), b"");
+    restore(RestoreConfig::new(&backup_dir, None)).unwrap();
+
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let db = DFileDatabase::new(&read_manifest(&dlist, None).unwrap(), false).unwrap();
+    db.create_block_id_to_filenames(&[backup_dir.join("duplicati-b0001.dblock.zip")])
+        .unwrap();
+    let entries = parse_dlist_file(&dlist, None).unwrap();
+    let map = block_map(&entries.entries[1], &db).unwrap();
+    assert_eq!(map.len(), 5);
+    for (block, chunk) in map.iter().zip(big.chunks(1024)) {
+        assert_eq!(block.hash, BlockIdHash::from_bytes(&sha1(chunk)).unwrap());
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
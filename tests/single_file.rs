@@ -0,0 +1,68 @@
+//! --single-file checks the path and range before touching --output
+
+mod common;
+
+use clap::Parser;
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::cli::run;
+use rust_duplicati_restore::flags::RestoreFlags;
+use std::fs;
+use std::path::Path;
+
+fn extract(backup_dir: &Path, output: &Path, path: &str, range: &[&str]) -> eyre::Result<()> {
+    let mut argv = vec![
+        "rust-duplicati-restore",
+        "--backup-dir",
+        backup_dir.to_str().unwrap(),
+        "--single-file",
+        path,
+        "--output",
+        output.to_str().unwrap(),
+    ];
+    argv.extend_from_slice(range);
+    run(RestoreFlags::try_parse_from(argv).unwrap()).map(drop)
+}
+
+#[test]
+fn bad_path_or_range_leaves_output_alone() {
+    let dir = test_dir("single-file");
+    let content: Vec<u8> = (0..2500u32).map(|i| (i % 233) as u8).collect();
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\big.bin", &content, METHOD_STORED)
+        .write(&dir.join("backup"));
+    let output = dir.join("out.bin");
+    fs::write(&output, b"keep").unwrap();
+
+    let err = extract(&backup_dir, &output, "C:\\d\\nope.bin", &[]).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("is not in the dlist"),
+        "{:#}",
+        err
+    );
+    assert_eq!(fs::read(&output).unwrap(), b"keep");
+
+    let past_end = ["--offset", "2000", "--length", "600"];
+    let err = extract(&backup_dir, &output, "C:\\d\\big.bin", &past_end).unwrap_err();
+    assert!(format!("{:#}", err).contains("past the end"), "{:#}", err);
+    assert_eq!(fs::read(&output).unwrap(), b"keep");
+
+    let err = extract(&backup_dir, &output, "C:\\d\\", &[]).unwrap_err();
+    assert!(format!("{:#}", err).contains("is not a file"), "{:#}", err);
+    assert_eq!(fs::read(&output).unwrap(), b"keep");
+
+    let range = ["--offset", "1000", "--length", "100"];
+    extract(&backup_dir, &output, "C:\\d\\big.bin", &range).unwrap();
+    assert_eq!(fs::read(&output).unwrap(), &content[1000..1100]);
+
+    extract(
+        &backup_dir,
+        &output,
+        "C:\\d\\big.bin",
+        &["--offset", "2000"],
+    )
+    .unwrap();
+    assert_eq!(fs::read(&output).unwrap(), &content[2000..]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
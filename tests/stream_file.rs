@@ -0,0 +1,83 @@
+//! open_file streams a file block by block, the same bytes a full restore writes
+
+mod common;
+
+use common::{test_dir, BackupBuilder, METHOD_STORED};
+use rust_duplicati_restore::filereader::open_file;
+use rust_duplicati_restore::restoring::HashMismatch;
+use rust_duplicati_restore::{parse_dlist_file, read_manifest, DFileDatabase};
+use rust_duplicati_restore::{restore, RestoreConfig};
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+fn open_db(backup_dir: &Path) -> DFileDatabase {
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let db = DFileDatabase::new(&read_manifest(&dlist, None).unwrap(), false).unwrap();
+    db.create_block_id_to_filenames(&[backup_dir.join("duplicati-b0001.dblock.zip")])
+        .unwrap();
+    db
+}
+
+#[test]
+fn streamed_file_matches_restored_file() {
+    let dir = test_dir("stream-file");
+    let content: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 256) as u8).collect();
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file("C:\\d\\big.bin", &content, METHOD_STORED)
+        .file("C:\\d\\empty.txt", b"", METHOD_STORED)
+        .write(&dir.join("backup"));
+
+    let restore_dir = dir.join("restore");
+    restore(RestoreConfig::new(&backup_dir, Some(restore_dir.clone()))).unwrap();
+    let restored = std::fs::read(restore_dir.join("C/d/big.bin")).unwrap();
+
+    let db = open_db(&backup_dir);
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let entries = parse_dlist_file(&dlist, None).unwrap().entries;
+    let mut reader = open_file(&db, &entries, "C:\\d\\big.bin").unwrap();
+    // Reads smaller than a block and across block ends
+    let mut streamed = Vec::new();
+    let mut buf = [0u8; 300];
+    loop {
+        let n = reader.read(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        streamed.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(streamed, restored);
+    assert_eq!(streamed, content);
+
+    let mut empty = Vec::new();
+    open_file(&db, &entries, "C:\\d\\empty.txt")
+        .unwrap()
+        .read_to_end(&mut empty)
+        .unwrap();
+    assert!(empty.is_empty());
+    assert!(open_file(&db, &entries, "C:\\d\\").is_err());
+    assert!(open_file(&db, &entries, "C:\\d\\nope.txt").is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt_block_fails_the_read() {
+    let dir = test_dir("stream-file-corrupt");
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    let backup_dir = BackupBuilder::new(1024)
+        .folder("C:\\d\\")
+        .file_with_corrupt_block("C:\\d\\big.bin", &content)
+        .write(&dir.join("backup"));
+
+    let db = open_db(&backup_dir);
+    let dlist = backup_dir.join("duplicati-20240101T000000Z.dlist.zip");
+    let entries = parse_dlist_file(&dlist, None).unwrap().entries;
+    let mut reader = open_file(&db, &entries, "C:\\d\\big.bin").unwrap();
+    let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let mismatch = err.get_ref().unwrap().downcast_ref::<HashMismatch>();
+    assert!(mismatch.unwrap().block, "{:?}", err);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
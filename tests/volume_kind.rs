@@ -0,0 +1,45 @@
+//! Backup files are told apart by the kind in their name, whatever follows it
+
+use rust_duplicati_restore::dlist::VolumeKind;
+
+#[test]
+fn kind_whatever_the_compression_and_encryption() {
+    let cases = [
+        ("duplicati-b0a1.dblock.zip", Some(VolumeKind::Dblock)),
+        ("duplicati-b0a1.dblock.zip.aes", Some(VolumeKind::Dblock)),
+        ("duplicati-b0a1.dblock.7z", Some(VolumeKind::Dblock)),
+        ("duplicati-i0a1.dindex.7z", Some(VolumeKind::Dindex)),
+        ("duplicati-i0a1.dindex.zip.gpg", Some(VolumeKind::Dindex)),
+        (
+            "duplicati-20240101T000000Z.dlist.zip.aes",
+            Some(VolumeKind::Dlist),
+        ),
+        (
+            "backup/duplicati-20240101T000000Z.DLIST.ZIP",
+            Some(VolumeKind::Dlist),
+        ),
+        // Renamed on the remote, e.g. with a custom prefix or a suffix added
+        (
+            "mine-20240101T000000Z.dlist.zip.aes.1",
+            Some(VolumeKind::Dlist),
+        ),
+    ];
+    for (name, kind) in cases {
+        assert_eq!(VolumeKind::of(name), kind, "{}", name);
+    }
+}
+
+#[test]
+fn other_files_have_no_kind() {
+    for name in [
+        "duplicati-verification.json",
+        "notes.txt",
+        // The kind is followed by at least the compression
+        "duplicati-b0a1.dblock",
+        "dblock",
+        "dblock.zip",
+        "duplicati-b0a1.dblocks.zip",
+    ] {
+        assert_eq!(VolumeKind::of(name), None, "{}", name);
+    }
+}
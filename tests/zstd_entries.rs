@@ -0,0 +1,30 @@
+//! A backup whose content blocks are zstd entries, as written by newer Duplicati versions
+
+mod common;
+
+use common::{test_dir, BackupBuilder, METHOD_ZSTD};
+use rust_duplicati_restore::{restore, RestoreConfig};
+use std::fs;
+
+const BLOCK_SIZE: usize = 1024;
+
+#[test]
+fn zstd_blocks_round_trip() {
+    let dir = test_dir("zstd-entries");
+    // Three blocks, the last one short, so offsets depend on exact decompressed lengths
+    let content: Vec<u8> = (0..2 * BLOCK_SIZE + 452)
+        .map(|i| b"zstd block content "[i % 19])
+        .collect();
+    let backup_dir = BackupBuilder::new(BLOCK_SIZE)
+        .folder("C:\\d\\")
+        .file("C:\\d\\zstd.txt", &content, METHOD_ZSTD)
+        .write(&dir.join("backup"));
+
+    let restore_dir = dir.join("restore");
+    let summary = restore(RestoreConfig::new(&backup_dir, Some(restore_dir.clone()))).unwrap();
+    assert_eq!(summary.file_count, 1);
+    let restored = fs::read(restore_dir.join("C").join("d").join("zstd.txt")).unwrap();
+    assert_eq!(restored, content);
+
+    fs::remove_dir_all(&dir).unwrap();
+}